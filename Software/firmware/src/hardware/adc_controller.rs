@@ -0,0 +1,51 @@
+use arduino_hal::pac::adc::admux::MUX_A;
+use arduino_hal::pac::ADC;
+use atmega_hal::adc::{AdcChannel, AdcSettings, ClockDivider};
+use atmega_hal::Atmega;
+use core::cell::RefCell;
+
+/* The internal inputs */
+pub use atmega_hal::adc::channel::{Temperature, Vbg};
+pub use atmega_hal::adc::ReferenceVoltage;
+
+pub type AdcReference = RefCell<AdcController>;
+
+pub const ADC_MAX: u16 = 1023;
+
+pub struct AdcController {
+    adc: atmega_hal::Adc<arduino_hal::DefaultClock>,
+    /* Input and reference of the latest conversion */
+    selected: Option<(MUX_A, ReferenceVoltage)>,
+}
+
+impl AdcController {
+    pub fn new(adc: ADC) -> Self {
+        AdcController {
+            adc: atmega_hal::Adc::new(adc, settings(ReferenceVoltage::AVcc)),
+            selected: None,
+        }
+    }
+
+    /* Single conversion, takes ~0.1 ms or a few ms when the input or reference changes */
+    pub fn read<C: AdcChannel<Atmega, ADC>>(&mut self, channel: &C, reference: ReferenceVoltage) -> u16 {
+        let selected = Some((channel.channel(), reference));
+        if selected != self.selected {
+            self.adc.initialize(settings(reference));
+            self.selected = selected;
+
+            /* The AREF capacitor and the bandgap need time to settle, and the
+             * first conversion after a switch is off anyway */
+            arduino_hal::delay_ms(2);
+            self.adc.read_blocking(channel);
+        }
+        return self.adc.read_blocking(channel);
+    }
+}
+
+/* 16 MHz / 128 = 125 kHz, within the 50-200 kHz for full resolution */
+fn settings(reference: ReferenceVoltage) -> AdcSettings {
+    return AdcSettings {
+        clock_divider: ClockDivider::Factor128,
+        ref_voltage: reference,
+    };
+}
@@ -0,0 +1,64 @@
+use arduino_hal::pac::TC0;
+use avr_device::interrupt::Mutex;
+use core::cell::Cell;
+
+/*
+ * Monotonic system clock driven by Timer0.
+ *
+ * Timer0 runs in CTC mode with a prescaler of 64, so at 16 MHz every timer
+ * tick is 4 us and a compare match fires every 250 ticks (1 ms). The compare
+ * interrupt counts milliseconds, the current counter value gives the
+ * sub-millisecond part for micros().
+ */
+const PRESCALER: u32 = 64;
+const TIMER_COUNTS: u32 = 250;
+const US_PER_TICK: u32 = PRESCALER / 16;
+const MILLIS_INCREMENT: u32 = PRESCALER * TIMER_COUNTS / 16_000;
+
+static MILLIS_COUNTER: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+pub fn init(tc0: TC0) {
+    tc0.tccr0a.write(|w| w.wgm0().ctc());
+    tc0.ocr0a.write(|w| unsafe { w.bits((TIMER_COUNTS - 1) as u8) });
+    tc0.tccr0b.write(|w| w.cs0().prescale_64());
+    tc0.timsk0.write(|w| w.ocie0a().set_bit());
+
+    avr_device::interrupt::free(|cs| {
+        MILLIS_COUNTER.borrow(cs).set(0);
+    });
+}
+
+/* Milliseconds since init(), wraps after ~49 days */
+pub fn millis() -> u32 {
+    avr_device::interrupt::free(|cs| MILLIS_COUNTER.borrow(cs).get())
+}
+
+/* Microseconds since init(), wraps after ~71 minutes */
+pub fn micros() -> u32 {
+    avr_device::interrupt::free(|cs| {
+        let tc0 = unsafe { &*TC0::ptr() };
+        let mut millis = MILLIS_COUNTER.borrow(cs).get();
+        let ticks = tc0.tcnt0.read().bits() as u32;
+
+        // A compare match may be pending while interrupts are disabled, in
+        // which case the counter has already restarted from zero.
+        if tc0.tifr0.read().ocf0a().bit_is_set() && ticks < TIMER_COUNTS - 1 {
+            millis = millis.wrapping_add(MILLIS_INCREMENT);
+        }
+
+        millis.wrapping_mul(1000).wrapping_add(ticks * US_PER_TICK)
+    })
+}
+
+/* Milliseconds elapsed since a previous millis() timestamp */
+pub fn elapsed_ms(since: u32) -> u32 {
+    millis().wrapping_sub(since)
+}
+
+#[avr_device::interrupt(atmega328p)]
+fn TIMER0_COMPA() {
+    avr_device::interrupt::free(|cs| {
+        let counter = MILLIS_COUNTER.borrow(cs);
+        counter.set(counter.get().wrapping_add(MILLIS_INCREMENT));
+    })
+}
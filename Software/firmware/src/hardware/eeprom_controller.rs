@@ -0,0 +1,88 @@
+use arduino_hal::pac::EEPROM;
+use kitchen_core::settings::Storage;
+
+pub const EEPROM_SIZE: u16 = 1024;
+
+#[derive(ufmt::derive::uDebug, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum EepromError {
+    OutOfRange,
+}
+
+pub struct EepromController {
+    eeprom: EEPROM,
+}
+
+impl EepromController {
+    pub fn new(eeprom: EEPROM) -> Self {
+        EepromController { eeprom }
+    }
+
+    pub fn read(&mut self, address: u16, buffer: &mut [u8]) -> Result<(), EepromError> {
+        Self::check_range(address, buffer.len())?;
+
+        for (i, b) in buffer.iter_mut().enumerate() {
+            *b = self.read_byte(address + i as u16);
+        }
+        Ok(())
+    }
+
+    /* Only bytes that differ are written, to spare the cells */
+    pub fn write(&mut self, address: u16, buffer: &[u8]) -> Result<(), EepromError> {
+        Self::check_range(address, buffer.len())?;
+
+        for (i, b) in buffer.iter().enumerate() {
+            let addr = address + i as u16;
+            if self.read_byte(addr) != *b {
+                self.write_byte(addr, *b);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_byte(&mut self, address: u16) -> u8 {
+        self.wait();
+
+        self.eeprom.eear.write(|w| unsafe { w.bits(address) });
+        self.eeprom.eecr.write(|w| unsafe { w.bits(1 << 0) }); /* Start read (EERE) */
+
+        self.eeprom.eedr.read().bits()
+    }
+
+    pub fn write_byte(&mut self, address: u16, value: u8) {
+        self.wait();
+
+        self.eeprom.eear.write(|w| unsafe { w.bits(address) });
+        self.eeprom.eedr.write(|w| unsafe { w.bits(value) });
+
+        /* EEPE has to be set within four cycles of EEMPE, so no interrupts in between */
+        avr_device::interrupt::free(|_| {
+            self.eeprom.eecr.write(|w| unsafe { w.bits(1 << 2) }); /* Master write enable (EEMPE) */
+            self.eeprom.eecr.write(|w| unsafe { w.bits((1 << 2) | (1 << 1)) }); /* Start write (EEPE) */
+        });
+    }
+
+    fn check_range(address: u16, len: usize) -> Result<(), EepromError> {
+        if address as usize + len > EEPROM_SIZE as usize {
+            return Err(EepromError::OutOfRange);
+        }
+        Ok(())
+    }
+
+    /* Wait for a previous write to finish */
+    fn wait(&self) {
+        while (self.eeprom.eecr.read().bits() & (1 << 1)) != 0 {}
+    }
+}
+
+/* The settings record lives in the EEPROM */
+impl Storage for EepromController {
+    type Error = EepromError;
+
+    fn read(&mut self, address: u16, buffer: &mut [u8]) -> Result<(), EepromError> {
+        EepromController::read(self, address, buffer)
+    }
+
+    fn write(&mut self, address: u16, buffer: &[u8]) -> Result<(), EepromError> {
+        EepromController::write(self, address, buffer)
+    }
+}
@@ -0,0 +1,47 @@
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use kitchen_core::weighing::weight::Weight;
+
+use super::clock;
+use super::sensor_generics::*;
+
+/* The driver itself is portable, only the timestamps need the target clock */
+pub use kitchen_core::drivers::hx711::*;
+
+/* Net weight with the current offset and calibration */
+impl<IN, OUT, D> Sensor for HX711<IN, OUT, D>
+where
+    IN: InputPin,
+    OUT: OutputPin,
+    D: DelayMs<u16>,
+{
+    type Output = Weight;
+    type Error = Hx711Error;
+
+    fn units(&self) -> SensorUnits {
+        SensorUnits {
+            quantity: Quantity::Mass,
+            symbol: "g",
+            decimals: 3,
+        }
+    }
+
+    fn read_sensor(&mut self) -> Result<Measurement<Weight>, Hx711Error> {
+        let raw = self.read()?;
+        return Ok(Measurement {
+            value: self.to_units(raw),
+            timestamp_us: clock::micros(),
+        });
+    }
+
+    fn poll(&mut self) -> Result<Option<Measurement<Weight>>, Hx711Error> {
+        match self.try_read() {
+            Ok(raw) => Ok(Some(Measurement {
+                value: self.to_units(raw),
+                timestamp_us: clock::micros(),
+            })),
+            Err(Hx711Error::NotReady | Hx711Error::Settling) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
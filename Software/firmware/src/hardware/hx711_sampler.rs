@@ -0,0 +1,135 @@
+use arduino_hal::pac::EXINT;
+use arduino_hal::port::{mode, Pin};
+use arduino_hal::Delay;
+use avr_device::interrupt::Mutex;
+use core::cell::RefCell;
+
+use super::clock;
+use super::hx711::{Hx711Channel, Hx711Error, HX711};
+use kitchen_core::utils::ringbuffer::RingBuffer;
+
+/*
+ * Interrupt driven HX711 sampling.
+ *
+ * DOUT of the HX711 is wired to d2 (INT0). DOUT falls when a conversion is
+ * ready, so INT0 is configured for falling edges and the interrupt handler
+ * clocks out the 24 bits and queues the result. The main loop only drains
+ * the queue, the HX711 output rate (10 or 80 SPS) paces the samples.
+ */
+pub const SAMPLE_BUFFER_SIZE: usize = 16;
+
+/* The driver as wired on the board, DOUT on d2 and PD_SCK on d3 */
+pub type LoadCell = HX711<Pin<mode::Input<mode::PullUp>>, Pin<mode::Output>, Delay>;
+
+#[derive(Clone, Copy)]
+pub struct Sample {
+    /* Channel A unless the driver alternates, errors are reported as channel A */
+    pub channel: Hx711Channel,
+    pub reading: Result<i32, Hx711Error>,
+    /* Gain the conversion was made at, samples queued before a switch keep the old one */
+    pub gain: u8,
+    /* Conversions the driver dropped since the previous sample, settling after a switch */
+    pub skipped: u8,
+    pub timestamp_us: u32,
+}
+
+struct SamplerState {
+    sensor: LoadCell,
+    samples: RingBuffer<Sample, SAMPLE_BUFFER_SIZE>,
+    dropped: u16,
+    skipped: u8,
+}
+
+static SAMPLER: Mutex<RefCell<Option<SamplerState>>> = Mutex::new(RefCell::new(None));
+
+pub struct Hx711Sampler {
+    exint: EXINT,
+}
+
+impl Hx711Sampler {
+    pub fn start(sensor: LoadCell, exint: EXINT) -> Self {
+        avr_device::interrupt::free(|cs| {
+            SAMPLER.borrow(cs).replace(Some(SamplerState {
+                sensor,
+                samples: RingBuffer::new(),
+                dropped: 0,
+                skipped: 0,
+            }));
+        });
+
+        /* Falling edge on INT0 */
+        exint.eicra.modify(|_, w| w.isc0().bits(0x02));
+        exint.eifr.write(|w| w.intf0().set_bit());
+        exint.eimsk.modify(|_, w| w.int0().set_bit());
+
+        Self { exint }
+    }
+
+    pub fn stop(self) -> (LoadCell, EXINT) {
+        self.exint.eimsk.modify(|_, w| w.int0().clear_bit());
+
+        let state = avr_device::interrupt::free(|cs| SAMPLER.borrow(cs).replace(None));
+
+        // start() is the only way to construct a sampler, so the state is always there
+        (state.unwrap().sensor, self.exint)
+    }
+
+    /* Oldest queued sample, if any */
+    pub fn pop(&mut self) -> Option<Sample> {
+        avr_device::interrupt::free(|cs| {
+            SAMPLER
+                .borrow(cs)
+                .borrow_mut()
+                .as_mut()
+                .and_then(|state| state.samples.pop())
+        })
+    }
+
+    /* Number of samples lost because the queue was full, resets the count */
+    pub fn take_dropped(&mut self) -> u16 {
+        avr_device::interrupt::free(|cs| {
+            SAMPLER
+                .borrow(cs)
+                .borrow_mut()
+                .as_mut()
+                .map_or(0, |state| core::mem::replace(&mut state.dropped, 0))
+        })
+    }
+
+    /* Access the driver (offset, scale, gain) with the sampling interrupt held off */
+    pub fn with_sensor<R>(&mut self, f: impl FnOnce(&mut LoadCell) -> R) -> R {
+        avr_device::interrupt::free(|cs| {
+            let mut state = SAMPLER.borrow(cs).borrow_mut();
+            f(&mut state.as_mut().unwrap().sensor)
+        })
+    }
+}
+
+#[avr_device::interrupt(atmega328p)]
+fn INT0() {
+    avr_device::interrupt::free(|cs| {
+        if let Some(state) = SAMPLER.borrow(cs).borrow_mut().as_mut() {
+            let result = state.sensor.try_read_channel();
+
+            // Edge left over from a previous readout, or a conversion dropped after a switch
+            if matches!(result, Err(Hx711Error::Settling)) {
+                state.skipped = state.skipped.saturating_add(1);
+            } else if !matches!(result, Err(Hx711Error::NotReady)) {
+                let sample = Sample {
+                    channel: result.map_or(Hx711Channel::A, |(channel, _)| channel),
+                    reading: result.map(|(_, value)| value),
+                    gain: state.sensor.reading_gain(),
+                    skipped: core::mem::replace(&mut state.skipped, 0),
+                    timestamp_us: clock::micros(),
+                };
+                if state.samples.push(sample).is_err() {
+                    state.dropped = state.dropped.saturating_add(1);
+                }
+            }
+        }
+
+        // DOUT toggled while shifting out the bits, discard those edges
+        let exint = unsafe { &*EXINT::ptr() };
+        exint.eifr.write(|w| w.intf0().set_bit());
+    })
+}
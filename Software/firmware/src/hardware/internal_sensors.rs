@@ -0,0 +1,144 @@
+use core::convert::Infallible;
+
+use super::adc_controller::*;
+use super::clock;
+use super::sensor_generics::*;
+
+/*
+ * Sensors built into the ATmega328P, read through the shared ADC.
+ *
+ * A conversion only takes a fraction of a millisecond, so poll() converts
+ * right away once the sensor's interval has passed and returns None before.
+ */
+
+// Nominal bandgap voltage, the datasheet allows ±10 %
+const BANDGAP_MV: u32 = 1100;
+
+// Temperature sensor: ~314 mV at 25 °C, rising ~1 mV/°C
+const TEMPERATURE_MV_AT_25C: i32 = 314;
+
+struct Schedule {
+    interval_ms: u32,
+    last_ms: Option<u32>,
+}
+
+impl Schedule {
+    fn new(interval_ms: u32) -> Self {
+        Schedule { interval_ms, last_ms: None }
+    }
+
+    fn due(&self) -> bool {
+        match self.last_ms {
+            Some(last) => clock::elapsed_ms(last) >= self.interval_ms,
+            None => true,
+        }
+    }
+
+    fn mark(&mut self) {
+        self.last_ms = Some(clock::millis());
+    }
+}
+
+/* Die temperature in tenths of °C. Only good to ±10 °C without the offset */
+pub struct InternalTemperature<'a> {
+    adc: &'a AdcReference,
+    offset_tenths: i16,
+    schedule: Schedule,
+}
+
+impl<'a> InternalTemperature<'a> {
+    pub fn new(adc: &'a AdcReference, interval_ms: u32) -> Self {
+        InternalTemperature {
+            adc,
+            offset_tenths: 0,
+            schedule: Schedule::new(interval_ms),
+        }
+    }
+
+    /* Single point correction, added to every reading */
+    pub fn set_offset(&mut self, offset_tenths: i16) {
+        self.offset_tenths = offset_tenths;
+    }
+
+    fn to_tenths(&self, counts: u16) -> i16 {
+        let mv = (counts as u32 * BANDGAP_MV / (ADC_MAX as u32 + 1)) as i32;
+        return ((mv - TEMPERATURE_MV_AT_25C) * 10 + 250 + self.offset_tenths as i32) as i16;
+    }
+}
+
+impl<'a> Sensor for InternalTemperature<'a> {
+    type Output = i16;
+    type Error = Infallible;
+
+    fn units(&self) -> SensorUnits {
+        SensorUnits {
+            quantity: Quantity::Temperature,
+            symbol: "C",
+            decimals: 1,
+        }
+    }
+
+    fn read_sensor(&mut self) -> Result<Measurement<i16>, Infallible> {
+        let counts = self.adc.borrow_mut().read(&Temperature, ReferenceVoltage::Internal);
+        self.schedule.mark();
+        return Ok(Measurement {
+            value: self.to_tenths(counts),
+            timestamp_us: clock::micros(),
+        });
+    }
+
+    fn poll(&mut self) -> Result<Option<Measurement<i16>>, Infallible> {
+        if !self.schedule.due() {
+            return Ok(None);
+        }
+        return self.read_sensor().map(Some);
+    }
+}
+
+/*
+ * Supply voltage in mV, found by measuring the bandgap against AVcc. This is
+ * the battery voltage as long as the battery feeds VCC without a regulator.
+ */
+pub struct BatteryVoltage<'a> {
+    adc: &'a AdcReference,
+    schedule: Schedule,
+}
+
+impl<'a> BatteryVoltage<'a> {
+    pub fn new(adc: &'a AdcReference, interval_ms: u32) -> Self {
+        BatteryVoltage {
+            adc,
+            schedule: Schedule::new(interval_ms),
+        }
+    }
+}
+
+impl<'a> Sensor for BatteryVoltage<'a> {
+    type Output = u16;
+    type Error = Infallible;
+
+    fn units(&self) -> SensorUnits {
+        SensorUnits {
+            quantity: Quantity::Voltage,
+            symbol: "V",
+            decimals: 3,
+        }
+    }
+
+    fn read_sensor(&mut self) -> Result<Measurement<u16>, Infallible> {
+        let counts = self.adc.borrow_mut().read(&Vbg, ReferenceVoltage::AVcc);
+        self.schedule.mark();
+        let mv = BANDGAP_MV * (ADC_MAX as u32 + 1) / (counts as u32).max(1);
+        return Ok(Measurement {
+            value: mv.min(u16::MAX as u32) as u16,
+            timestamp_us: clock::micros(),
+        });
+    }
+
+    fn poll(&mut self) -> Result<Option<Measurement<u16>>, Infallible> {
+        if !self.schedule.due() {
+            return Ok(None);
+        }
+        return self.read_sensor().map(Some);
+    }
+}
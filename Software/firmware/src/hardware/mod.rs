@@ -1,17 +1,19 @@
 /* Generic sensor traits */
 pub mod sensor_generics;
 
-/* Specific peripherals */
+/* Specific peripherals, the portable drivers live in kitchen_core::drivers */
 //pub mod hcsr04;
-pub mod lcd;
 //pub mod mma8451;
-pub mod pca9685;
 pub mod hx711;
+pub mod hx711_sampler;
+pub mod internal_sensors;
 //pub mod sd;
 //pub mod lcd_c;
 
 /* Peripheral controllers */
-//pub mod eeprom_controller;
+pub mod adc_controller;
+pub mod clock;
+pub mod eeprom_controller;
 //pub mod spi_controller;
 pub mod twi_conroller;
 pub mod usart_controller;
@@ -0,0 +1,44 @@
+use kitchen_core::weighing::format::Fixed;
+
+/* What a sensor measures, so readings can be labelled without knowing the source */
+#[derive(ufmt::derive::uDebug, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Quantity {
+    Mass,
+    Temperature,
+    Voltage,
+}
+
+/* Readings are fixed point, `value` counts steps of 10^-decimals `symbol` */
+#[derive(ufmt::derive::uDebug, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SensorUnits {
+    pub quantity: Quantity,
+    pub symbol: &'static str,
+    pub decimals: u8,
+}
+
+#[derive(ufmt::derive::uDebug, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Measurement<T> {
+    pub value: T,
+    /* clock::micros() when the value was taken */
+    pub timestamp_us: u32,
+}
+
+impl<T: Copy + Into<i32>> Measurement<T> {
+    /* The value ready for printing with the sensor's units */
+    pub fn fixed(&self, units: &SensorUnits) -> Fixed {
+        return Fixed::new(self.value.into(), units.decimals);
+    }
+}
+
+pub trait Sensor {
+    type Output: Copy + Into<i32>;
+    type Error;
+
+    fn units(&self) -> SensorUnits;
+
+    /* Waits for a new value, how long depends on the sensor */
+    fn read_sensor(&mut self) -> Result<Measurement<Self::Output>, Self::Error>;
+
+    /* Never blocks, Ok(None) while no new value is available */
+    fn poll(&mut self) -> Result<Option<Measurement<Self::Output>>, Self::Error>;
+}
@@ -1,5 +1,6 @@
 use arduino_hal::pac::TWI;
 use core::cell::RefCell;
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
 
 pub type TwiReference = RefCell<TwiController>;
 
@@ -36,14 +37,7 @@ impl TwiController {
 
         self.start_transaction(slave_address, DataDirection::Read);
 
-        let last_byte = buffer.len() - 1;
-        for i in 0..last_byte + 1 {
-            buffer[i] = if i < last_byte {
-                self.read_ack()
-            } else {
-                self.read_nack()
-            };
-        }
+        self.read_data(buffer);
 
         self.stop_transaction();
     }
@@ -54,9 +48,24 @@ impl TwiController {
         self.stop_transaction();
     }
 
-    fn write_data(&mut self, slave_address: u8, buffer: &[u8]) {
-        self.start_transaction(slave_address, DataDirection::Write);
+    fn write_data(&mut self, slave_address: u8, buffer: &[u8]) -> bool {
+        if !self.start_transaction(slave_address, DataDirection::Write) {
+            return false;
+        }
         buffer.into_iter().for_each(|b| self.write_byte(*b));
+        return true;
+    }
+
+    /* Reads into buffer, assumes a read transaction has been started */
+    fn read_data(&mut self, buffer: &mut [u8]) {
+        let last_byte = buffer.len() - 1;
+        for i in 0..last_byte + 1 {
+            buffer[i] = if i < last_byte {
+                self.read_ack()
+            } else {
+                self.read_nack()
+            };
+        }
     }
 
     /* Assumes a read transaction has been started */
@@ -97,19 +106,7 @@ impl TwiController {
     }
 
     pub fn ping_device(&mut self, slave_address: u8) -> bool {
-        let ret: bool;
-        self.start_transaction(slave_address, DataDirection::Write);
-
-        self.wait();
-
-        match self.i2c.twsr.read().bits() & 0xF8 {
-            0x18 => {
-                ret = true;
-            }
-            _ => {
-                ret = false;
-            }
-        }
+        let ret = self.start_transaction(slave_address, DataDirection::Write);
 
         self.stop_transaction();
         return ret;
@@ -143,7 +140,8 @@ impl TwiController {
         self.wait();
     }
 
-    fn start_transaction(&mut self, slave_address: u8, direction: DataDirection) {
+    /* Returns whether the slave acknowledged its address */
+    fn start_transaction(&mut self, slave_address: u8, direction: DataDirection) -> bool {
         let byte: u8 = (slave_address << 1) | direction as u8;
 
         self.send_start_condition();
@@ -153,6 +151,11 @@ impl TwiController {
         self.write_byte(byte);
 
         self.wait();
+
+        match self.i2c.twsr.read().bits() & 0xF8 {
+            0x18 | 0x40 => true, /* SLA+W or SLA+R acknowledged */
+            _ => false,
+        }
     }
 
     pub fn stop_transaction(&mut self) {
@@ -184,3 +187,45 @@ impl TwiController {
         while (self.i2c.twcr.read().bits() & (1 << 7)) == 0 {}
     }
 }
+
+/* Blocking embedded-hal I2C, a missing acknowledge of the address is NoConnection */
+impl Write for TwiController {
+    type Error = TwiError;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), TwiError> {
+        let acked = self.write_data(address, bytes);
+        self.stop_transaction();
+        if !acked {
+            return Err(TwiError::NoConnection);
+        }
+        Ok(())
+    }
+}
+
+impl Read for TwiController {
+    type Error = TwiError;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), TwiError> {
+        if !self.start_transaction(address, DataDirection::Read) {
+            self.stop_transaction();
+            return Err(TwiError::NoConnection);
+        }
+        if !buffer.is_empty() {
+            self.read_data(buffer);
+        }
+        self.stop_transaction();
+        Ok(())
+    }
+}
+
+impl WriteRead for TwiController {
+    type Error = TwiError;
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), TwiError> {
+        if !self.write_data(address, bytes) {
+            self.stop_transaction();
+            return Err(TwiError::NoConnection);
+        }
+        self.read(address, buffer)
+    }
+}
@@ -0,0 +1,310 @@
+#![no_std]
+#![no_main]
+#![feature(generic_const_exprs)]
+#![feature(core_ffi_c)]
+#![feature(abi_avr_interrupt)]
+
+/* Import crates */
+pub mod hardware;
+pub mod utils;
+
+use core::cell::RefCell;
+
+use panic_halt as _;
+
+use hardware::{
+    adc_controller::*,
+    eeprom_controller::*,
+    twi_conroller::*,
+    usart_controller::*,
+    hx711::*,
+    hx711_sampler::*,
+    internal_sensors::*,
+    sensor_generics::*,
+    clock,
+};
+use kitchen_core::app::{Scale, ScaleEvent};
+use kitchen_core::drivers::{button::*, lcd::LCD, pca9685::*, shared_bus::SharedI2c};
+#[cfg(feature = "load-cell-array")]
+use kitchen_core::drivers::load_cell_array::{LoadCellArray, SckWiring};
+#[cfg(feature = "load-cell-array")]
+use kitchen_core::weighing::corner_correction::PLATFORM_CELLS;
+#[cfg(feature = "load-cell-array")]
+use arduino_hal::port::{mode, Pin};
+use kitchen_core::settings::Settings;
+use kitchen_core::weighing::calibration::LoadCellCalibration;
+use kitchen_core::weighing::format::*;
+use kitchen_core::weighing::overload::{LoadLimits, DEFAULT_LIMITS};
+use kitchen_core::weighing::timing::SampleTiming;
+use utils::logging_tool::*;
+
+type Callback = fn(&mut [u8]);
+
+/* Logs the next value of any sensor together with its units */
+fn log_sensor<S: Sensor>(logger_ref: &LoggingToolReference, sensor: &mut S) {
+    if let Ok(Some(measurement)) = sensor.poll() {
+        let units = sensor.units();
+        logln!(logger_ref, "{} {}", measurement.fixed(&units), units.symbol);
+    }
+}
+
+/* Zero and calibration of channel A at gain 128 and at gain 64 */
+#[cfg(not(feature = "load-cell-array"))]
+type RangeCalibrations = (i32, LoadCellCalibration, i32, LoadCellCalibration);
+
+#[cfg(not(feature = "load-cell-array"))]
+fn range_calibrations(settings: &Settings) -> RangeCalibrations {
+    return (settings.offset, settings.calibration, settings.offset_a64, settings.calibration_a64);
+}
+
+#[cfg(not(feature = "load-cell-array"))]
+fn set_range_calibrations(hx711: &mut LoadCell, ranges: RangeCalibrations) {
+    hx711.set_range_calibration(128, ranges.0, ranges.1);
+    hx711.set_range_calibration(64, ranges.2, ranges.3);
+}
+
+/* One HX711 of a multi-cell platform */
+#[cfg(feature = "load-cell-array")]
+fn load_cell(dout: Pin<mode::Input<mode::PullUp>>, pd_sck: Pin<mode::Output>, rate: Hx711Rate) -> LoadCell {
+    let mut cell = HX711::new(dout, pd_sck, arduino_hal::Delay::new(), 1);
+    cell.set_rate(rate);
+    cell.set_clock(clock::millis);
+    return cell;
+}
+
+#[arduino_hal::entry]
+fn main() -> ! {
+    const BAUD_RATE: u32 = 57600;
+    const LCD_SLAVE_ADDR: u8 = 0x27;
+    const HOUSEKEEPING_INTERVAL_MS: u32 = 10_000;
+    // RATE of the HX711 is tied to GND on the board
+    const HX711_RATE: Hx711Rate = Hx711Rate::Sps10;
+    // SW1 still held this long after power up starts the temperature drift test instead,
+    // on a multi-cell platform held on to the second mark the corner-load test
+    const DRIFT_TEST_HOLD_MS: u32 = 5000;
+    const CORNER_TEST_HOLD_MS: u32 = 10_000;
+    // Rated capacity of the TAL220B and the overload margin. A platform on four
+    // cells keeps the rating of one, a load over a corner sits mostly on one cell.
+    const LOAD_LIMITS: LoadLimits = DEFAULT_LIMITS;
+
+    let dp = arduino_hal::Peripherals::take().unwrap();
+    let pins = arduino_hal::pins!(dp);
+    let mut serial = UsartController::new(
+        dp.USART0,
+        pins.d0.downgrade(),
+        pins.d1.into_output().downgrade(),
+    );
+    serial.init(BAUD_RATE);
+
+    clock::init(dp.TC0);
+    unsafe { avr_device::interrupt::enable() };
+
+    let uart_ref = RefCell::new(serial);
+    let mut logger = LoggingTool::new(LoggerType::Uart(uart_ref));
+    let logger_ref = RefCell::new(logger);
+
+    /* TWI Controller */
+    let mut twi_controller = TwiController::new(dp.TWI);
+    let devices_connected = twi_controller.ping_for_devices();
+    let twi_reference: TwiReference = RefCell::new(twi_controller);
+
+    /* Internal sensors share the ADC */
+    let adc_reference: AdcReference = RefCell::new(AdcController::new(dp.ADC));
+    let mut temperature = InternalTemperature::new(&adc_reference, HOUSEKEEPING_INTERVAL_MS);
+    let mut battery = BatteryVoltage::new(&adc_reference, HOUSEKEEPING_INTERVAL_MS);
+
+    /* Settings for the driver, defaults if the EEPROM holds no valid record */
+    let mut eeprom = EepromController::new(dp.EEPROM);
+    #[cfg(not(feature = "load-cell-array"))]
+    let settings = Settings::load(&mut eeprom).unwrap_or_default();
+
+    let lcd = LCD::init(SharedI2c::new(&twi_reference), arduino_hal::Delay::new());
+
+    #[cfg(not(feature = "load-cell-array"))]
+    let mut weight_sensor: LoadCell = HX711::new(
+        pins.d2.into_pull_up_input().downgrade(),
+        pins.d3.into_output().downgrade(),
+        arduino_hal::Delay::new(),
+        1
+    );
+    #[cfg(not(feature = "load-cell-array"))]
+    let (mut sensor_offset, mut sensor_calibration) = (settings.offset, settings.calibration);
+    // Gain 128 for small loads, gain 64 once a heavy pot gets near the rails
+    #[cfg(not(feature = "load-cell-array"))]
+    let (mut sensor_gain, mut sensor_ranges) = (None, range_calibrations(&settings));
+    #[cfg(not(feature = "load-cell-array"))]
+    {
+        weight_sensor.set_rate(HX711_RATE);
+        weight_sensor.set_clock(clock::millis);
+        weight_sensor.set_offset(sensor_offset);
+        weight_sensor.set_calibration(sensor_calibration);
+        weight_sensor.set_auto_range(true);
+        set_range_calibrations(&mut weight_sensor, sensor_ranges);
+    }
+
+    /*
+     * One HX711 per corner cell, DOUT and PD_SCK of each on their own pins,
+     * polled from the main loop. The cells do not auto-range, the scale adds
+     * them up with the corner-load factors from the settings.
+     */
+    #[cfg(feature = "load-cell-array")]
+    let mut cells: LoadCellArray<_, _, _, PLATFORM_CELLS> = LoadCellArray::new(
+        [
+            load_cell(pins.d2.into_pull_up_input().downgrade(), pins.d3.into_output().downgrade(), HX711_RATE),
+            load_cell(pins.d5.into_pull_up_input().downgrade(), pins.d6.into_output().downgrade(), HX711_RATE),
+            load_cell(pins.d7.into_pull_up_input().downgrade(), pins.d8.into_output().downgrade(), HX711_RATE),
+            load_cell(pins.d9.into_pull_up_input().downgrade(), pins.d10.into_output().downgrade(), HX711_RATE),
+        ],
+        SckWiring::Separate,
+    );
+    #[cfg(feature = "load-cell-array")]
+    let mut cells_gain = 128;
+
+    /*
+     * SW1, holding it during power up starts the calibration wizard, holding
+     * it 5 s the drift test, 10 s the corner-load test of a multi-cell platform
+     */
+    let mut button = Button::new(pins.d4.into_pull_up_input().downgrade(), clock::millis());
+    let mut scale = Scale::new(lcd, eeprom, HX711_RATE.sample_rate(), button.is_pressed());
+    scale.set_limits(LOAD_LIMITS);
+    logln!(logger_ref, "capacity {} g", LOAD_LIMITS.capacity.grams());
+    let mut power_up_hold = button.is_pressed();
+    let mut drift_test_started = false;
+    let power_up_ms = clock::millis();
+
+    #[cfg(not(feature = "load-cell-array"))]
+    let mut sampler = Hx711Sampler::start(weight_sensor, dp.EXINT);
+    let mut last_sample_ms = clock::millis();
+    let mut timing = SampleTiming::new(HX711_RATE.sample_rate());
+    let mut last_timing_ms = clock::millis();
+
+    loop {
+        #[cfg(not(feature = "load-cell-array"))]
+        while let Some(sample) = sampler.pop() {
+            last_sample_ms = clock::millis();
+            timing.on_sample(sample.timestamp_us, sample.skipped);
+            // Channel B has nothing attached yet, the scale weighs on channel A
+            if sample.channel == Hx711Channel::A {
+                scale.on_sample(sample.reading, sample.gain);
+            }
+        }
+        #[cfg(feature = "load-cell-array")]
+        match cells.try_read_cells() {
+            Ok(Some(raws)) => {
+                last_sample_ms = clock::millis();
+                timing.on_sample(clock::micros(), 0);
+                scale.on_cells(Ok(raws), cells_gain);
+            }
+            Ok(None) => {}
+            Err(e) => scale.on_cells(Err(e), cells_gain),
+        }
+        if clock::elapsed_ms(last_sample_ms) > HX711_RATE.ready_timeout_ms() {
+            scale.on_sample(Err(Hx711Error::Timeout), 128);
+        }
+
+        // The wizards hold the gain, all cells follow
+        #[cfg(feature = "load-cell-array")]
+        if scale.fixed_gain().unwrap_or(128) != cells_gain {
+            cells_gain = scale.fixed_gain().unwrap_or(128);
+            cells.set_gain(cells_gain);
+            // Conversions dropped while the cells settle are not missed ones
+            timing.set_rate(HX711_RATE.sample_rate());
+        }
+
+        /* Keep the driver in step, its Sensor output uses offset and calibration too */
+        #[cfg(not(feature = "load-cell-array"))]
+        if scale.offset() != sensor_offset {
+            sensor_offset = scale.offset();
+            sampler.with_sensor(|hx711| hx711.set_offset(sensor_offset));
+        }
+        #[cfg(not(feature = "load-cell-array"))]
+        if scale.settings().calibration != sensor_calibration {
+            sensor_calibration = scale.settings().calibration;
+            sampler.with_sensor(|hx711| hx711.set_calibration(sensor_calibration));
+        }
+        #[cfg(not(feature = "load-cell-array"))]
+        if range_calibrations(scale.settings()) != sensor_ranges {
+            sensor_ranges = range_calibrations(scale.settings());
+            sampler.with_sensor(|hx711| set_range_calibrations(hx711, sensor_ranges));
+        }
+        // The calibration wizard captures each gain on its own
+        #[cfg(not(feature = "load-cell-array"))]
+        if scale.fixed_gain() != sensor_gain {
+            sensor_gain = scale.fixed_gain();
+            sampler.with_sensor(|hx711| match sensor_gain {
+                Some(gain) => hx711.set_gain(gain),
+                None => hx711.set_auto_range(true),
+            });
+        }
+
+        /* Every reading is corrected for the die temperature, the drift test needs it too */
+        if let Ok(Some(measurement)) = temperature.poll() {
+            scale.on_temperature(measurement.value);
+            if !scale.is_calibrating() {
+                let units = temperature.units();
+                logln!(logger_ref, "{} {}", measurement.fixed(&units), units.symbol);
+            }
+        }
+
+        let pressed = button.poll(clock::millis());
+        if power_up_hold && !button.is_pressed() {
+            power_up_hold = false;
+        } else if power_up_hold && clock::elapsed_ms(power_up_ms) >= CORNER_TEST_HOLD_MS {
+            power_up_hold = false;
+            scale.start_corner_test();
+        } else if power_up_hold && !drift_test_started && clock::elapsed_ms(power_up_ms) >= DRIFT_TEST_HOLD_MS {
+            drift_test_started = true;
+            power_up_hold = cfg!(feature = "load-cell-array");
+            scale.learn_drift();
+        }
+        let event = scale.update(pressed);
+        if scale.is_calibrating() {
+            continue;
+        }
+
+        log_sensor(&logger_ref, &mut battery);
+
+        /* Sample timing since the last report, a rate far off means RATE is wired differently */
+        if clock::elapsed_ms(last_timing_ms) >= HOUSEKEEPING_INTERVAL_MS {
+            last_timing_ms = clock::millis();
+            let stats = timing.take();
+            #[cfg(not(feature = "load-cell-array"))]
+            let dropped = sampler.take_dropped();
+            #[cfg(feature = "load-cell-array")]
+            let dropped = 0;
+            logln!(
+                logger_ref,
+                "samples {} us jitter {} us missed {} dropped {}",
+                stats.mean_interval_us,
+                stats.jitter_us,
+                stats.missed,
+                dropped
+            );
+            if !stats.matches(HX711_RATE.sample_rate()) {
+                logln!(logger_ref, "HX711 rate differs from the configured {} SPS", HX711_RATE.sample_rate().sps());
+            }
+        }
+
+        /* Every settled reading also goes out on the UART, formatted like the LCD, and so do overloads */
+        match event {
+            Some(ScaleEvent::Settled { reading, unit }) => {
+                logln!(
+                    logger_ref,
+                    "{} {}",
+                    reading.styled(Style::right(12)),
+                    unit.styled(Style::left(5))
+                );
+            }
+            Some(ScaleEvent::Overload { gross, count }) => {
+                logln!(logger_ref, "overload {} g, {} so far", gross.grams(), count);
+            }
+            Some(ScaleEvent::Underload { gross }) => {
+                logln!(logger_ref, "underload {} g, platform lifted", gross.grams());
+            }
+            Some(ScaleEvent::LoadNormal) => {
+                logln!(logger_ref, "load back in range");
+            }
+            None => {}
+        }
+    }
+}
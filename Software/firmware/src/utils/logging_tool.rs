@@ -104,4 +104,10 @@ macro_rules! input_char {
 macro_rules! log {
     ( $( $arg:expr ),* ) => {};
 }
+
+#[cfg(not(debug_assertions))]
+#[macro_export]
+macro_rules! logln {
+    ( $( $arg:expr ),* ) => {};
+}
 pub(crate) use {input_char, input_string, log, logln};
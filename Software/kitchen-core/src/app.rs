@@ -0,0 +1,740 @@
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::blocking::i2c::{Read, Write};
+
+use crate::drivers::button::ButtonEvent;
+use crate::drivers::hx711::Hx711Error;
+use crate::drivers::lcd::LCD;
+use crate::settings::{Settings, Storage};
+use crate::ui::calibration_wizard::CalibrationWizard;
+use crate::ui::corner_test::CornerTest;
+use crate::ui::drift_wizard::DriftWizard;
+use crate::ui::glyphs;
+use crate::weighing::corner_correction::{self, PLATFORM_CELLS};
+use crate::weighing::filter::{Filter, FilterPipeline, DEFAULT_PIPELINE};
+use crate::weighing::format::{Render, Style};
+use crate::weighing::graduation::{Graduation, DEFAULT_HYSTERESIS_PERCENT};
+use crate::weighing::overload::{LoadLimits, LoadState, OverloadDetector};
+use crate::weighing::stability::{Motion, StabilityDetector, DEFAULT_STABILITY};
+use crate::weighing::timing::SampleRate;
+use crate::weighing::units::{Unit, UnitFormat, UnitReading};
+use crate::weighing::weight::Weight;
+use crate::weighing::zero_tracking::{ZeroTracker, DEFAULT_ZERO_TRACKING};
+
+/*
+ * The scale application: everything between the load cell samples and the
+ * button on one side, and the LCD and the settings storage on the other.
+ * The firmware and the host simulator drive it from their main loop, so it
+ * never blocks and has no notion of time; timeouts are detected by the
+ * caller and fed in as sensor errors. The sample rate the HX711 is wired for
+ * sizes the filter and stability windows.
+ *
+ * A short press tares once the reading is stable, a long press switches to
+ * the next unit. Starting with `calibrate` runs the calibration wizard first.
+ * Outside the wizard the HX711 is expected to auto-range, with the gain 64
+ * readings already mapped to gain 128 counts, see HX711::set_auto_range.
+ *
+ * The internal temperature, fed in whenever the caller measures it, corrects
+ * every reading for the drift learned by the drift wizard, see
+ * weighing::temperature.
+ *
+ * Past the capacity the display shows "OL", with the platform lifted "-OL",
+ * and the button does nothing until the load is back in range. A saturated
+ * HX711 is out of range too, the sign of the last reading tells which way.
+ * Every overload is counted in the settings.
+ *
+ * A platform on PLATFORM_CELLS cells (see LoadCellArray) feeds all cells
+ * through `on_cells` instead. The calibration covers the platform as a whole
+ * and weighs the sum of the cells, each scaled by the factor the corner-load
+ * test saved in the settings.
+ */
+
+/* Things worth telling the outside world about, the firmware logs them on the UART */
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ScaleEvent {
+    /* The reading settled, as shown on the display */
+    Settled { reading: UnitReading, unit: Unit },
+    /* Gross load past the capacity, `count` overloads so far */
+    Overload { gross: Weight, count: u32 },
+    /* Gross load below zero, the platform is lifted */
+    Underload { gross: Weight },
+    /* Back within the limits */
+    LoadNormal,
+}
+
+pub struct Scale<I2C, D, S> {
+    lcd: LCD<I2C, D>,
+    storage: S,
+    settings: Settings,
+    wizard: Option<CalibrationWizard>,
+    drift_wizard: Option<DriftWizard>,
+    corner_test: Option<CornerTest<PLATFORM_CELLS>>,
+    rate: SampleRate,
+    /* Latest internal temperature, tenths of °C */
+    temperature: Option<i16>,
+    filter: FilterPipeline,
+    filtered_raw: i32,
+    /* Latest reading before any correction, its sign tells which rail a saturated HX711 hit */
+    last_raw: i32,
+    offset: i32,
+    format: UnitFormat,
+    graduation: Graduation,
+    displayed_steps: i32,
+    stability: StabilityDetector,
+    zero_tracker: ZeroTracker,
+    overload: OverloadDetector,
+    /* Load state change and the gross load that caused it, not handled yet */
+    load_change: Option<(LoadState, Weight)>,
+    tare_pending: bool,
+    status: Option<Result<i32, Hx711Error>>,
+    sensor_error: Option<Hx711Error>,
+    shown_motion: Option<Motion>,
+    shown_reading: Option<(i32, Unit)>,
+}
+
+impl<I2C, D, S> Scale<I2C, D, S>
+where
+    I2C: Write + Read,
+    D: DelayUs<u16> + DelayMs<u16>,
+    S: Storage,
+{
+    /* Loads the settings, defaults if the storage holds no valid record */
+    pub fn new(mut lcd: LCD<I2C, D>, mut storage: S, rate: SampleRate, calibrate: bool) -> Self {
+        let settings = Settings::load(&mut storage).unwrap_or_default();
+
+        lcd.clear();
+        lcd.home();
+        glyphs::load(&mut lcd);
+        if !settings.backlight {
+            lcd.no_backlight();
+        }
+
+        let format = settings.unit.format(settings.gram_division, settings.density);
+        let division = settings.calibration.counts_for(format.division.weight());
+        Self {
+            lcd,
+            storage,
+            wizard: if calibrate { Some(CalibrationWizard::new(rate)) } else { None },
+            drift_wizard: None,
+            corner_test: None,
+            rate,
+            temperature: None,
+            filter: FilterPipeline::new(&DEFAULT_PIPELINE, rate),
+            filtered_raw: 0,
+            last_raw: 0,
+            offset: settings.offset,
+            format,
+            graduation: Graduation::new(format.division, DEFAULT_HYSTERESIS_PERCENT),
+            displayed_steps: 0,
+            stability: StabilityDetector::new(DEFAULT_STABILITY, division, rate),
+            zero_tracker: ZeroTracker::new(DEFAULT_ZERO_TRACKING, division, rate),
+            overload: OverloadDetector::new(settings.limits),
+            load_change: None,
+            tare_pending: false,
+            status: None,
+            sensor_error: None,
+            shown_motion: None,
+            shown_reading: None,
+            settings,
+        }
+    }
+
+    pub fn settings(&self) -> &Settings {
+        return &self.settings;
+    }
+
+    /* Raw reading of the empty platform, including zero tracking */
+    pub fn offset(&self) -> i32 {
+        return self.offset;
+    }
+
+    pub fn motion(&self) -> Motion {
+        return self.stability.state();
+    }
+
+    pub fn load_state(&self) -> LoadState {
+        return self.overload.state();
+    }
+
+    /* Capacity and overload margin of the cell, saved with the settings. False if they are not valid. */
+    pub fn set_limits(&mut self, limits: LoadLimits) -> bool {
+        if !limits.is_valid() {
+            return false;
+        }
+        if self.settings.limits != limits {
+            self.settings.limits = limits;
+            self.settings.save(&mut self.storage);
+        }
+        self.overload.set_limits(limits);
+        return true;
+    }
+
+    pub fn is_calibrating(&self) -> bool {
+        return self.wizard.is_some() || self.drift_wizard.is_some() || self.corner_test.is_some();
+    }
+
+    /* Gain the HX711 has to be held at, None while it may auto-range */
+    pub fn fixed_gain(&self) -> Option<u8> {
+        if self.drift_wizard.is_some() || self.corner_test.is_some() {
+            return Some(128);
+        }
+        return self.wizard.as_ref().map(|wizard| wizard.gain());
+    }
+
+    /* Starts the temperature drift test, in place of the calibration wizard if that runs */
+    pub fn learn_drift(&mut self) {
+        self.wizard = None;
+        self.corner_test = None;
+        self.drift_wizard = Some(DriftWizard::new(self.rate, self.temperature));
+    }
+
+    /* Starts the corner-load test of a multi-cell platform, in place of any other wizard */
+    pub fn start_corner_test(&mut self) {
+        self.wizard = None;
+        self.drift_wizard = None;
+        self.corner_test = Some(CornerTest::new(self.rate));
+    }
+
+    /* Internal temperature in tenths of °C, whenever the caller measured it */
+    pub fn on_temperature(&mut self, tenths: i16) {
+        self.temperature = Some(tenths);
+        if let Some(wizard) = self.drift_wizard.as_mut() {
+            wizard.on_temperature(tenths);
+        }
+    }
+
+    /* Gives the display and the storage back */
+    pub fn release(self) -> (LCD<I2C, D>, S) {
+        return (self.lcd, self.storage);
+    }
+
+    /*
+     * Feeds one conversion and the gain it was made at, or the error the
+     * sampler ran into. The wizards only take readings at the gain they ask
+     * for, see fixed_gain().
+     */
+    pub fn on_sample(&mut self, reading: Result<i32, Hx711Error>, gain: u8) {
+        if let Ok(raw) = reading {
+            self.last_raw = raw;
+            // The wizards work on the raw reading, they capture at the current temperature
+            match (self.wizard.as_mut(), self.drift_wizard.as_mut()) {
+                (Some(wizard), _) => wizard.on_sample(raw, gain),
+                (None, Some(wizard)) => wizard.on_sample(raw, gain),
+                (None, None) => {
+                    let raw = match self.temperature {
+                        Some(tenths) => self.settings.compensation.apply(raw, self.settings.offset, tenths),
+                        None => raw,
+                    };
+                    self.filtered_raw = self.filter.update(raw);
+                    let motion = self.stability.update(self.filtered_raw);
+                    self.offset += self.zero_tracker.update(self.filtered_raw - self.offset, motion);
+                    self.displayed_steps = self
+                        .graduation
+                        .update(self.settings.calibration.to_weight(self.filtered_raw - self.offset));
+                    // Gross, from the calibration zero whatever the tare
+                    let gross = self.settings.calibration.to_weight(self.filtered_raw - self.settings.offset);
+                    if let Some(state) = self.overload.update(gross) {
+                        self.load_change = Some((state, gross));
+                    }
+                }
+            }
+        }
+        // Past the rails of the converter, far beyond any capacity
+        if reading == Err(Hx711Error::Saturated) && !self.is_calibrating() {
+            let rail = if self.last_raw < 0 { Weight::MIN } else { Weight::MAX };
+            if let Some(state) = self.overload.update(rail) {
+                // The last reading still within the rails, for the log
+                let gross = self.settings.calibration.to_weight(self.last_raw - self.settings.offset);
+                self.load_change = Some((state, gross));
+            }
+            self.status = Some(Ok(self.last_raw));
+            return;
+        }
+        self.status = Some(reading);
+    }
+
+    /*
+     * Feeds one reading of every cell of a multi-cell platform, see on_sample.
+     * The corner-load test takes each cell on its own, in grams through the
+     * calibration of the whole platform.
+     */
+    pub fn on_cells(&mut self, reading: Result<[i32; PLATFORM_CELLS], Hx711Error>, gain: u8) {
+        match (self.corner_test.as_mut(), reading) {
+            (Some(test), Ok(raws)) => {
+                let calibration = self.settings.calibration;
+                test.on_sample(&raws.map(|raw| calibration.to_weight(raw)));
+            }
+            (Some(_), Err(_)) => {}
+            (None, _) => {
+                let corrections = self.settings.corner_ppm;
+                self.on_sample(reading.map(|raws| corner_correction::corrected_counts(&raws, &corrections)), gain);
+            }
+        }
+    }
+
+    /* Handles the button and redraws what changed, once per main loop pass */
+    pub fn update(&mut self, button: Option<ButtonEvent>) -> Option<ScaleEvent> {
+        let status = self.status.take();
+
+        if self.wizard.is_some() {
+            self.update_wizard(button);
+            return None;
+        }
+        if self.drift_wizard.is_some() {
+            self.update_drift_wizard(button);
+            return None;
+        }
+        if self.corner_test.is_some() {
+            self.update_corner_test(button);
+            return None;
+        }
+
+        /* Tare waits for the reading to settle, the button is ignored out of range */
+        let button = button.filter(|_| self.overload.state() == LoadState::Normal);
+        if button == Some(ButtonEvent::Short) {
+            self.tare_pending = true;
+        }
+        if button == Some(ButtonEvent::Long) {
+            self.settings.unit = self.settings.unit.next();
+            self.settings.save(&mut self.storage);
+            self.apply_format();
+        }
+        if self.tare_pending && self.stability.is_stable() {
+            self.offset = self.filtered_raw;
+            self.zero_tracker.rezero();
+            self.tare_pending = false;
+        }
+
+        match status {
+            Some(Err(e)) if self.sensor_error != Some(e) => {
+                self.lcd.clear();
+                self.lcd.write_str(e.as_str());
+                self.sensor_error = Some(e);
+                self.shown_motion = None;
+                self.shown_reading = None;
+            }
+            Some(Ok(_)) if self.sensor_error.is_some() => {
+                self.lcd.clear();
+                self.sensor_error = None;
+            }
+            _ => {}
+        }
+        if self.sensor_error.is_some() {
+            return None;
+        }
+
+        if let Some((state, gross)) = self.load_change.take() {
+            self.lcd.clear();
+            self.shown_motion = None;
+            self.shown_reading = None;
+            match state {
+                LoadState::Over => {
+                    self.settings.overload_count = self.settings.overload_count.saturating_add(1);
+                    self.settings.save(&mut self.storage);
+                    self.tare_pending = false;
+                    self.show_out_of_range("OL", "Remove the load");
+                    return Some(ScaleEvent::Overload { gross, count: self.settings.overload_count });
+                }
+                LoadState::Under => {
+                    self.tare_pending = false;
+                    self.show_out_of_range("-OL", "Platform lifted");
+                    return Some(ScaleEvent::Underload { gross });
+                }
+                // The reading is drawn again on the next pass
+                LoadState::Normal => return Some(ScaleEvent::LoadNormal),
+            }
+        }
+        if self.overload.state() != LoadState::Normal {
+            return None;
+        }
+
+        /* Value right aligned in the first 12 columns, unit symbol after it */
+        let unit = self.settings.unit;
+        let reading = unit.reading(self.displayed_steps, &self.format);
+        if self.shown_reading != Some((self.displayed_steps, unit)) {
+            self.lcd.set_cursor(0, 0);
+            self.lcd.write_str(reading.styled(Style::right(12)).to_field().as_str());
+            self.lcd.write_char(b' ');
+            self.lcd.write_str(unit.styled(Style::left(5)).to_field().as_str());
+            self.shown_reading = Some((self.displayed_steps, unit));
+        }
+
+        let motion = self.stability.state();
+        if self.shown_motion != Some(motion) {
+            self.lcd.set_cursor(19, 0);
+            self.lcd.write_char(match motion {
+                Motion::Stable => glyphs::STABLE_GLYPH,
+                Motion::Moving => b' ',
+            });
+            self.shown_motion = Some(motion);
+
+            if motion == Motion::Stable {
+                return Some(ScaleEvent::Settled { reading, unit });
+            }
+        }
+        return None;
+    }
+
+    /* "OL" where the value goes, the reason below it */
+    fn show_out_of_range(&mut self, symbol: &str, reason: &str) {
+        self.lcd.set_cursor(12 - symbol.len() as u8, 0);
+        self.lcd.write_str(symbol);
+        self.lcd.set_cursor(0, 1);
+        self.lcd.write_str(reason);
+    }
+
+    fn update_wizard(&mut self, button: Option<ButtonEvent>) {
+        let wizard = match self.wizard.as_mut() {
+            Some(wizard) => wizard,
+            None => return,
+        };
+        if let Some(event) = button {
+            wizard.on_button(event);
+        }
+        wizard.render(&mut self.lcd);
+        if !wizard.is_finished() {
+            return;
+        }
+
+        if let Some((zero, calibration)) = wizard.result() {
+            self.offset = zero;
+            self.settings.offset = zero;
+            self.settings.calibration = calibration;
+            if let Some((zero, calibration)) = wizard.low_gain_result() {
+                self.settings.offset_a64 = zero;
+                self.settings.calibration_a64 = calibration;
+            }
+            // The drift is corrected back to the temperature of the calibration, none turns it off
+            self.settings.compensation.reference_tenths = self.temperature;
+            self.settings.save(&mut self.storage);
+            self.apply_format();
+            self.zero_tracker.rezero();
+        }
+        self.wizard = None;
+        self.leave_wizard();
+    }
+
+    fn update_drift_wizard(&mut self, button: Option<ButtonEvent>) {
+        let wizard = match self.drift_wizard.as_mut() {
+            Some(wizard) => wizard,
+            None => return,
+        };
+        if let Some(event) = button {
+            wizard.on_button(event);
+        }
+        wizard.render(&mut self.lcd);
+        if !wizard.is_finished() {
+            return;
+        }
+
+        if let Some(drift) = wizard.result() {
+            self.settings.compensation.drift = drift;
+            self.settings.save(&mut self.storage);
+        }
+        self.drift_wizard = None;
+        self.leave_wizard();
+    }
+
+    fn update_corner_test(&mut self, button: Option<ButtonEvent>) {
+        let test = match self.corner_test.as_mut() {
+            Some(test) => test,
+            None => return,
+        };
+        if let Some(event) = button {
+            test.on_button(event);
+        }
+        test.render(&mut self.lcd);
+        if !test.is_finished() {
+            return;
+        }
+
+        if let Some(corrections) = test.result() {
+            // The corrected sum of the empty platform is the new zero
+            let calibration = self.settings.calibration;
+            let zero = test.zero().map(|weight| calibration.to_counts(weight));
+            self.settings.corner_ppm = corrections;
+            self.settings.offset = corner_correction::corrected_counts(&zero, &corrections);
+            self.offset = self.settings.offset;
+            self.settings.save(&mut self.storage);
+            self.zero_tracker.rezero();
+        }
+        self.corner_test = None;
+        self.leave_wizard();
+    }
+
+    /* Back to weighing, the filters start over */
+    fn leave_wizard(&mut self) {
+        self.filter.reset();
+        self.stability.reset();
+        self.lcd.clear();
+        self.shown_motion = None;
+        self.shown_reading = None;
+    }
+
+    /* Display division, and everything sized by it, after a unit or calibration change */
+    fn apply_format(&mut self) {
+        self.format = self.settings.unit.format(self.settings.gram_division, self.settings.density);
+        self.graduation.set_division(self.format.division);
+        let division = self.settings.calibration.counts_for(self.format.division.weight());
+        self.stability.set_division_counts(division);
+        self.zero_tracker.set_division_counts(division);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::shared_bus::SharedI2c;
+    use crate::settings::tests::Eeprom;
+    use crate::weighing::calibration::LoadCellCalibration;
+    use crate::weighing::temperature::TemperatureCompensation;
+    use crate::weighing::weight::Weight;
+    use core::cell::RefCell;
+    use kitchen_emu::delay::VirtualDelay;
+    use kitchen_emu::hd44780::LcdBackpack;
+
+    type TestScale<'a> = Scale<SharedI2c<'a, LcdBackpack>, VirtualDelay, Eeprom>;
+
+    const ZERO: i32 = 50_000;
+    const COUNTS_PER_GRAM: i32 = 400;
+
+    fn calibrated() -> Eeprom {
+        let mut eeprom = Eeprom([0xFF; 1024]);
+        let settings = Settings {
+            offset: ZERO,
+            calibration: LoadCellCalibration::from_scale(1000 * COUNTS_PER_GRAM, Weight::from_kg(1)),
+            // Calibrated at 25 °C
+            compensation: TemperatureCompensation::new(Some(250)),
+            ..Settings::default()
+        };
+        settings.save(&mut eeprom);
+        return eeprom;
+    }
+
+    fn scale<'a>(backpack: &'a RefCell<LcdBackpack>, eeprom: Eeprom, calibrate: bool) -> TestScale<'a> {
+        let lcd = LCD::init(SharedI2c::new(backpack), VirtualDelay::new());
+        return Scale::new(lcd, eeprom, SampleRate::from_sps(10), calibrate);
+    }
+
+    /* Feeds `samples` readings of a constant weight, returns the last event */
+    fn weigh(scale: &mut TestScale, grams: i32, samples: usize) -> Option<ScaleEvent> {
+        let mut last = None;
+        for _ in 0..samples {
+            scale.on_sample(Ok(ZERO + grams * COUNTS_PER_GRAM), 128);
+            last = scale.update(None).or(last);
+        }
+        return last;
+    }
+
+    #[test]
+    fn shows_the_settled_weight() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        let mut scale = scale(&backpack, calibrated(), false);
+
+        let event = weigh(&mut scale, 250, 20);
+        assert_eq!(
+            event,
+            Some(ScaleEvent::Settled {
+                reading: UnitReading::Decimal { value: 250, decimals: 0 },
+                unit: Unit::Grams
+            })
+        );
+        assert_eq!(backpack.borrow().lcd().row_text(0), "         250 g     ①");
+        assert_eq!(backpack.borrow().lcd().glyph(glyphs::STABLE_GLYPH), glyphs::STABLE_CHARMAP);
+    }
+
+    #[test]
+    fn tare_and_unit_change() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        let mut scale = scale(&backpack, calibrated(), false);
+        weigh(&mut scale, 100, 20);
+
+        scale.update(Some(ButtonEvent::Short));
+        weigh(&mut scale, 100, 5);
+        assert_eq!(scale.offset(), ZERO + 100 * COUNTS_PER_GRAM);
+        assert_eq!(backpack.borrow().lcd().row_text(0), "           0 g     ①");
+
+        scale.update(Some(ButtonEvent::Long));
+        assert_eq!(scale.settings().unit, Unit::Kilograms);
+        weigh(&mut scale, 1600, 30);
+        assert_eq!(backpack.borrow().lcd().row_text(0), "       1.500 kg    ①");
+
+        // The unit is saved
+        let (_, mut eeprom) = scale.release();
+        assert_eq!(Settings::load(&mut eeprom).map(|s| s.unit), Ok(Unit::Kilograms));
+    }
+
+    #[test]
+    fn sensor_errors_replace_the_reading() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        let mut scale = scale(&backpack, calibrated(), false);
+        weigh(&mut scale, 10, 20);
+
+        scale.on_sample(Err(Hx711Error::Timeout), 128);
+        scale.update(None);
+        assert_eq!(backpack.borrow().lcd().row_text(0), "HX711 not found     ");
+
+        weigh(&mut scale, 10, 1);
+        assert_eq!(backpack.borrow().lcd().row_text(0), "          10 g     ①");
+    }
+
+    #[test]
+    fn calibration_wizard_first() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        let mut scale = scale(&backpack, Eeprom([0xFF; 1024]), true);
+        assert!(scale.is_calibrating());
+        assert_eq!(scale.fixed_gain(), Some(128));
+
+        scale.update(Some(ButtonEvent::Long));
+        assert!(!scale.is_calibrating());
+        assert_eq!(scale.fixed_gain(), None);
+        // Cancelled, nothing saved
+        let (_, mut eeprom) = scale.release();
+        assert_eq!(Settings::load(&mut eeprom), Err(crate::settings::SettingsError::NoRecord));
+    }
+
+    #[test]
+    fn overload_and_lifted_platform() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        let mut scale = scale(&backpack, calibrated(), false);
+        assert!(!scale.set_limits(LoadLimits { capacity: Weight::ZERO, margin: Weight::from_grams(50) }));
+        assert!(scale.set_limits(LoadLimits { capacity: Weight::from_kg(2), margin: Weight::from_grams(50) }));
+        weigh(&mut scale, 500, 20);
+        scale.update(Some(ButtonEvent::Short));
+        weigh(&mut scale, 500, 5);
+
+        // The tared pot counts towards the capacity
+        let event = weigh(&mut scale, 2100, 30);
+        assert!(matches!(event, Some(ScaleEvent::Overload { count: 1, .. })), "{:?}", event);
+        assert_eq!(scale.load_state(), LoadState::Over);
+        assert_eq!(backpack.borrow().lcd().row_text(0).trim_end(), "          OL");
+        assert_eq!(backpack.borrow().lcd().row_text(1).trim_end(), "Remove the load");
+        scale.update(Some(ButtonEvent::Long));
+        assert_eq!(scale.settings().unit, Unit::Grams);
+
+        weigh(&mut scale, 1000, 30);
+        assert_eq!(scale.load_state(), LoadState::Normal);
+        assert_eq!(backpack.borrow().lcd().row_text(0), "         500 g     ①");
+
+        assert!(matches!(weigh(&mut scale, -200, 30), Some(ScaleEvent::Underload { .. })));
+        assert_eq!(backpack.borrow().lcd().row_text(0).trim_end(), "         -OL");
+
+        // Limits and count are saved
+        let (_, mut eeprom) = scale.release();
+        let settings = Settings::load(&mut eeprom).unwrap();
+        assert_eq!(settings.limits.capacity, Weight::from_kg(2));
+        assert_eq!(settings.overload_count, 1);
+    }
+
+    #[test]
+    fn saturated_hx711_is_out_of_range() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        let mut scale = scale(&backpack, calibrated(), false);
+        weigh(&mut scale, 4000, 20);
+
+        scale.on_sample(Err(Hx711Error::Saturated), 128);
+        let event = scale.update(None);
+        assert!(matches!(event, Some(ScaleEvent::Overload { count: 1, .. })), "{:?}", event);
+        assert_eq!(backpack.borrow().lcd().row_text(0).trim_end(), "          OL");
+        // Still saturated, still the one overload
+        scale.on_sample(Err(Hx711Error::Saturated), 128);
+        assert_eq!(scale.update(None), None);
+
+        weigh(&mut scale, 0, 30);
+        assert_eq!(scale.load_state(), LoadState::Normal);
+        // Heading for the negative rail
+        scale.on_sample(Ok(-10), 128);
+        scale.on_sample(Err(Hx711Error::Saturated), 128);
+        assert!(matches!(scale.update(None), Some(ScaleEvent::Underload { .. })));
+        assert_eq!(backpack.borrow().lcd().row_text(0).trim_end(), "         -OL");
+
+        let (_, mut eeprom) = scale.release();
+        assert_eq!(Settings::load(&mut eeprom).map(|s| s.overload_count), Ok(1));
+    }
+
+    /* Raw cells with `grams` on the platform, 70 % of it over `corner`, cell 1 reads 5 % high */
+    fn cells(grams: i32, corner: usize) -> [i32; PLATFORM_CELLS] {
+        let mut raws = [ZERO / PLATFORM_CELLS as i32; PLATFORM_CELLS];
+        for (i, raw) in raws.iter_mut().enumerate() {
+            let share = if i == corner { 70 } else { 10 };
+            let sensitivity = if i == 1 { 105 } else { 100 };
+            *raw += grams * COUNTS_PER_GRAM / 100 * share * sensitivity / 100;
+        }
+        return raws;
+    }
+
+    #[test]
+    fn corner_test_evens_out_the_corners() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        let mut scale = scale(&backpack, calibrated(), false);
+        scale.start_corner_test();
+        assert_eq!(scale.fixed_gain(), Some(128));
+
+        scale.update(Some(ButtonEvent::Short));
+        (0..30).for_each(|_| scale.on_cells(Ok(cells(0, 0)), 128));
+        // 1 kg test mass
+        scale.update(Some(ButtonEvent::Short));
+        scale.update(Some(ButtonEvent::Long));
+        for corner in 0..PLATFORM_CELLS {
+            scale.update(Some(ButtonEvent::Short));
+            (0..30).for_each(|_| scale.on_cells(Ok(cells(1000, corner)), 128));
+        }
+        scale.update(Some(ButtonEvent::Short));
+        assert!(!scale.is_calibrating());
+
+        // The same wherever the mass sits, and the mass it is
+        for corner in [0, 1, 3] {
+            for _ in 0..30 {
+                scale.on_cells(Ok(cells(1000, corner)), 128);
+                scale.update(None);
+            }
+            assert_eq!(backpack.borrow().lcd().row_text(0), "        1000 g     ①", "corner {}", corner);
+        }
+        // The zero moved with the factors
+        for _ in 0..30 {
+            scale.on_cells(Ok(cells(0, 0)), 128);
+            scale.update(None);
+        }
+        assert_eq!(backpack.borrow().lcd().row_text(0), "           0 g     ①");
+
+        let corners = scale.settings().corner_ppm;
+        assert!((corners[1] - 952_381).abs() < 100, "{:?}", corners);
+        let (_, mut eeprom) = scale.release();
+        assert_eq!(Settings::load(&mut eeprom).map(|s| s.corner_ppm), Ok(corners));
+    }
+
+    #[test]
+    fn drift_is_learned_and_corrected() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        let mut scale = scale(&backpack, calibrated(), false);
+        scale.on_temperature(250);
+        scale.learn_drift();
+        assert_eq!(scale.fixed_gain(), Some(128));
+
+        // 1 kg at 25 °C, where it was calibrated, and at 40 °C: zero +20 counts/°C, span +200 ppm/°C
+        for raw in [ZERO, ZERO + 400_000, 0, ZERO + 300, ZERO + 300 + 401_200] {
+            if raw == 0 {
+                // Off to the oven
+                scale.on_temperature(400);
+            }
+            scale.update(Some(ButtonEvent::Short));
+            if raw == 0 {
+                continue;
+            }
+            (0..30).for_each(|_| scale.on_sample(Ok(raw), 128));
+        }
+        scale.update(Some(ButtonEvent::Short));
+        scale.update(None);
+        assert!(!scale.is_calibrating());
+        assert_eq!(scale.settings().compensation.drift.offset_per_c, 20);
+        assert_eq!(scale.settings().compensation.drift.scale_ppm_per_c, 200);
+
+        // 500 g at 40 °C reads 500 g
+        let raw = ZERO + 300 + 200_600;
+        for _ in 0..30 {
+            scale.on_sample(Ok(raw), 128);
+            scale.update(None);
+        }
+        assert_eq!(backpack.borrow().lcd().row_text(0), "         500 g     ①");
+    }
+}
@@ -0,0 +1,143 @@
+use embedded_hal::digital::v2::InputPin;
+
+const DEBOUNCE_MS: u32 = 30;
+const LONG_PRESS_MS: u32 = 1000;
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ButtonEvent {
+    Short,
+    Long,
+}
+
+/*
+ * Debounced push button (SW1), active low against the internal pull-up.
+ * A short press is reported on release, a long press as soon as the
+ * button has been held for LONG_PRESS_MS. Times come from the caller, in
+ * milliseconds of any wrapping clock.
+ */
+pub struct Button<P> {
+    pin: P,
+    raw_pressed: bool,
+    pressed: bool,
+    last_change_ms: u32,
+    pressed_at_ms: u32,
+    long_reported: bool,
+}
+
+impl<P: InputPin> Button<P> {
+    pub fn new(pin: P, now_ms: u32) -> Self {
+        let pressed = pin.is_low().unwrap_or(false);
+        Self {
+            pin,
+            raw_pressed: pressed,
+            pressed,
+            last_change_ms: now_ms,
+            pressed_at_ms: now_ms,
+            // A button held at power up must be released before it reports anything
+            long_reported: pressed,
+        }
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        return self.pressed;
+    }
+
+    pub fn poll(&mut self, now: u32) -> Option<ButtonEvent> {
+        let raw_pressed = self.pin.is_low().unwrap_or(false);
+
+        if raw_pressed != self.raw_pressed {
+            self.raw_pressed = raw_pressed;
+            self.last_change_ms = now;
+        }
+        if now.wrapping_sub(self.last_change_ms) < DEBOUNCE_MS {
+            return None;
+        }
+
+        if raw_pressed != self.pressed {
+            self.pressed = raw_pressed;
+            if self.pressed {
+                self.pressed_at_ms = now;
+                self.long_reported = false;
+            } else if !self.long_reported {
+                return Some(ButtonEvent::Short);
+            }
+        } else if self.pressed
+            && !self.long_reported
+            && now.wrapping_sub(self.pressed_at_ms) >= LONG_PRESS_MS
+        {
+            self.long_reported = true;
+            return Some(ButtonEvent::Long);
+        }
+
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use core::convert::Infallible;
+
+    struct Pin<'a>(&'a Cell<bool>);
+
+    impl InputPin for Pin<'_> {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Infallible> {
+            return Ok(!self.0.get());
+        }
+
+        fn is_low(&self) -> Result<bool, Infallible> {
+            return Ok(self.0.get());
+        }
+    }
+
+    /* Polls every millisecond from `from` to `to`, returns the events */
+    fn run(button: &mut Button<Pin>, from: u32, to: u32) -> std::vec::Vec<(u32, ButtonEvent)> {
+        return (from..to).filter_map(|t| button.poll(t).map(|e| (t, e))).collect();
+    }
+
+    #[test]
+    fn short_press_on_release() {
+        let pressed = Cell::new(false);
+        let mut button = Button::new(Pin(&pressed), 0);
+        pressed.set(true);
+        assert!(run(&mut button, 0, 200).is_empty());
+        assert!(button.is_pressed());
+        pressed.set(false);
+        assert_eq!(run(&mut button, 200, 300), [(230, ButtonEvent::Short)]);
+    }
+
+    #[test]
+    fn long_press_while_held() {
+        let pressed = Cell::new(false);
+        let mut button = Button::new(Pin(&pressed), u32::MAX - 100);
+        pressed.set(true);
+        assert_eq!(run(&mut button, u32::MAX - 100, u32::MAX).len(), 0);
+        assert_eq!(run(&mut button, 0, 2000), [(929, ButtonEvent::Long)]);
+        pressed.set(false);
+        assert!(run(&mut button, 2000, 2100).is_empty());
+    }
+
+    #[test]
+    fn bounces_are_ignored() {
+        let pressed = Cell::new(false);
+        let mut button = Button::new(Pin(&pressed), 0);
+        for t in 0..100 {
+            pressed.set(t % 10 < 5);
+            assert_eq!(button.poll(t), None);
+        }
+        assert!(!button.is_pressed());
+    }
+
+    #[test]
+    fn held_at_power_up() {
+        let pressed = Cell::new(true);
+        let mut button = Button::new(Pin(&pressed), 0);
+        assert!(run(&mut button, 0, 2000).is_empty());
+        pressed.set(false);
+        assert!(run(&mut button, 2000, 2100).is_empty());
+    }
+}
@@ -0,0 +1,843 @@
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+use crate::weighing::calibration::LoadCellCalibration;
+use crate::weighing::timing::SampleRate;
+use crate::weighing::weight::Weight;
+
+// Limits of the 24-bit two's complement conversion result. The HX711 clamps
+// its output to these codes when the input is out of range.
+pub const HX711_MAX: i32 = 0x7F_FFFF;
+pub const HX711_MIN: i32 = -0x80_0000;
+
+// Time a read waits for DOUT to go low before giving up. At 10 SPS a
+// conversion takes 100 ms and waking from power down takes ~400 ms.
+pub const HX711_READY_TIMEOUT_MS: u32 = 500;
+// The same at 80 SPS, 12.5 ms per conversion and ~50 ms to wake up
+const HX711_READY_TIMEOUT_80SPS_MS: u32 = 100;
+
+/* Output data rate, the RATE pin of the HX711 tied low or high */
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Hx711Rate {
+    Sps10,
+    Sps80,
+}
+
+impl Hx711Rate {
+    pub fn sample_rate(&self) -> SampleRate {
+        match self {
+            Hx711Rate::Sps10 => return SampleRate::from_sps(10),
+            Hx711Rate::Sps80 => return SampleRate::from_sps(80),
+        }
+    }
+
+    /* Longest wait for a conversion, including the settling after power up */
+    pub fn ready_timeout_ms(&self) -> u32 {
+        match self {
+            Hx711Rate::Sps10 => return HX711_READY_TIMEOUT_MS,
+            Hx711Rate::Sps80 => return HX711_READY_TIMEOUT_80SPS_MS,
+        }
+    }
+}
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Hx711Error {
+    NotReady,
+    Timeout,
+    Saturated,
+    /* First conversion after a switch of channel or gain, not valid yet */
+    Settling,
+}
+
+impl Hx711Error {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Hx711Error::NotReady => "HX711 not ready",
+            Hx711Error::Timeout => "HX711 not found",
+            Hx711Error::Saturated => "Load cell overload",
+            Hx711Error::Settling => "HX711 settling",
+        }
+    }
+}
+
+/* Inputs of the HX711, the load cell is on channel A */
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Hx711Channel {
+    A,
+    B,
+}
+
+// Clock pulses after the 24 data bits, they select the input of the next conversion
+const PULSES_A128: u8 = 1;
+const PULSES_B32: u8 = 2;
+const PULSES_A64: u8 = 3;
+
+fn pulses_gain(pulses: u8) -> u8 {
+    match pulses {
+        PULSES_B32 => 32,
+        PULSES_A64 => 64,
+        _ => 128,
+    }
+}
+
+// Auto ranging switches to gain 64 from 7/8 of full scale at gain 128, and
+// back below 3/4 of it, in gain 128 counts. The gap keeps a load near the
+// limit from switching on every reading.
+const RANGE_UP: i32 = HX711_MAX / 8 * 7;
+const RANGE_DOWN: i32 = HX711_MAX / 4 * 3;
+
+/* Offset and calibration of one channel */
+#[derive(Clone, Copy)]
+struct ChannelScale {
+    offset: i32,
+    calibration: LoadCellCalibration,
+}
+
+/*
+ * Generic over the pins and the delay, so it runs against host mocks as well.
+ *
+ * The pulses after each readout select the channel and gain of the next
+ * conversion. That conversion has not settled yet and the driver drops it,
+ * as well as any conversion on a selection no longer wanted, so a switch
+ * costs two conversions. With alternation on, the channels take turns and
+ * every other conversion is a valid reading.
+ *
+ * With auto ranging on, channel A runs at gain 128 and moves to gain 64 when
+ * a reading gets close to the rails, see RANGE_UP and RANGE_DOWN. Readings at
+ * gain 64 are mapped onto the gain 128 scale through the range calibration of
+ * both gains, so offset, calibration and everything downstream keep working
+ * in gain 128 counts and the weight does not jump at the handover. Until
+ * both gains are calibrated the nominal factor of two is used. Conversions
+ * at the old gain keep coming until the new one has settled, a reading
+ * saturated at gain 128 only triggers the switch. Chips on a shared clock
+ * (see LoadCellArray) must not range, they need the same pulses.
+ */
+pub struct HX711<IN, OUT, D> {
+    pd_sck: OUT,
+    dout: IN,
+    delay: D,
+    rate: Hx711Rate,
+    /* Pulses selecting the next conversion */
+    gain: u8,
+    /* Pulses for channel A, gain 128 or 64 */
+    gain_a: u8,
+    alternate: bool,
+    auto_range: bool,
+    /* Auto ranging moved channel A to gain 64 */
+    low_range: bool,
+    /* Selection of the conversion in progress, and whether it is the first one on it */
+    converting: u8,
+    settling: bool,
+    /* Selection the latest reading was converted at */
+    read_selection: u8,
+    channels: [ChannelScale; 2],
+    /* Reading of the empty platform and calibration at gain 128 and at gain 64 */
+    ranges: [ChannelScale; 2],
+    /* Millisecond clock for the ready timeouts, see set_clock */
+    clock: Option<fn() -> u32>,
+}
+
+impl<IN, OUT, D> HX711<IN, OUT, D>
+where
+    IN: InputPin,
+    OUT: OutputPin,
+    D: DelayMs<u16>,
+{
+    pub fn new(dout: IN, pd_sck: OUT, delay: D, gain: u8) -> Self {
+        Self {
+            pd_sck,
+            dout,
+            delay,
+            // RATE tied low, as on most boards
+            rate: Hx711Rate::Sps10,
+            gain,
+            gain_a: if gain == PULSES_A64 { PULSES_A64 } else { PULSES_A128 },
+            alternate: false,
+            auto_range: false,
+            low_range: false,
+            // The chip starts out on channel A at gain 128
+            converting: PULSES_A128,
+            settling: false,
+            read_selection: PULSES_A128,
+            channels: [ChannelScale { offset: 0, calibration: LoadCellCalibration::new() }; 2],
+            ranges: [ChannelScale { offset: 0, calibration: LoadCellCalibration::new() }; 2],
+            clock: None,
+        }
+   }
+
+    /* Gives the pins and the delay back */
+    pub fn release(self) -> (IN, OUT, D) {
+        return (self.dout, self.pd_sck, self.delay);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        // A pin that cannot be read counts as busy
+        return self.dout.is_low().unwrap_or(false);
+    }
+
+    /* The rate the chip is wired for, it sizes the timeouts and read_average_ms */
+    pub fn set_rate(&mut self, rate: Hx711Rate) {
+        self.rate = rate;
+    }
+
+    pub fn rate(&self) -> Hx711Rate {
+        return self.rate;
+    }
+
+    /*
+     * Monotonic millisecond clock for the ready timeouts, e.g. the Timer0
+     * millis() of the firmware. Without one the timeouts count the delays
+     * between the polls, which runs long by whatever the polls take.
+     */
+    pub fn set_clock(&mut self, now_ms: fn() -> u32) {
+        self.clock = Some(now_ms);
+    }
+
+    /* 128 or 64 select channel A at that gain, 32 selects channel B. Ends auto ranging. */
+    pub fn set_gain(&mut self, gain: u8) {
+        match gain {
+            128 => self.gain_a = PULSES_A128,
+            64 => self.gain_a = PULSES_A64,
+            32 => {
+                self.gain = PULSES_B32;
+                return;
+            }
+            _ => return,
+        }
+        self.auto_range = false;
+        self.low_range = false;
+        self.gain = self.gain_a;
+    }
+
+    /* Channel A at gain 128, or 64 for readings near the rails */
+    pub fn set_auto_range(&mut self, auto_range: bool) {
+        self.auto_range = auto_range;
+        self.low_range = false;
+        self.gain_a = PULSES_A128;
+        if self.gain != PULSES_B32 {
+            self.gain = PULSES_A128;
+        }
+    }
+
+    pub fn is_auto_range(&self) -> bool {
+        return self.auto_range;
+    }
+
+    /* Gain channel A is read at, 128 or 64 */
+    pub fn range_gain(&self) -> u8 {
+        return pulses_gain(self.channel_pulses(Hx711Channel::A));
+    }
+
+    /*
+     * Gain the latest reading was converted at, 128 or 64 on channel A and
+     * 32 on channel B. Readings queued before a gain switch still carry the
+     * old one; with auto ranging the value is in gain 128 counts either way.
+     */
+    pub fn reading_gain(&self) -> u8 {
+        return pulses_gain(self.read_selection);
+    }
+
+    /*
+     * Calibration of channel A at gain 128 or 64 for auto ranging, with the
+     * reading of the empty platform it was made at. Unlike the offset it
+     * does not follow the tare.
+     */
+    pub fn set_range_calibration(&mut self, gain: u8, zero: i32, calibration: LoadCellCalibration) {
+        match gain {
+            128 => self.ranges[0] = ChannelScale { offset: zero, calibration },
+            64 => self.ranges[1] = ChannelScale { offset: zero, calibration },
+            _ => {}
+        }
+    }
+
+    /* Reads one channel from the next conversion on, channel A at the gain set last */
+    pub fn select_channel(&mut self, channel: Hx711Channel) {
+        self.alternate = false;
+        self.gain = self.channel_pulses(channel);
+    }
+
+    /* Reads channel A and channel B in turns */
+    pub fn set_alternating(&mut self, alternate: bool) {
+        self.alternate = alternate;
+    }
+
+    pub fn is_alternating(&self) -> bool {
+        return self.alternate;
+    }
+
+    /* Blocking read of the selected channel, with alternation on see `read_any` */
+    pub fn read(&mut self) -> Result<i32, Hx711Error> {
+        return self.read_any().map(|(_, value)| value);
+    }
+
+    /* Next valid reading of `channel`, switches to it if needed */
+    pub fn read_channel(&mut self, channel: Hx711Channel) -> Result<i32, Hx711Error> {
+        self.select_channel(channel);
+        return self.read();
+    }
+
+    /* Blocks for the next valid reading, of whichever channel is due */
+    pub fn read_any(&mut self) -> Result<(Hx711Channel, i32), Hx711Error> {
+        // A switch costs the conversion in progress and the one after it
+        for _ in 0..3 {
+            if !self.wait_ready_timeout(self.rate.ready_timeout_ms(), 1) {
+                return Err(Hx711Error::Timeout);
+            }
+            match self.try_read_channel() {
+                Err(Hx711Error::Settling) => continue,
+                result => return result,
+            }
+        }
+        return Err(Hx711Error::Settling);
+    }
+
+    /* Non-blocking read, fails with NotReady if no conversion is available */
+    pub fn try_read(&mut self) -> Result<i32, Hx711Error> {
+        return self.try_read_channel().map(|(_, value)| value);
+    }
+
+    /*
+     * Non-blocking read that tells the channel as well. Fails with Settling
+     * for a conversion that has to be dropped after a switch.
+     */
+    pub fn try_read_channel(&mut self) -> Result<(Hx711Channel, i32), Hx711Error> {
+        if !self.is_ready() {
+            return Err(Hx711Error::NotReady);
+        }
+        let (channel, valid) = self.begin_readout();
+
+        // Pulse the clock pin 24 times to read the data, MSB first.
+        let mut raw: u32 = 0;
+        for _ in 0..3 {
+            raw = (raw << 8) | self.shift_in(BitOrder::MSB) as u32;
+        }
+
+        let result = self.end_readout(channel, valid, raw);
+
+        // Set the channel and the gain factor for the next reading using the clock pin.
+        for _ in 0..self.gain {
+            self.clock_high();
+            self.clock_low();
+        }
+
+        return result;
+    }
+
+    /* Channel of the conversion about to be read and whether it counts, picks the next selection */
+    pub(crate) fn begin_readout(&mut self) -> (Hx711Channel, bool) {
+        let selection = self.converting;
+        let channel = if selection == PULSES_B32 { Hx711Channel::B } else { Hx711Channel::A };
+        // Without alternation only the selected channel counts
+        let wanted = if self.alternate { self.channel_pulses(channel) } else { self.gain };
+        let valid = !self.settling && selection == wanted;
+
+        // Take turns once the current channel has given a valid reading
+        if self.alternate && valid {
+            let other = if channel == Hx711Channel::A { Hx711Channel::B } else { Hx711Channel::A };
+            self.gain = self.channel_pulses(other);
+        }
+        return (channel, valid);
+    }
+
+    /* Decodes the 24 bits and settles the next selection, its pulses go out right after */
+    pub(crate) fn end_readout(
+        &mut self,
+        channel: Hx711Channel,
+        valid: bool,
+        raw: u32,
+    ) -> Result<(Hx711Channel, i32), Hx711Error> {
+        let selection = self.converting;
+        self.read_selection = selection;
+        let result = if valid { self.decode(channel, selection, raw) } else { Err(Hx711Error::Settling) };
+        self.settling = self.gain != self.converting;
+        self.converting = self.gain;
+        return result;
+    }
+
+    /* A valid conversion, auto ranging may pick the next selection */
+    fn decode(&mut self, channel: Hx711Channel, selection: u8, raw: u32) -> Result<(Hx711Channel, i32), Hx711Error> {
+        // Sign extend the 24-bit two's complement value into an i32
+        let mut value = ((raw << 8) as i32) >> 8;
+        let saturated = value == HX711_MAX || value == HX711_MIN;
+
+        if self.auto_range && channel == Hx711Channel::A {
+            if selection == PULSES_A64 {
+                value = self.to_gain128(value);
+            }
+            let low_range = value.abs() >= if self.low_range { RANGE_DOWN } else { RANGE_UP };
+            if low_range != self.low_range {
+                self.low_range = low_range;
+                if !self.alternate {
+                    self.gain = self.channel_pulses(Hx711Channel::A);
+                }
+            }
+            // Too much for gain 128 only, gain 64 takes over
+            if saturated && selection == PULSES_A128 {
+                return Err(Hx711Error::Settling);
+            }
+        }
+
+        if saturated {
+            return Err(Hx711Error::Saturated);
+        }
+
+        return Ok((channel, value));
+    }
+
+    /* A gain 64 reading in gain 128 counts, through the weight both read it as */
+    fn to_gain128(&self, value: i32) -> i32 {
+        let [high, low] = &self.ranges;
+        if high.calibration.points().is_empty() || low.calibration.points().is_empty() {
+            return value.saturating_mul(2);
+        }
+        let weight = low.calibration.to_weight(value - low.offset);
+        return high.offset.saturating_add(high.calibration.to_counts(weight));
+    }
+
+    /* Clock pulses after the data bits */
+    pub(crate) fn selection_pulses(&self) -> u8 {
+        return self.gain;
+    }
+
+    pub fn wait_ready(&mut self, delay_ms: u16) {
+        // Wait for the chip to become ready.
+        // This is a blocking implementation and will
+        // halt the sketch until a load cell is connected.
+        while !self.is_ready() {
+            // Probably will do no harm on AVR but will feed the Watchdog Timer (WDT) on ESP.
+            // https://github.com/bogde/HX711/issues/73
+            self.delay.delay_ms(delay_ms);
+        }
+    }
+
+    pub fn wait_ready_retry(&mut self, retries: u16, delay_ms: u16) -> bool {
+        // Wait for the chip to become ready by
+        // retrying for a specified amount of attempts.
+        // https://github.com/bogde/HX711/issues/76
+        let mut count = 0;
+        while count < retries {
+            if self.is_ready() {
+                return true;
+            }
+            self.delay.delay_ms(delay_ms);
+            count += 1;
+        }
+        return false;
+    }
+
+    pub fn wait_ready_timeout(&mut self, timeout: u32, delay_ms: u16) -> bool {
+        // Wait for the chip to become ready until timeout.
+        // https://github.com/bogde/HX711/pull/96
+        // Real time when a clock is set, otherwise the delays are counted.
+        let started = self.clock.map(|now_ms| now_ms());
+        let mut waited: u32 = 0;
+        while waited < timeout {
+            if self.is_ready() {
+                return true;
+            }
+            self.delay.delay_ms(delay_ms);
+            waited = match (self.clock, started) {
+                (Some(now_ms), Some(started)) => now_ms().wrapping_sub(started),
+                _ => waited + delay_ms.max(1) as u32,
+            };
+        }
+        return false;
+    }
+
+    /* Blocks for `times` conversions, see average_duration_ms */
+    pub fn read_average(&mut self, times: u8) -> Result<i32, Hx711Error> {
+        let times = times.max(1);
+        // 255 readings of up to 2^23 do not fit in an i32
+        let mut sum: i64 = 0;
+        for _ in 0..times {
+            sum += self.read()? as i64;
+            // Probably will do no harm on AVR but will feed the Watchdog Timer (WDT) on ESP.
+            // https://github.com/bogde/HX711/issues/73
+            self.delay.delay_ms(0);
+        }
+        return Ok((sum / times as i64) as i32);
+    }
+
+    /* Average over the conversions of about `window_ms`, at least one */
+    pub fn read_average_ms(&mut self, window_ms: u16) -> Result<i32, Hx711Error> {
+        let times = self.rate.sample_rate().window(window_ms);
+        return self.read_average(times);
+    }
+
+    /* How long read_average(times) takes at the configured rate, without a switch */
+    pub fn average_duration_ms(&self, times: u8) -> u32 {
+        return self.rate.sample_rate().duration_ms(times.max(1) as u32);
+    }
+
+    pub fn get_value(&mut self, times: u8) -> Result<i32, Hx711Error> {
+        let value = self.read_average(times)?;
+        return Ok(value - self.channels[0].offset);
+    }
+
+    pub fn get_units(&mut self, times: u8) -> Result<Weight, Hx711Error> {
+        let value = self.read_average(times)?;
+        return Ok(self.to_units(value));
+    }
+
+    /* Convert a raw reading taken elsewhere, e.g. by the interrupt sampler */
+    pub fn to_units(&self, raw: i32) -> Weight {
+        return self.channel_units(Hx711Channel::A, raw);
+    }
+
+    /* The same for a reading of either channel, with that channel's offset and calibration */
+    pub fn channel_units(&self, channel: Hx711Channel, raw: i32) -> Weight {
+        let scale = &self.channels[channel as usize];
+        return scale.calibration.to_weight(raw - scale.offset);
+    }
+
+    pub fn tare(&mut self, times: u8) -> Result<(), Hx711Error> {
+        self.channels[0].offset = self.read_average(times)?;
+        return Ok(());
+    }
+
+    /* Single point calibration, `counts` net counts read as `weight`. Replaces the calibration table */
+    pub fn set_scale(&mut self, counts: i32, weight: Weight) {
+        self.channels[0].calibration = LoadCellCalibration::from_scale(counts, weight);
+    }
+
+    /* Counts per kilogram around zero */
+    pub fn get_scale(&self) -> i32 {
+        return self.channels[0].calibration.counts_for(Weight::from_kg(1));
+    }
+
+    /* Offset, scale and calibration without a channel are those of channel A */
+    pub fn set_calibration(&mut self, calibration: LoadCellCalibration) {
+        self.channels[0].calibration = calibration;
+    }
+
+    pub fn get_calibration(&self) -> &LoadCellCalibration {
+        return &self.channels[0].calibration;
+    }
+
+    pub fn set_offset(&mut self, offset: i32) {
+        self.channels[0].offset = offset;
+    }
+
+    pub fn get_offset(&self) -> i32 {
+        return self.channels[0].offset;
+    }
+
+    pub fn set_channel_calibration(&mut self, channel: Hx711Channel, calibration: LoadCellCalibration) {
+        self.channels[channel as usize].calibration = calibration;
+    }
+
+    pub fn get_channel_calibration(&self, channel: Hx711Channel) -> &LoadCellCalibration {
+        return &self.channels[channel as usize].calibration;
+    }
+
+    pub fn set_channel_offset(&mut self, channel: Hx711Channel, offset: i32) {
+        self.channels[channel as usize].offset = offset;
+    }
+
+    pub fn get_channel_offset(&self, channel: Hx711Channel) -> i32 {
+        return self.channels[channel as usize].offset;
+    }
+
+    pub fn power_down(&mut self) {
+        self.clock_low();
+        self.clock_high();
+    }
+
+    /* Waking up resets the chip to channel A at gain 128 */
+    pub fn power_up(&mut self) {
+        self.clock_low();
+        self.converting = PULSES_A128;
+        self.settling = false;
+    }
+
+    fn channel_pulses(&self, channel: Hx711Channel) -> u8 {
+        match channel {
+            Hx711Channel::A if self.low_range => return PULSES_A64,
+            Hx711Channel::A => return self.gain_a,
+            Hx711Channel::B => return PULSES_B32,
+        }
+    }
+
+    // Pin errors are ignored, the conversion result shows when the wiring is broken
+    pub(crate) fn clock_high(&mut self) {
+        let _ = self.pd_sck.set_high();
+    }
+
+    pub(crate) fn clock_low(&mut self) {
+        let _ = self.pd_sck.set_low();
+    }
+
+    pub(crate) fn data_bit(&self) -> u8 {
+        return self.dout.is_high().unwrap_or(false) as u8;
+    }
+
+    fn shift_in(&mut self, bit_order: BitOrder) -> u8 {
+        let mut value: u8 = 0;
+
+        for i in 0..8 {
+            self.clock_high();
+            match bit_order {
+                BitOrder::LSB => value |= self.data_bit() << i,
+                BitOrder::MSB => value |= self.data_bit() << (7 - i),
+            }
+            self.clock_low();
+        }
+        return value;
+    }
+
+}
+
+#[allow(dead_code, clippy::upper_case_acronyms)]
+#[derive(Clone, Copy)]
+enum BitOrder {
+    LSB,
+    MSB
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU32, Ordering};
+    use kitchen_emu::hx711::{Channel, Gain, Hx711Delay, Hx711Dout, Hx711Model, Hx711Sck};
+
+    type TestHx711<'a> = HX711<Hx711Dout<'a>, Hx711Sck<'a>, Hx711Delay<'a>>;
+
+    fn hx711(model: &Hx711Model) -> TestHx711<'_> {
+        return HX711::new(model.dout(), model.pd_sck(), model.delay(), 1);
+    }
+
+    #[test]
+    fn decodes_twos_complement() {
+        let model = Hx711Model::new(80);
+        let mut hx711 = hx711(&model);
+        for counts in [0, 1, -1, 0x12_3456, -0x12_3456, HX711_MAX as i64 - 1, HX711_MIN as i64 + 1] {
+            model.set_input(Channel::A, counts);
+            assert_eq!(hx711.read(), Ok(counts as i32));
+        }
+    }
+
+    #[test]
+    fn saturation() {
+        let model = Hx711Model::new(80);
+        let mut hx711 = hx711(&model);
+        model.set_input(Channel::A, 1 << 24);
+        assert_eq!(hx711.read(), Err(Hx711Error::Saturated));
+        model.set_input(Channel::A, -(1 << 24));
+        assert_eq!(hx711.read(), Err(Hx711Error::Saturated));
+    }
+
+    #[test]
+    fn gain_pulses() {
+        let model = Hx711Model::new(80);
+        let mut hx711 = hx711(&model);
+        model.set_input(Channel::A, 2000);
+        model.set_input(Channel::B, 12_000);
+        assert_eq!(hx711.read(), Ok(2000));
+        assert_eq!(model.selection(), Gain::A128);
+        assert_eq!(hx711.reading_gain(), 128);
+
+        // The conversion in progress is still at gain 128 and the next one has not settled
+        hx711.set_gain(64);
+        model.advance_us(12_500);
+        assert_eq!(hx711.try_read(), Err(Hx711Error::Settling));
+        assert_eq!(model.selection(), Gain::A64);
+        model.advance_us(12_500);
+        assert_eq!(hx711.try_read(), Err(Hx711Error::Settling));
+        model.advance_us(12_500);
+        assert_eq!(hx711.try_read(), Ok(1000));
+        assert_eq!(hx711.reading_gain(), 64);
+
+        // A blocking read skips them
+        hx711.set_gain(32);
+        let before = model.conversions();
+        assert_eq!(hx711.read(), Ok(3000));
+        assert_eq!(model.selection(), Gain::B32);
+        assert_eq!(hx711.reading_gain(), 32);
+        assert_eq!(model.conversions() - before, 3);
+
+        // Anything else keeps the last gain
+        hx711.set_gain(100);
+        hx711.read().unwrap();
+        assert_eq!(model.selection(), Gain::B32);
+    }
+
+    #[test]
+    fn not_ready_between_conversions() {
+        let model = Hx711Model::new(10);
+        let mut hx711 = hx711(&model);
+        assert!(!hx711.is_ready());
+        assert_eq!(hx711.try_read(), Err(Hx711Error::NotReady));
+
+        // Waits out the settling time, then one conversion period per reading
+        hx711.read().unwrap();
+        assert_eq!(model.now_us(), 400_000);
+        assert_eq!(hx711.try_read(), Err(Hx711Error::NotReady));
+        hx711.read().unwrap();
+        assert_eq!(model.now_us(), 500_000);
+    }
+
+    #[test]
+    fn power_down() {
+        let model = Hx711Model::new(10);
+        let mut hx711 = hx711(&model);
+        model.set_input(Channel::A, 300);
+        hx711.set_gain(64);
+        hx711.read().unwrap();
+
+        hx711.power_down();
+        model.advance_us(100);
+        assert!(model.is_powered_down());
+        let before = model.now_us();
+        assert_eq!(hx711.read(), Err(Hx711Error::Timeout));
+        assert_eq!(model.now_us() - before, HX711_READY_TIMEOUT_MS as u64 * 1000);
+
+        // Waking up resets the chip to gain 128, the driver drops that and switches back
+        hx711.power_up();
+        assert!(!model.is_powered_down());
+        assert_eq!(hx711.read(), Ok(150));
+        assert_eq!(model.selection(), Gain::A64);
+    }
+
+    #[test]
+    fn timeout_follows_the_clock() {
+        // Every poll takes 100 ms of real time but only delays 1 ms
+        static NOW_MS: AtomicU32 = AtomicU32::new(0);
+        fn slow_clock() -> u32 {
+            return NOW_MS.fetch_add(100, Ordering::Relaxed);
+        }
+        let model = Hx711Model::new(10);
+        let mut hx711 = hx711(&model);
+        hx711.power_down();
+        model.advance_us(100);
+        hx711.set_clock(slow_clock);
+        let before = model.now_us();
+        assert!(!hx711.wait_ready_timeout(HX711_READY_TIMEOUT_MS, 1));
+        assert_eq!(model.now_us() - before, 5_000);
+    }
+
+    #[test]
+    fn tare_and_units() {
+        let model = Hx711Model::new(80);
+        let mut hx711 = hx711(&model);
+        model.set_input(Channel::A, 84_000);
+        hx711.tare(4).unwrap();
+        assert_eq!(hx711.get_offset(), 84_000);
+
+        hx711.set_scale(420_000, Weight::from_kg(1));
+        model.set_input(Channel::A, 84_000 + 42_000);
+        assert_eq!(hx711.get_value(2), Ok(42_000));
+        assert_eq!(hx711.get_units(1), Ok(Weight::from_grams(100)));
+    }
+
+    #[test]
+    fn windows_and_timeouts_follow_the_rate() {
+        let model = Hx711Model::new(80);
+        let mut hx711 = hx711(&model);
+        model.set_input(Channel::A, 1_000);
+        hx711.read().unwrap();
+
+        hx711.set_rate(Hx711Rate::Sps80);
+        let before = model.conversions();
+        assert_eq!(hx711.read_average_ms(100), Ok(1_000));
+        assert_eq!(model.conversions() - before, 8);
+        assert_eq!(hx711.average_duration_ms(8), 100);
+
+        // A chip that stays in power down is given up on after 100 ms rather than 500 ms
+        hx711.power_down();
+        let start_us = model.now_us();
+        assert_eq!(hx711.read(), Err(Hx711Error::Timeout));
+        assert_eq!((model.now_us() - start_us) / 1000, 100);
+    }
+
+    #[test]
+    fn auto_range_with_hysteresis() {
+        let model = Hx711Model::new(80);
+        let mut hx711 = hx711(&model);
+        hx711.set_auto_range(true);
+        model.set_input(Channel::A, 1_000_000);
+        assert_eq!(hx711.read(), Ok(1_000_000));
+
+        // Close to the rail the reading still counts, the next ones come from gain 64
+        model.set_input(Channel::A, 7_500_000);
+        assert_eq!(hx711.read(), Ok(7_500_000));
+        assert_eq!(hx711.range_gain(), 64);
+        assert_eq!(hx711.read(), Ok(7_500_000));
+        assert_eq!(model.selection(), Gain::A64);
+        model.set_input(Channel::A, 9_000_000);
+        assert_eq!(hx711.read(), Ok(9_000_000));
+
+        // Between the thresholds it stays, below the lower one it goes back
+        model.set_input(Channel::A, 7_000_000);
+        assert_eq!(hx711.read(), Ok(7_000_000));
+        assert_eq!(hx711.range_gain(), 64);
+        model.set_input(Channel::A, 6_000_000);
+        assert_eq!(hx711.read(), Ok(6_000_000));
+        assert_eq!(hx711.read(), Ok(6_000_000));
+        assert_eq!(model.selection(), Gain::A128);
+
+        // A sudden load saturates gain 128, that is not an overload yet
+        model.set_input(Channel::A, 12_000_000);
+        assert_eq!(hx711.read(), Ok(12_000_000));
+        model.set_input(Channel::A, 17_000_000);
+        assert_eq!(hx711.read(), Err(Hx711Error::Saturated));
+
+        hx711.set_gain(128);
+        assert!(!hx711.is_auto_range());
+        assert_eq!(hx711.range_gain(), 128);
+    }
+
+    #[test]
+    fn range_calibration_maps_gain_64() {
+        let model = Hx711Model::new(80);
+        let mut hx711 = hx711(&model);
+        hx711.set_auto_range(true);
+        // Zero at 1000 counts and 800 counts per gram at gain 128; at gain 64 the
+        // zero is not exactly half of that
+        hx711.set_range_calibration(128, 1_000, LoadCellCalibration::from_scale(800_000, Weight::from_kg(1)));
+        hx711.set_range_calibration(64, 700, LoadCellCalibration::from_scale(400_000, Weight::from_kg(1)));
+
+        model.set_input(Channel::A, 7_601_000);
+        hx711.read().unwrap();
+        // 3_800_500 at gain 64 reads as 9.4995 kg
+        assert_eq!(hx711.read(), Ok(7_600_600));
+    }
+
+    #[test]
+    fn channels_take_turns() {
+        let model = Hx711Model::new(80);
+        let mut hx711 = hx711(&model);
+        model.set_input(Channel::A, 84_000 + 42_000);
+        model.set_input(Channel::B, 4 * 5_000);
+        hx711.set_offset(84_000);
+        hx711.set_scale(420_000, Weight::from_kg(1));
+        hx711.set_channel_offset(Hx711Channel::B, 1_000);
+        hx711.set_channel_calibration(Hx711Channel::B, LoadCellCalibration::from_scale(4_000, Weight::from_grams(50)));
+        hx711.set_alternating(true);
+
+        let mut readings = Vec::new();
+        for _ in 0..4 {
+            let (channel, raw) = hx711.read_any().unwrap();
+            readings.push((channel, hx711.channel_units(channel, raw)));
+        }
+        assert_eq!(
+            readings,
+            [
+                (Hx711Channel::A, Weight::from_grams(100)),
+                (Hx711Channel::B, Weight::from_grams(50)),
+                (Hx711Channel::A, Weight::from_grams(100)),
+                (Hx711Channel::B, Weight::from_grams(50)),
+            ]
+        );
+        // Every other conversion is dropped
+        assert_eq!(model.conversions(), 7);
+
+        // Selecting a channel ends the alternation
+        assert_eq!(hx711.read_channel(Hx711Channel::B), Ok(5_000));
+        assert!(!hx711.is_alternating());
+        assert_eq!(hx711.read(), Ok(5_000));
+        assert_eq!(hx711.read_channel(Hx711Channel::A), Ok(126_000));
+    }
+}
@@ -1,10 +1,12 @@
 #![allow(non_snake_case)]
 #![allow(non_upper_case_globals)]
-use crate::{
-    TwiReference,
-    
-};
-use arduino_hal;
+// The full HD44780 command set is kept, and the bit recipes spell out every flag
+#![allow(dead_code)]
+#![allow(clippy::identity_op)]
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::blocking::i2c::{Read, Write};
+#[cfg(feature = "ufmt")]
+use ufmt::uWrite;
 
 const LCD_CLEAR_DISPLAY: u8         = 0x01;
 const LCD_RETURN_HOME: u8           = 0x02;
@@ -72,9 +74,10 @@ const LCD_LINE3: u8                 = 0x14;
 const LCD_LINE4: u8                 = 0x54;
 
 #[repr(C)]
-pub struct LCD<'a> {
+pub struct LCD<I2C, D> {
     address: u8,
-    i2c: &'a TwiReference,
+    i2c: I2C,
+    delay: D,
     function_set: u8,
     entrymode_set: u8,
     display_function: u8,
@@ -83,11 +86,17 @@ pub struct LCD<'a> {
     backlight_val: u8
 }
 
-impl<'a> LCD<'a> {
-    pub fn init(i2c: &'a TwiReference) -> Self {
+/* HD44780 behind a PCF8574 I2C expander, generic over the bus and the delay */
+impl<I2C, D> LCD<I2C, D>
+where
+    I2C: Write + Read,
+    D: DelayUs<u16> + DelayMs<u16>,
+{
+    pub fn init(i2c: I2C, delay: D) -> Self {
         let mut s = Self {
             address: LCD_PCF8574_ADDR,
             i2c,
+            delay,
             function_set: 0x00,
             entrymode_set: 0x00,
             display_function: 0x00,
@@ -96,25 +105,24 @@ impl<'a> LCD<'a> {
             backlight_val: Bl
         };
 
-        arduino_hal::delay_ms(50);
+        s.delay.delay_ms(50);
 
-        let mut twi = s.i2c.borrow_mut();
         let lcd_init: u8 = ((0b00000000 | En) & !Rs) & (!Rw);
-        twi.write_reg(s.address, &[lcd_init]);
-        
-        arduino_hal::delay_us(100);
+        s.write_pcf8574(lcd_init);
+
+        s.delay.delay_us(100);
 
         s.write_4_bits(LCD_8BIT_INIT);
-        arduino_hal::delay_us(4500);
+        s.delay.delay_us(4500);
 
         s.write_4_bits(LCD_8BIT_INIT);
-        arduino_hal::delay_us(150);
+        s.delay.delay_us(150);
 
         s.write_4_bits(LCD_8BIT_INIT);
-        arduino_hal::delay_us(150);
+        s.delay.delay_us(150);
 
         s.write_4_bits(LCD_4BIT_INIT);
-        arduino_hal::delay_us(150);
+        s.delay.delay_us(150);
 
         s.function_set = LCD_INTF4BITS | LCD_TWO_LINES | LCD_FONT_5_7;
         s.command_write(LCD_FUNCTION_SET | s.function_set);
@@ -136,6 +144,10 @@ impl<'a> LCD<'a> {
 
         return s;
     }
+    /* Gives the bus and the delay back */
+    pub fn release(self) -> (I2C, D) {
+        return (self.i2c, self.delay);
+    }
 
     /* High level commands */
     pub fn write_char(&mut self, message: u8) {
@@ -143,24 +155,22 @@ impl<'a> LCD<'a> {
     }
 
     pub fn write_str(&mut self, message: &str) {
-        message.as_bytes().into_iter().for_each(|b| self.data_write(*b));
+        message.as_bytes().iter().for_each(|b| self.data_write(*b));
     }
 
     pub fn clear(&mut self) {
         self.command_write(LCD_CLEAR_DISPLAY);
-        arduino_hal::delay_ms(30);
+        self.delay.delay_ms(30);
     }
 
     pub fn home(&mut self) {
         self.command_write(LCD_RETURN_HOME);
-        arduino_hal::delay_ms(30);
+        self.delay.delay_ms(30);
     }
 
     pub fn set_cursor(&mut self, col: u8, row: u8) {
         let row_offsets: [u8; 4] = [LCD_LINE1, LCD_LINE2, LCD_LINE3, LCD_LINE4];
-        if row >= self.num_lines {
-            let row = self.num_lines - 1;
-        }
+        let row = row.min(self.num_lines - 1);
 
         self.command_write(LCD_DD_RAM_ADDRESS | (col + row_offsets[row as usize]));
     }
@@ -242,20 +252,18 @@ impl<'a> LCD<'a> {
     pub fn createChar(&mut self, location: u8, charmap: &[u8]) {
         let location = location & 0x7; // we only have 8 locations 0-7
         self.command_write(LCD_CG_RAM_ADDRESS | (location << 3));
-        charmap.into_iter().for_each(|b| self.data_write(*b));
+        charmap.iter().for_each(|b| self.data_write(*b));
     }
 
     // Turn the (optional) backlight off/on
  	pub fn no_backlight(&mut self){
         self.backlight_val &= !Bl;
-        let dummy_data = self.read_pcf8574();
-	    self.write_pcf8574(dummy_data);  // Dummy write to LCD, only led control bit is of interest
+	    self.write_pcf8574(0);  // Dummy write with En low, only the led control bit is of interest
     }
 
  	pub fn backlight(&mut self){
         self.backlight_val |= Bl;
-        let dummy_data = self.read_pcf8574();
-        self.write_pcf8574(dummy_data);  // Dummy write to LCD, only led control bit is of interest
+        self.write_pcf8574(0);  // Dummy write with En low, only the led control bit is of interest
     }
 
 
@@ -320,14 +328,11 @@ impl<'a> LCD<'a> {
 
 // read either command or data
     fn receive(&mut self, RsMode: u8) -> u8 {
-        let highnib: u8;
-        let lownib: u8;
-
         self.write_pcf8574(LCD_PCF8574_WEAK_PU | (En & !En) | RsMode); // Set P7..P4 = 1, En = 0, RnW = 0, Rs = XX
-        highnib = self.read_4_bits(LCD_PCF8574_WEAK_PU | En | RsMode);
-        lownib = self.read_4_bits(LCD_PCF8574_WEAK_PU | En | RsMode);
-        self.write_pcf8574((LCD_PCF8574_WEAK_PU & !LCD_PCF8574_WEAK_PU) | En | RsMode); // Set P7..P4 = 1, En = 1, RnW = 0, Rs = XX
-        return ((highnib & 0xF0) | ((lownib & 0xF0) >> 4));
+        let highnib = self.read_4_bits(LCD_PCF8574_WEAK_PU | En | RsMode);
+        let lownib = self.read_4_bits(LCD_PCF8574_WEAK_PU | En | RsMode);
+        self.write_pcf8574((LCD_PCF8574_WEAK_PU & !LCD_PCF8574_WEAK_PU) | (En & !En) | RsMode); // Set P7..P4 = 0, En = 0, RnW = 0, Rs = XX
+        return (highnib & 0xF0) | ((lownib & 0xF0) >> 4);
     }
 
 	fn write_4_bits(&mut self, nibEnRsMode: u8) {
@@ -337,43 +342,249 @@ impl<'a> LCD<'a> {
 
 
     fn read_4_bits(&mut self, rs_en_mode: u8) -> u8 {
-        let b: u8;
         self.pulse_enable_pos(rs_en_mode | Rw);
-        b = self.read_pcf8574(); // Read the data from the LCD just after the rising edge. NOT WELL DOCUMENTED!
+        let b = self.read_pcf8574(); // Read the data from the LCD just after the rising edge. NOT WELL DOCUMENTED!
         self.pulse_enable_neg(rs_en_mode | Rw);
         return b;
     }
 
 	fn pulse_enable_neg(&mut self, data: u8) {
 	    self.write_pcf8574(data | En);	// En high
-        arduino_hal::delay_us(1);		// enable pulse must be >450ns
+        self.delay.delay_us(1);		// enable pulse must be >450ns
 
         self.write_pcf8574(data & !En);	// En low
-        arduino_hal::delay_us(50);		// commands need > 37us to settle
+        self.delay.delay_us(50);		// commands need > 37us to settle
     }
 
 	fn pulse_enable_pos(&mut self, data: u8) {
         self.write_pcf8574(data & !En);	// En low
-        arduino_hal::delay_us(1);		// enable pulse must be >450ns
+        self.delay.delay_us(1);		// enable pulse must be >450ns
     
         self.write_pcf8574(data | En);	// En high
-        arduino_hal::delay_us(50);		// commands need > 37us to settle
+        self.delay.delay_us(50);		// commands need > 37us to settle
     }
 
 
 	fn write_pcf8574(&mut self, value: u8) {
-        if let Ok(mut twi) = self.i2c.try_borrow_mut() {
-            twi.write_reg(self.address, &[value | self.backlight_val]);
-        }
+        let _ = self.i2c.write(self.address, &[value | self.backlight_val]);
     }
 
+    /* The PCF8574 has no registers, a plain read returns the port */
     fn read_pcf8574(&mut self) -> u8 {
-        if let Ok(mut twi) = self.i2c.try_borrow_mut() {
-            let mut result = [0x00];
-            twi.read_reg(self.address, 0x00, &mut result);
-            return result[0];
-        } else {
+        let mut result = [0xFF];
+        if self.i2c.read(self.address, &mut result).is_err() {
             return 0xFF;
         }
+        return result[0];
+    }
+}
+
+/* Lets uwrite! print straight to the display, at the current cursor */
+#[cfg(feature = "ufmt")]
+impl<I2C, D> uWrite for LCD<I2C, D>
+where
+    I2C: Write + Read,
+    D: DelayUs<u16> + DelayMs<u16>,
+{
+    type Error = core::convert::Infallible;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        s.as_bytes().iter().for_each(|b| self.data_write(*b));
+        Ok(())
+    }
+
+    /* The character ROM is ASCII below 0x80, anything else is shown as '?' */
+    fn write_char(&mut self, c: char) -> Result<(), Self::Error> {
+        self.data_write(if c.is_ascii() { c as u8 } else { b'?' });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::shared_bus::SharedI2c;
+    use core::cell::RefCell;
+    use kitchen_emu::delay::VirtualDelay;
+    use kitchen_emu::hd44780::LcdBackpack;
+
+    fn lcd(backpack: &RefCell<LcdBackpack>) -> LCD<SharedI2c<'_, LcdBackpack>, VirtualDelay> {
+        return LCD::init(SharedI2c::new(backpack), VirtualDelay::new());
+    }
+
+    fn screen(backpack: &RefCell<LcdBackpack>) -> Vec<String> {
+        let backpack = backpack.borrow();
+        return (0..4).map(|row| backpack.lcd().row_text(row)).collect();
+    }
+
+    #[test]
+    fn init() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        lcd(&backpack);
+
+        let backpack = backpack.borrow();
+        let controller = backpack.lcd();
+        assert!(controller.is_four_bit());
+        assert!(controller.is_two_lines());
+        assert!(controller.is_display_on());
+        assert!(!controller.is_cursor_on());
+        assert!(!controller.is_blink_on());
+        assert!(controller.is_increment());
+        assert!(!controller.is_autoscroll());
+        assert_eq!(controller.shift(), 0);
+        assert_eq!(controller.cursor(), Some((0, 0)));
+        assert!(backpack.is_backlight_on());
+        assert!((0..4).all(|row| controller.row_text(row) == " ".repeat(20)));
+    }
+
+    #[test]
+    fn set_cursor_and_write_str() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        let mut lcd = lcd(&backpack);
+        for (row, text) in ["first", "second", "third", "fourth"].iter().enumerate() {
+            lcd.set_cursor(row as u8 * 2, row as u8);
+            lcd.write_str(text);
+        }
+        lcd.set_cursor(15, 3);
+        lcd.write_char(b'!');
+        assert_eq!(
+            screen(&backpack),
+            [
+                "first               ",
+                "  second            ",
+                "    third           ",
+                "      fourth   !    ",
+            ]
+        );
+        assert_eq!(backpack.borrow().lcd().cursor(), Some((16, 3)));
+
+        // Rows past the last one land on it
+        lcd.set_cursor(0, 7);
+        assert_eq!(backpack.borrow().lcd().cursor(), Some((0, 3)));
+    }
+
+    #[test]
+    fn long_text_continues_two_rows_down() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        let mut lcd = lcd(&backpack);
+        lcd.set_cursor(16, 0);
+        lcd.write_str("wrapped");
+        assert_eq!(screen(&backpack)[0], "                wrap");
+        assert_eq!(screen(&backpack)[2], "ped                 ");
+
+        lcd.clear();
+        assert!(screen(&backpack).iter().all(|row| row.trim().is_empty()));
+        assert_eq!(backpack.borrow().lcd().cursor(), Some((0, 0)));
+    }
+
+    #[test]
+    fn create_char() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        let mut lcd = lcd(&backpack);
+        let degree = [0b01100, 0b10010, 0b10010, 0b01100, 0, 0, 0, 0];
+        lcd.createChar(9, &degree);
+        lcd.set_cursor(3, 1);
+        lcd.write_char(1);
+        lcd.write_str("C");
+
+        let backpack = backpack.borrow();
+        assert_eq!(backpack.lcd().glyph(1), degree);
+        assert_eq!(backpack.lcd().glyph(0), [0; 8]);
+        assert_eq!(backpack.lcd().row(1)[3..5], [1, b'C']);
+    }
+
+    #[test]
+    fn display_controls() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        let mut lcd = lcd(&backpack);
+        lcd.cursor_on();
+        lcd.blink_on();
+        lcd.display_off();
+        {
+            let backpack = backpack.borrow();
+            assert!(!backpack.lcd().is_display_on());
+            assert!(backpack.lcd().is_cursor_on());
+            assert!(backpack.lcd().is_blink_on());
+        }
+        lcd.display_on();
+        lcd.cursor_off();
+        lcd.blink_off();
+        let backpack = backpack.borrow();
+        assert!(backpack.lcd().is_display_on());
+        assert!(!backpack.lcd().is_cursor_on());
+        assert!(!backpack.lcd().is_blink_on());
+    }
+
+    #[test]
+    fn scrolling() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        let mut lcd = lcd(&backpack);
+        lcd.write_str("scroll");
+        lcd.scroll_display_left();
+        lcd.scroll_display_left();
+        assert_eq!(screen(&backpack)[0], "roll                ");
+        lcd.scroll_display_right();
+        lcd.scroll_display_right();
+        lcd.scroll_display_right();
+        assert_eq!(screen(&backpack)[0], " scroll             ");
+
+        lcd.home();
+        assert_eq!(screen(&backpack)[0], "scroll              ");
+    }
+
+    #[test]
+    fn entry_modes() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        let mut lcd = lcd(&backpack);
+        lcd.set_cursor(9, 0);
+        lcd.right_to_left();
+        lcd.write_str("abc");
+        assert_eq!(screen(&backpack)[0], "       cba          ");
+
+        lcd.clear();
+        lcd.left_to_right();
+        lcd.set_cursor(19, 1);
+        lcd.autoscroll();
+        lcd.write_str("12");
+        // The text moves left under a cursor that stays put
+        assert_eq!(screen(&backpack)[1], "                 12 ");
+        assert_eq!(backpack.borrow().lcd().shift(), 2);
+        assert_eq!(backpack.borrow().lcd().cursor(), Some((19, 1)));
+        lcd.no_autoscroll();
+        assert!(!backpack.borrow().lcd().is_autoscroll());
+    }
+
+    #[test]
+    fn backlight() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        let mut lcd = lcd(&backpack);
+        lcd.write_str("lit");
+        lcd.no_backlight();
+        assert!(!backpack.borrow().is_backlight_on());
+        lcd.write_str(" dark");
+        assert!(!backpack.borrow().is_backlight_on());
+        lcd.backlight();
+        assert!(backpack.borrow().is_backlight_on());
+        assert_eq!(screen(&backpack)[0], "lit dark            ");
+    }
+
+    #[test]
+    fn reads_back() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        let mut lcd = lcd(&backpack);
+        lcd.set_cursor(2, 1);
+        lcd.write_str("xy");
+        assert_eq!(lcd.address_counter(), LCD_LINE2 + 4);
+        assert_eq!(lcd.busy(), 0);
+        assert_eq!(lcd.read_DDRam(LCD_LINE2 + 2), b'x');
+        assert_eq!(lcd.data_read(), b'y');
+
+        // Reads leave the nibble pairing intact for the next write
+        lcd.set_cursor(0, 3);
+        lcd.write_str("ok");
+        assert_eq!(screen(&backpack)[3], "ok                  ");
+
+        lcd.createChar(2, &[0x1F; 8]);
+        assert_eq!(lcd.read_CGRam(2 << 3), 0x1F);
     }
 }
@@ -0,0 +1,309 @@
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+use super::hx711::{Hx711Channel, Hx711Error, HX711, HX711_READY_TIMEOUT_MS};
+use crate::weighing::corner_correction::{self, UNITY_PPM};
+use crate::weighing::weight::Weight;
+
+/*
+ * Platform on several load cells, one HX711 per cell.
+ *
+ * Every cell has its own DOUT pin, offset and calibration, set through
+ * `cell_mut`. The PD_SCK pins are either separate, and the chips are read
+ * one after the other, or one line clocks all of them (see SharedPin), and
+ * the chips are read together: each clock edge shifts out one bit of every
+ * chip. A shared clock needs all chips ready before the readout and the
+ * same selection pulses for all, so the cells stay on channel A at the gain
+ * set through the array, without auto ranging.
+ *
+ * The weight is the sum of the calibrated cell weights, each scaled by its
+ * corner-load correction factor, see weighing::corner_correction.
+ */
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SckWiring {
+    Separate,
+    Shared,
+}
+
+pub struct LoadCellArray<IN, OUT, D, const N: usize> {
+    cells: [HX711<IN, OUT, D>; N],
+    wiring: SckWiring,
+    corrections: [i32; N],
+    /* Separate clocks: latest reading of each cell, until all have one */
+    pending: [Option<i32>; N],
+}
+
+impl<IN, OUT, D, const N: usize> LoadCellArray<IN, OUT, D, N>
+where
+    IN: InputPin,
+    OUT: OutputPin,
+    D: DelayMs<u16>,
+{
+    pub fn new(cells: [HX711<IN, OUT, D>; N], wiring: SckWiring) -> Self {
+        Self {
+            cells,
+            wiring,
+            corrections: [UNITY_PPM; N],
+            pending: [None; N],
+        }
+    }
+
+    /* Gives the drivers back */
+    pub fn release(self) -> [HX711<IN, OUT, D>; N] {
+        return self.cells;
+    }
+
+    pub fn wiring(&self) -> SckWiring {
+        return self.wiring;
+    }
+
+    pub fn cell(&self, index: usize) -> &HX711<IN, OUT, D> {
+        return &self.cells[index];
+    }
+
+    /* For the offset and calibration of one cell */
+    pub fn cell_mut(&mut self, index: usize) -> &mut HX711<IN, OUT, D> {
+        return &mut self.cells[index];
+    }
+
+    /* 128 or 64, channel A of every cell. Readings from before the switch are dropped. */
+    pub fn set_gain(&mut self, gain: u8) {
+        if gain == 128 || gain == 64 {
+            self.cells.iter_mut().for_each(|cell| {
+                cell.set_gain(gain);
+                cell.select_channel(Hx711Channel::A);
+            });
+            self.pending = [None; N];
+        }
+    }
+
+    /* Corner-load correction factors in ppm, from the corner-load test */
+    pub fn set_corrections(&mut self, corrections: [i32; N]) {
+        self.corrections = corrections;
+    }
+
+    pub fn corrections(&self) -> &[i32; N] {
+        return &self.corrections;
+    }
+
+    pub fn is_ready(&self) -> bool {
+        return self.cells.iter().all(|cell| cell.is_ready());
+    }
+
+    /*
+     * Non-blocking read of all cells. Gives the raw readings once every cell
+     * has a new one, None until then.
+     */
+    pub fn try_read_cells(&mut self) -> Result<Option<[i32; N]>, Hx711Error> {
+        if self.wiring == SckWiring::Shared {
+            return self.read_together();
+        }
+        for (cell, pending) in self.cells.iter_mut().zip(self.pending.iter_mut()) {
+            match cell.try_read() {
+                Ok(raw) => *pending = Some(raw),
+                Err(Hx711Error::NotReady | Hx711Error::Settling) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if self.pending.iter().any(|p| p.is_none()) {
+            return Ok(None);
+        }
+        let mut raws = [0; N];
+        for (raw, pending) in raws.iter_mut().zip(self.pending.iter_mut()) {
+            *raw = pending.take().unwrap_or(0);
+        }
+        return Ok(Some(raws));
+    }
+
+    /* Blocks until every cell has a new reading */
+    pub fn read_cells(&mut self) -> Result<[i32; N], Hx711Error> {
+        // Settling conversions after a gain switch cost up to two rounds
+        for _ in 0..3 {
+            for cell in self.cells.iter_mut() {
+                if !cell.wait_ready_timeout(HX711_READY_TIMEOUT_MS, 1) {
+                    return Err(Hx711Error::Timeout);
+                }
+            }
+            if let Some(raws) = self.try_read_cells()? {
+                return Ok(raws);
+            }
+        }
+        return Err(Hx711Error::Settling);
+    }
+
+    /* Calibrated weight of every cell, without the corner-load correction */
+    pub fn cell_weights(&self, raws: &[i32; N]) -> [Weight; N] {
+        let mut weights = [Weight::ZERO; N];
+        for (i, weight) in weights.iter_mut().enumerate() {
+            *weight = self.cells[i].to_units(raws[i]);
+        }
+        return weights;
+    }
+
+    /* Weight on the platform for a set of raw readings */
+    pub fn to_units(&self, raws: &[i32; N]) -> Weight {
+        return corner_correction::corrected_total(&self.cell_weights(raws), &self.corrections);
+    }
+
+    pub fn read(&mut self) -> Result<Weight, Hx711Error> {
+        let raws = self.read_cells()?;
+        return Ok(self.to_units(&raws));
+    }
+
+    /* Zeroes every cell on the average of `times` readings */
+    pub fn tare(&mut self, times: u8) -> Result<(), Hx711Error> {
+        let times = times.max(1);
+        let mut sums = [0i64; N];
+        for _ in 0..times {
+            let raws = self.read_cells()?;
+            sums.iter_mut().zip(raws.iter()).for_each(|(sum, &raw)| *sum += raw as i64);
+        }
+        for (cell, sum) in self.cells.iter_mut().zip(sums.iter()) {
+            cell.set_offset((sum / times as i64) as i32);
+        }
+        return Ok(());
+    }
+
+    /* One readout of all chips on the shared clock line, clocked through the first driver */
+    fn read_together(&mut self) -> Result<Option<[i32; N]>, Hx711Error> {
+        if N == 0 || !self.is_ready() {
+            return Ok(None);
+        }
+        let mut begun = [(Hx711Channel::A, false); N];
+        for (cell, begin) in self.cells.iter_mut().zip(begun.iter_mut()) {
+            *begin = cell.begin_readout();
+        }
+
+        let mut bits = [0u32; N];
+        for _ in 0..24 {
+            self.cells[0].clock_high();
+            for (cell, word) in self.cells.iter().zip(bits.iter_mut()) {
+                *word = (*word << 1) | cell.data_bit() as u32;
+            }
+            self.cells[0].clock_low();
+        }
+
+        // Every chip has to do its bookkeeping, the first error wins
+        let mut raws = [0; N];
+        let mut settling = false;
+        let mut error = None;
+        for (((cell, (channel, valid)), word), raw) in self.cells.iter_mut().zip(begun).zip(bits).zip(raws.iter_mut()) {
+            match cell.end_readout(channel, valid, word) {
+                Ok((_, value)) => *raw = value,
+                Err(Hx711Error::Settling) => settling = true,
+                Err(e) => error = error.or(Some(e)),
+            }
+        }
+        for _ in 0..self.cells[0].selection_pulses() {
+            self.cells[0].clock_high();
+            self.cells[0].clock_low();
+        }
+        if let Some(e) = error {
+            return Err(e);
+        }
+        return Ok(if settling { None } else { Some(raws) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::shared_bus::SharedPin;
+    use core::cell::RefCell;
+    use kitchen_emu::hx711::{Channel, Gain, Hx711Model, Hx711SckGroup};
+
+    fn calibrate<IN: InputPin, OUT: OutputPin, D: DelayMs<u16>, const N: usize>(
+        array: &mut LoadCellArray<IN, OUT, D, N>,
+    ) {
+        // 100 counts per gram on every cell
+        for i in 0..N {
+            array.cell_mut(i).set_scale(100_000, Weight::from_grams(1000));
+        }
+    }
+
+    #[test]
+    fn separate_clocks() {
+        let models = [Hx711Model::new(80), Hx711Model::new(80)];
+        let cells = [0, 1].map(|i| HX711::new(models[i].dout(), models[i].pd_sck(), models[i].delay(), 1));
+        let mut array = LoadCellArray::new(cells, SckWiring::Separate);
+        calibrate(&mut array);
+
+        models[0].set_input(Channel::A, 5_000);
+        models[1].set_input(Channel::A, -3_000);
+        array.tare(4).unwrap();
+        assert_eq!(array.cell(0).get_offset(), 5_000);
+        assert_eq!(array.cell(1).get_offset(), -3_000);
+
+        models[0].set_input(Channel::A, 5_000 + 30_000);
+        models[1].set_input(Channel::A, -3_000 + 20_000);
+        assert_eq!(array.read(), Ok(Weight::from_grams(500)));
+
+        array.set_corrections([1_000_000, 1_100_000]);
+        assert_eq!(array.read(), Ok(Weight::from_grams(520)));
+    }
+
+    #[test]
+    fn partial_readings_wait_for_every_cell() {
+        let models = [Hx711Model::new(80), Hx711Model::new(10)];
+        let cells = [0, 1].map(|i| HX711::new(models[i].dout(), models[i].pd_sck(), models[i].delay(), 1));
+        let mut array = LoadCellArray::new(cells, SckWiring::Separate);
+        models[0].set_input(Channel::A, 100);
+        models[1].set_input(Channel::A, 200);
+
+        models[0].advance_us(60_000);
+        assert_eq!(array.try_read_cells(), Ok(None));
+        models[1].advance_us(450_000);
+        assert_eq!(array.try_read_cells(), Ok(Some([100, 200])));
+        assert_eq!(array.try_read_cells(), Ok(None));
+    }
+
+    #[test]
+    fn gain_switch_drops_pending_readings() {
+        let models = [Hx711Model::new(80), Hx711Model::new(80)];
+        let cells = [0, 1].map(|i| HX711::new(models[i].dout(), models[i].pd_sck(), models[i].delay(), 1));
+        let mut array = LoadCellArray::new(cells, SckWiring::Separate);
+        models[0].set_input(Channel::A, 100);
+        models[1].set_input(Channel::A, 200);
+
+        // The first cell has a gain 128 reading waiting for the second one
+        models[0].advance_us(60_000);
+        assert_eq!(array.try_read_cells(), Ok(None));
+        array.set_gain(64);
+        for _ in 0..4 {
+            models[1].advance_us(20_000);
+            assert_eq!(array.try_read_cells(), Ok(None));
+        }
+        for _ in 0..4 {
+            models[0].advance_us(20_000);
+            models[1].advance_us(20_000);
+            if let Some(raws) = array.try_read_cells().unwrap() {
+                assert_eq!(raws, [50, 100]);
+                return;
+            }
+        }
+        panic!("no reading at gain 64");
+    }
+
+    #[test]
+    fn shared_clock() {
+        let models = [Hx711Model::new(80), Hx711Model::new(80), Hx711Model::new(80), Hx711Model::new(80)];
+        let sck = RefCell::new(Hx711SckGroup::new(&[&models[0], &models[1], &models[2], &models[3]]));
+        let cells = [0, 1, 2, 3].map(|i| HX711::new(models[i].dout(), SharedPin::new(&sck), models[i].delay(), 1));
+        let mut array = LoadCellArray::new(cells, SckWiring::Shared);
+        calibrate(&mut array);
+
+        for (i, model) in models.iter().enumerate() {
+            model.set_input(Channel::A, 10_000 * (i as i64 + 1));
+        }
+        assert_eq!(array.read_cells(), Ok([10_000, 20_000, 30_000, 40_000]));
+        assert_eq!(array.read(), Ok(Weight::from_grams(1000)));
+        assert!(models.iter().all(|model| model.conversions() == 2 && model.selection() == Gain::A128));
+
+        // Every chip gets the new selection, the unsettled conversion is skipped
+        array.set_gain(64);
+        assert_eq!(array.read_cells(), Ok([5_000, 10_000, 15_000, 20_000]));
+        assert!(models.iter().all(|model| model.selection() == Gain::A64));
+    }
+}
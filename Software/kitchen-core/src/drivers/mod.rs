@@ -0,0 +1,7 @@
+/* Drivers over embedded-hal traits, the firmware supplies the pins and buses */
+pub mod button;
+pub mod hx711;
+pub mod lcd;
+pub mod load_cell_array;
+pub mod pca9685;
+pub mod shared_bus;
@@ -1,41 +1,34 @@
-use super::twi_conroller::*;
+use embedded_hal::blocking::i2c::Write;
 
+/* PWM driver, generic over the I2C bus. Share a bus with hardware::shared_bus::SharedI2c */
 #[repr(C)]
-pub struct PCA9685<'a> {
+pub struct PCA9685<I2C> {
     address: u8,
-    i2c: &'a TwiReference,
+    i2c: I2C,
 }
 
-impl<'a> PCA9685<'a> {
-    pub fn new(address: u8, i2c: &'a TwiReference) -> Self {
+impl<I2C: Write> PCA9685<I2C> {
+    pub fn new(address: u8, i2c: I2C) -> Self {
         PCA9685 {
-            address: address,
-            i2c: i2c,
+            address,
+            i2c,
         }
     }
 
-    /* Initializes to default settings */
-    pub fn init(&mut self) -> Result<(), TwiError> {
-        let mut i2c = self.i2c.borrow_mut();
+    /* Initializes to default settings, fails if the device does not answer */
+    pub fn init(&mut self) -> Result<(), I2C::Error> {
         let cfg_packet = [PCA9685_Register::MODE1 as u8, (1 << 5) | (1 << 4)];
         let freq_packet = [
             PCA9685_Register::PRE_SCALE as u8,
             0x03, /* Set to max PWM frequency */
         ];
 
-        if !i2c.ping_device(self.address) {
-            drop(i2c);
-            return Err(TwiError::NoConnection);
-        }
-
-        i2c.write_reg(self.address, &cfg_packet);
-        i2c.write_reg(self.address, &freq_packet);
-        drop(i2c);
+        self.i2c.write(self.address, &cfg_packet)?;
+        self.i2c.write(self.address, &freq_packet)?;
         Ok(())
     }
 
-    pub fn set_motor_speed(&mut self, led_num: u8, high_time: u16, low_time: u16) {
-        let mut i2c = self.i2c.borrow_mut();
+    pub fn set_motor_speed(&mut self, led_num: u8, high_time: u16, low_time: u16) -> Result<(), I2C::Error> {
         let packet: [u8; 5] = [
             PCA9685_Register::LED0_ON_L as u8 + 4 * led_num,
             (high_time & 0xFF) as u8,
@@ -43,13 +36,17 @@ impl<'a> PCA9685<'a> {
             (low_time & 0xFF) as u8,
             (low_time >> 8) as u8,
         ];
-        i2c.write_reg(self.address, &packet);
-        drop(i2c);
+        self.i2c.write(self.address, &packet)
+    }
+
+    pub fn release(self) -> I2C {
+        return self.i2c;
     }
 }
 
 #[allow(non_camel_case_types)]
-#[derive(ufmt::derive::uDebug, Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 #[repr(u8)]
 pub enum PCA9685_Register {
     MODE1 = 0x00,
@@ -0,0 +1,70 @@
+use core::cell::RefCell;
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+use embedded_hal::digital::v2::OutputPin;
+
+/*
+ * Handle to an I2C bus shared by several drivers.
+ *
+ * Every driver takes its own SharedI2c and the bus is only borrowed for the
+ * length of one transfer, so the drivers can be interleaved freely.
+ */
+pub struct SharedI2c<'a, I2C> {
+    bus: &'a RefCell<I2C>,
+}
+
+impl<'a, I2C> SharedI2c<'a, I2C> {
+    pub fn new(bus: &'a RefCell<I2C>) -> Self {
+        SharedI2c { bus }
+    }
+}
+
+impl<'a, I2C: Write> Write for SharedI2c<'a, I2C> {
+    type Error = I2C::Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.bus.borrow_mut().write(address, bytes)
+    }
+}
+
+impl<'a, I2C: Read> Read for SharedI2c<'a, I2C> {
+    type Error = I2C::Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.bus.borrow_mut().read(address, buffer)
+    }
+}
+
+impl<'a, I2C: WriteRead> WriteRead for SharedI2c<'a, I2C> {
+    type Error = I2C::Error;
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.bus.borrow_mut().write_read(address, bytes, buffer)
+    }
+}
+
+/*
+ * Handle to an output pin wired to several chips, e.g. one PD_SCK line
+ * clocking more than one HX711. Setting it through any handle sets it for
+ * all of them.
+ */
+pub struct SharedPin<'a, P> {
+    pin: &'a RefCell<P>,
+}
+
+impl<'a, P> SharedPin<'a, P> {
+    pub fn new(pin: &'a RefCell<P>) -> Self {
+        SharedPin { pin }
+    }
+}
+
+impl<'a, P: OutputPin> OutputPin for SharedPin<'a, P> {
+    type Error = P::Error;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.pin.borrow_mut().set_low()
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.pin.borrow_mut().set_high()
+    }
+}
@@ -0,0 +1,15 @@
+#![cfg_attr(not(test), no_std)]
+// Explicit returns are the house style
+#![allow(clippy::needless_return)]
+
+/*
+ * Hardware independent part of the kitchen scale: weighing, settings, the
+ * user interface state and drivers written against embedded-hal. Builds for
+ * the ATmega328P and for the host, where `cargo test` runs.
+ */
+pub mod app;
+pub mod drivers;
+pub mod settings;
+pub mod ui;
+pub mod utils;
+pub mod weighing;
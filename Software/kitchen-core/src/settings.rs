@@ -0,0 +1,581 @@
+use crate::utils::crc::crc16;
+use crate::weighing::calibration::{LoadCellCalibration, CALIBRATION_POINTS};
+use crate::weighing::corner_correction::{MAX_FACTOR_PPM, MIN_FACTOR_PPM, PLATFORM_CELLS, UNITY_PPM};
+use crate::weighing::graduation::Division;
+use crate::weighing::overload::{LoadLimits, DEFAULT_LIMITS};
+use crate::weighing::temperature::{Drift, TemperatureCompensation};
+use crate::weighing::units::{Density, Unit};
+use crate::weighing::weight::Weight;
+
+/*
+ * Persistent settings record, stored at the start of the EEPROM:
+ *
+ *   magic (u16) | version (u8) | length (u8) | payload (length bytes) | crc (u16)
+ *
+ * The CRC covers version, length and payload. All values are little endian.
+ * Layouts only ever grow by appending fields at the end of the payload, so a
+ * record written by an older firmware is migrated by decoding the fields it
+ * has and leaving the rest at their defaults. Where the encoding of a field
+ * changed, the decoder picks the old encoding based on the version. Anything
+ * that fails to load (blank EEPROM, bad CRC, newer layout) falls back to the
+ * defaults.
+ *
+ * Version history:
+ *   1: offset, calibration points with f32 grams, unit, auto off, backlight
+ *   2: calibration points hold the weight as i32 milligrams
+ *   3: density for volume units, gram display division
+ *   4: offset and calibration points at HX711 gain 64, for auto ranging
+ *   5: capacity and overload margin, overload count
+ *   6: calibration temperature, zero and span drift per °C
+ *   7: corner-load correction factors of a multi-cell platform
+ *   8: a calibration without a temperature reading stores NO_TEMPERATURE, not 25.0 °C
+ *   9: capacity and margin are checked, older records with bad limits get the defaults
+ */
+const SETTINGS_ADDRESS: u16 = 0x0000;
+const SETTINGS_MAGIC: u16 = 0x574B; // "KW"
+pub const SETTINGS_VERSION: u8 = 9;
+
+const HEADER_SIZE: usize = 4;
+const CRC_SIZE: usize = 2;
+const PAYLOAD_MAX: usize = 160;
+// Calibration temperature of a calibration done without a reading
+const NO_TEMPERATURE: u16 = 0x8000;
+pub const RECORD_MAX: usize = HEADER_SIZE + PAYLOAD_MAX + CRC_SIZE;
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SettingsError {
+    NoRecord,
+    Corrupt,
+    UnsupportedVersion,
+    InvalidData,
+}
+
+/* Non-volatile memory holding the record, the EEPROM on the target */
+pub trait Storage {
+    type Error;
+
+    fn read(&mut self, address: u16, buffer: &mut [u8]) -> Result<(), Self::Error>;
+    fn write(&mut self, address: u16, buffer: &[u8]) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub offset: i32,
+    pub calibration: LoadCellCalibration,
+    pub unit: Unit,
+    /* 0 keeps the scale on */
+    pub auto_off_minutes: u8,
+    pub backlight: bool,
+    /* 0 keeps the backlight on */
+    pub backlight_timeout_s: u8,
+    pub density: Density,
+    pub gram_division: Division,
+    /* Zero and calibration of channel A at gain 64, `offset` and `calibration` are at gain 128 */
+    pub offset_a64: i32,
+    pub calibration_a64: LoadCellCalibration,
+    pub limits: LoadLimits,
+    /* Overloads since the record was created, tells whether the cell was abused */
+    pub overload_count: u32,
+    pub compensation: TemperatureCompensation,
+    /* Factor of every cell in ppm, from the corner-load test, unused with a single cell */
+    pub corner_ppm: [i32; PLATFORM_CELLS],
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            calibration: LoadCellCalibration::new(),
+            unit: Unit::Grams,
+            auto_off_minutes: 5,
+            backlight: true,
+            backlight_timeout_s: 30,
+            density: Density::WATER,
+            gram_division: Division::One,
+            offset_a64: 0,
+            calibration_a64: LoadCellCalibration::new(),
+            limits: DEFAULT_LIMITS,
+            overload_count: 0,
+            compensation: TemperatureCompensation::default(),
+            corner_ppm: [UNITY_PPM; PLATFORM_CELLS],
+        }
+    }
+}
+
+impl Settings {
+    pub fn load<S: Storage>(storage: &mut S) -> Result<Self, SettingsError> {
+        let mut record = [0u8; RECORD_MAX];
+        storage
+            .read(SETTINGS_ADDRESS, &mut record)
+            .map_err(|_| SettingsError::NoRecord)?;
+        return Self::decode(&record);
+    }
+
+    pub fn save<S: Storage>(&self, storage: &mut S) {
+        let mut record = [0u8; RECORD_MAX];
+        let len = self.encode(&mut record);
+        // The record is far smaller than the EEPROM, this cannot be out of range
+        let _ = storage.write(SETTINGS_ADDRESS, &record[..len]);
+    }
+
+    /* Serialises the current layout into `record`, returns the record length */
+    pub fn encode(&self, record: &mut [u8; RECORD_MAX]) -> usize {
+        let mut payload = Writer::new(&mut record[HEADER_SIZE..HEADER_SIZE + PAYLOAD_MAX]);
+
+        /* Version 1, calibration weights changed to milligrams in version 2 */
+        payload.i32(self.offset);
+        payload.calibration(&self.calibration);
+        payload.u8(self.unit.id());
+        payload.u8(self.auto_off_minutes);
+        payload.u8(self.backlight as u8);
+        payload.u8(self.backlight_timeout_s);
+
+        /* Version 3 */
+        payload.u16(self.density.mg_per_ml());
+        payload.u8(self.gram_division.id());
+
+        /* Version 4 */
+        payload.i32(self.offset_a64);
+        payload.calibration(&self.calibration_a64);
+
+        /* Version 5 */
+        payload.i32(self.limits.capacity.mg());
+        payload.i32(self.limits.margin.mg());
+        payload.u32(self.overload_count);
+
+        /* Version 6 */
+        payload.u16(self.compensation.reference_tenths.map_or(NO_TEMPERATURE, |tenths| tenths as u16));
+        payload.i32(self.compensation.drift.offset_per_c);
+        payload.i32(self.compensation.drift.scale_ppm_per_c);
+
+        /* Version 7 */
+        self.corner_ppm.iter().for_each(|&ppm| payload.i32(ppm));
+
+        let len = payload.pos;
+        record[0..2].copy_from_slice(&SETTINGS_MAGIC.to_le_bytes());
+        record[2] = SETTINGS_VERSION;
+        record[3] = len as u8;
+        let crc = crc16(&record[2..HEADER_SIZE + len]);
+        record[HEADER_SIZE + len..HEADER_SIZE + len + CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+
+        return HEADER_SIZE + len + CRC_SIZE;
+    }
+
+    pub fn decode(record: &[u8]) -> Result<Self, SettingsError> {
+        if record.len() < HEADER_SIZE + CRC_SIZE
+            || u16::from_le_bytes([record[0], record[1]]) != SETTINGS_MAGIC
+        {
+            return Err(SettingsError::NoRecord);
+        }
+
+        let version = record[2];
+        let len = record[3] as usize;
+        if len > PAYLOAD_MAX || record.len() < HEADER_SIZE + len + CRC_SIZE {
+            return Err(SettingsError::Corrupt);
+        }
+
+        let crc_at = HEADER_SIZE + len;
+        let crc = u16::from_le_bytes([record[crc_at], record[crc_at + 1]]);
+        if crc16(&record[2..crc_at]) != crc {
+            return Err(SettingsError::Corrupt);
+        }
+        if version == 0 || version > SETTINGS_VERSION {
+            return Err(SettingsError::UnsupportedVersion);
+        }
+
+        return Self::migrate(version, &record[HEADER_SIZE..crc_at]);
+    }
+
+    /* Decodes any known layout, fields beyond the end of the payload keep their defaults */
+    fn migrate(version: u8, payload: &[u8]) -> Result<Self, SettingsError> {
+        let mut settings = Self::default();
+        let mut payload = Reader::new(payload);
+
+        /* Version 1 */
+        if let Some(offset) = payload.i32() {
+            settings.offset = offset;
+        }
+        if let Some(calibration) = payload.calibration(version)? {
+            settings.calibration = calibration;
+        }
+        if let Some(unit) = payload.u8() {
+            settings.unit = Unit::from_id(unit).ok_or(SettingsError::InvalidData)?;
+        }
+        if let Some(minutes) = payload.u8() {
+            settings.auto_off_minutes = minutes;
+        }
+        if let Some(backlight) = payload.u8() {
+            settings.backlight = backlight != 0;
+        }
+        if let Some(timeout) = payload.u8() {
+            settings.backlight_timeout_s = timeout;
+        }
+
+        /* Version 3 */
+        if let Some(density) = payload.u16() {
+            settings.density = Density::checked(density).ok_or(SettingsError::InvalidData)?;
+        }
+        if let Some(division) = payload.u8() {
+            settings.gram_division = Division::from_id(division).ok_or(SettingsError::InvalidData)?;
+        }
+
+        /* Version 4 */
+        if let Some(offset) = payload.i32() {
+            settings.offset_a64 = offset;
+        }
+        if let Some(calibration) = payload.calibration(version)? {
+            settings.calibration_a64 = calibration;
+        }
+
+        /* Version 5 */
+        if let (Some(capacity), Some(margin)) = (payload.i32(), payload.i32()) {
+            let limits = LoadLimits { capacity: Weight::from_mg(capacity), margin: Weight::from_mg(margin) };
+            if limits.is_valid() {
+                settings.limits = limits;
+            } else if version >= 9 {
+                return Err(SettingsError::InvalidData);
+            }
+        }
+        if let Some(count) = payload.u32() {
+            settings.overload_count = count;
+        }
+
+        /* Version 6 */
+        if let Some(tenths) = payload.u16() {
+            // Before version 8 25.0 °C stood in for a missing reading
+            settings.compensation.reference_tenths = match tenths {
+                NO_TEMPERATURE => None,
+                250 if version < 8 => None,
+                tenths => Some(tenths as i16),
+            };
+        }
+        if let (Some(offset_per_c), Some(scale_ppm_per_c)) = (payload.i32(), payload.i32()) {
+            settings.compensation.drift = Drift { offset_per_c, scale_ppm_per_c };
+        }
+
+        /* Version 7 */
+        for factor in settings.corner_ppm.iter_mut() {
+            if let Some(ppm) = payload.i32() {
+                if !(MIN_FACTOR_PPM..=MAX_FACTOR_PPM).contains(&ppm) {
+                    return Err(SettingsError::InvalidData);
+                }
+                *factor = ppm;
+            }
+        }
+
+        return Ok(settings);
+    }
+}
+
+/*
+ * Converts the bits of an f32 number of grams, as stored by version 1, into a
+ * Weight. Done on the raw IEEE 754 fields so no soft-float code is linked in.
+ */
+fn f32_grams_to_weight(bits: u32) -> Option<Weight> {
+    let negative = bits >> 31 != 0;
+    let exponent = ((bits >> 23) & 0xFF) as i32;
+    let mantissa = (bits & 0x7F_FFFF) as i64;
+
+    if exponent == 0xFF {
+        return None; // inf or NaN
+    }
+    // Zero and subnormals are far below a milligram
+    if exponent == 0 {
+        return Some(Weight::ZERO);
+    }
+
+    // value = (2^23 + mantissa) * 2^(exponent - 150), scaled to milligrams
+    let significand = ((1 << 23) | mantissa) * 1000;
+    let shift = exponent - 150;
+    let mg = if shift >= 0 {
+        if shift > 8 {
+            return None;
+        }
+        significand << shift
+    } else if shift > -63 {
+        (significand + (1 << (-shift - 1))) >> -shift
+    } else {
+        0
+    };
+    if mg > i32::MAX as i64 {
+        return None;
+    }
+
+    let mg = if negative { -mg } else { mg };
+    return Some(Weight::from_mg(mg as i32));
+}
+
+struct Writer<'a> {
+    buffer: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn new(buffer: &'a mut [u8]) -> Self {
+        Self { buffer, pos: 0 }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buffer[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
+        self.pos += bytes.len();
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes(&[value]);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn i32(&mut self, value: i32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    /* Point count, then all CALIBRATION_POINTS slots as counts and milligrams */
+    fn calibration(&mut self, calibration: &LoadCellCalibration) {
+        let points = calibration.points();
+        self.u8(points.len() as u8);
+        for i in 0..CALIBRATION_POINTS {
+            let point = points.get(i).copied().unwrap_or_default();
+            self.i32(point.counts);
+            self.i32(point.weight.mg());
+        }
+    }
+}
+
+struct Reader<'a> {
+    buffer: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buffer: &'a [u8]) -> Self {
+        Self { buffer, pos: 0 }
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.buffer.get(self.pos..self.pos + N)?;
+        self.pos += N;
+        let mut out = [0u8; N];
+        out.copy_from_slice(bytes);
+        Some(out)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes::<1>().map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes().map(u16::from_le_bytes)
+    }
+
+    fn i32(&mut self) -> Option<i32> {
+        self.bytes().map(i32::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes().map(u32::from_le_bytes)
+    }
+
+    /* None past the end of the payload, version 1 stored the weights as f32 grams */
+    fn calibration(&mut self, version: u8) -> Result<Option<LoadCellCalibration>, SettingsError> {
+        let count = match self.u8() {
+            Some(count) => count as usize,
+            None => return Ok(None),
+        };
+        if count > CALIBRATION_POINTS {
+            return Err(SettingsError::InvalidData);
+        }
+        let mut calibration = LoadCellCalibration::new();
+        for i in 0..CALIBRATION_POINTS {
+            let counts = self.i32().ok_or(SettingsError::InvalidData)?;
+            let weight = match version {
+                1 => self.u32().and_then(f32_grams_to_weight),
+                _ => self.i32().map(Weight::from_mg),
+            };
+            let weight = weight.ok_or(SettingsError::InvalidData)?;
+            if i < count {
+                calibration
+                    .add_point(counts, weight)
+                    .map_err(|_| SettingsError::InvalidData)?;
+            }
+        }
+        return Ok(Some(calibration));
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /* Blank 1 KiB EEPROM */
+    pub(crate) struct Eeprom(pub [u8; 1024]);
+
+    impl Storage for Eeprom {
+        type Error = ();
+
+        fn read(&mut self, address: u16, buffer: &mut [u8]) -> Result<(), ()> {
+            let start = address as usize;
+            let bytes = self.0.get(start..start + buffer.len()).ok_or(())?;
+            buffer.copy_from_slice(bytes);
+            return Ok(());
+        }
+
+        fn write(&mut self, address: u16, buffer: &[u8]) -> Result<(), ()> {
+            let start = address as usize;
+            self.0.get_mut(start..start + buffer.len()).ok_or(())?.copy_from_slice(buffer);
+            return Ok(());
+        }
+    }
+
+    fn custom() -> Settings {
+        let mut settings = Settings {
+            offset: -12345,
+            unit: Unit::PoundsOunces,
+            auto_off_minutes: 0,
+            backlight: false,
+            backlight_timeout_s: 10,
+            density: Density::from_mg_per_ml(920),
+            gram_division: Division::Half,
+            ..Settings::default()
+        };
+        settings.calibration.add_point(420_000, Weight::from_kg(1)).unwrap();
+        settings.calibration.add_point(-42_000, Weight::from_grams(-100)).unwrap();
+        settings.offset_a64 = -6_100;
+        settings.calibration_a64.add_point(210_300, Weight::from_kg(1)).unwrap();
+        settings.limits = LoadLimits { capacity: Weight::from_kg(3), margin: Weight::from_grams(50) };
+        settings.overload_count = 7;
+        settings.compensation = TemperatureCompensation {
+            reference_tenths: Some(-35),
+            drift: Drift { offset_per_c: -12, scale_ppm_per_c: 150 },
+        };
+        settings.corner_ppm = [1_012_500, 964_286, 1_012_500, 1_000_000];
+        return settings;
+    }
+
+    /* Builds a record around a hand written payload */
+    fn build_record(version: u8, payload: &[u8]) -> std::vec::Vec<u8> {
+        let mut record = std::vec::Vec::from(SETTINGS_MAGIC.to_le_bytes());
+        record.push(version);
+        record.push(payload.len() as u8);
+        record.extend_from_slice(payload);
+        let crc = crc16(&record[2..]);
+        record.extend_from_slice(&crc.to_le_bytes());
+        return record;
+    }
+
+    #[test]
+    fn round_trip_through_storage() {
+        let mut eeprom = Eeprom([0xFF; 1024]);
+        assert_eq!(Settings::load(&mut eeprom), Err(SettingsError::NoRecord));
+
+        custom().save(&mut eeprom);
+        assert_eq!(Settings::load(&mut eeprom), Ok(custom()));
+        assert_eq!(eeprom.0[2], SETTINGS_VERSION);
+    }
+
+    #[test]
+    fn rejects_damaged_records() {
+        let mut record = [0u8; RECORD_MAX];
+        let len = custom().encode(&mut record);
+
+        let mut flipped = record;
+        flipped[10] ^= 0x01;
+        assert_eq!(Settings::decode(&flipped[..len]), Err(SettingsError::Corrupt));
+        assert_eq!(Settings::decode(&record[..len - 1]), Err(SettingsError::Corrupt));
+        assert_eq!(Settings::decode(&record[..3]), Err(SettingsError::NoRecord));
+
+        let newer = build_record(SETTINGS_VERSION + 1, &record[HEADER_SIZE..len - CRC_SIZE]);
+        assert_eq!(Settings::decode(&newer), Err(SettingsError::UnsupportedVersion));
+
+        let mut bad_unit = record;
+        bad_unit[HEADER_SIZE + 5 + 8 * CALIBRATION_POINTS] = 99;
+        let bad_unit = build_record(SETTINGS_VERSION, &bad_unit[HEADER_SIZE..len - CRC_SIZE]);
+        assert_eq!(Settings::decode(&bad_unit), Err(SettingsError::InvalidData));
+
+        // Density right after unit, auto off, backlight and its timeout
+        let mut bad_density = record;
+        let at = HEADER_SIZE + 5 + 8 * CALIBRATION_POINTS + 4;
+        bad_density[at..at + 2].copy_from_slice(&u16::MAX.to_le_bytes());
+        let bad_density = build_record(SETTINGS_VERSION, &bad_density[HEADER_SIZE..len - CRC_SIZE]);
+        assert_eq!(Settings::decode(&bad_density), Err(SettingsError::InvalidData));
+
+        // A capacity of zero, older records never had their limits checked
+        let mut bad_limits = record;
+        let at = at + 3 + 4 + 1 + 8 * CALIBRATION_POINTS;
+        bad_limits[at..at + 4].copy_from_slice(&0i32.to_le_bytes());
+        let payload = &bad_limits[HEADER_SIZE..len - CRC_SIZE];
+        assert_eq!(Settings::decode(&build_record(SETTINGS_VERSION, payload)), Err(SettingsError::InvalidData));
+        let old = Settings::decode(&build_record(8, payload)).unwrap();
+        assert_eq!(old.limits, DEFAULT_LIMITS);
+        assert_eq!(old.calibration, custom().calibration);
+
+        // Corner factors are the last field
+        let mut bad_corner = record;
+        bad_corner[len - CRC_SIZE - 4..len - CRC_SIZE].copy_from_slice(&0i32.to_le_bytes());
+        let bad_corner = build_record(SETTINGS_VERSION, &bad_corner[HEADER_SIZE..len - CRC_SIZE]);
+        assert_eq!(Settings::decode(&bad_corner), Err(SettingsError::InvalidData));
+    }
+
+    #[test]
+    fn migrates_version_1() {
+        let mut payload = std::vec::Vec::new();
+        payload.extend_from_slice(&(-500i32).to_le_bytes());
+        payload.push(2);
+        for (counts, grams) in [(-10_000i32, -2.5f32), (200_000, 500.0), (0, 0.0), (0, 0.0), (0, 0.0), (0, 0.0)] {
+            payload.extend_from_slice(&counts.to_le_bytes());
+            payload.extend_from_slice(&grams.to_bits().to_le_bytes());
+        }
+        payload.extend_from_slice(&[Unit::Ounces.id(), 15, 1, 60]);
+
+        let settings = Settings::decode(&build_record(1, &payload)).unwrap();
+        assert_eq!(settings.offset, -500);
+        assert_eq!(settings.calibration.to_weight(-10_000), Weight::from_mg(-2500));
+        assert_eq!(settings.calibration.to_weight(200_000), Weight::from_grams(500));
+        assert_eq!(settings.unit, Unit::Ounces);
+        assert_eq!(settings.auto_off_minutes, 15);
+        assert_eq!(settings.backlight_timeout_s, 60);
+        // Not in version 1
+        assert_eq!(settings.density, Density::WATER);
+        assert_eq!(settings.gram_division, Division::One);
+        assert_eq!(settings.offset_a64, 0);
+        assert!(settings.calibration_a64.points().is_empty());
+        assert_eq!(settings.limits, DEFAULT_LIMITS);
+        assert_eq!(settings.overload_count, 0);
+        assert_eq!(settings.compensation, TemperatureCompensation::default());
+        assert_eq!(settings.corner_ppm, [UNITY_PPM; PLATFORM_CELLS]);
+    }
+
+    #[test]
+    fn calibration_temperature() {
+        let mut settings = custom();
+        for (reference, version, decoded) in [
+            (Some(250), SETTINGS_VERSION, Some(250)),
+            (None, SETTINGS_VERSION, None),
+            // Version 7 stored 25.0 °C when there was no reading
+            (Some(250), 7, None),
+            (Some(-35), 7, Some(-35)),
+        ] {
+            settings.compensation.reference_tenths = reference;
+            let mut record = [0u8; RECORD_MAX];
+            let len = settings.encode(&mut record);
+            let record = build_record(version, &record[HEADER_SIZE..len - CRC_SIZE]);
+            assert_eq!(Settings::decode(&record).map(|s| s.compensation.reference_tenths), Ok(decoded));
+        }
+    }
+
+    #[test]
+    fn f32_grams() {
+        assert_eq!(f32_grams_to_weight(0.1f32.to_bits()), Some(Weight::from_mg(100)));
+        assert_eq!(f32_grams_to_weight((-2.5f32).to_bits()), Some(Weight::from_mg(-2500)));
+        assert_eq!(f32_grams_to_weight(0.0f32.to_bits()), Some(Weight::ZERO));
+        assert_eq!(f32_grams_to_weight(f32::NAN.to_bits()), None);
+        assert_eq!(f32_grams_to_weight(1e7f32.to_bits()), None);
+    }
+}
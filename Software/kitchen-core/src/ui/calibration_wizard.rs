@@ -0,0 +1,356 @@
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::blocking::i2c::{Read, Write};
+
+use crate::drivers::button::ButtonEvent;
+use crate::drivers::lcd::LCD;
+use crate::weighing::calibration::LoadCellCalibration;
+use crate::weighing::stability::{Motion, StabilityConfig, StabilityDetector};
+use crate::weighing::timing::SampleRate;
+use crate::weighing::weight::Weight;
+
+/*
+ * Guided on-device calibration.
+ *
+ * Empty the platform -> tare -> select and place a reference mass -> measure
+ * -> confirm. The wizard is fed samples and button events by the main loop
+ * and never blocks. Zero and span are captured twice, at gain 128 and then
+ * at gain 64 (see gain()), so the HX711 can auto-range with a calibration
+ * for each gain. A short press advances, a long press cancels (or selects
+ * the reference mass). A capture only starts once the reading is stable and
+ * averages the readings of CAPTURE_MS, it is thrown away if the reading moves.
+ */
+pub const REFERENCE_MASSES: [(&str, Weight); 5] = [
+    ("100 g", Weight::from_grams(100)),
+    ("200 g", Weight::from_grams(200)),
+    ("500 g", Weight::from_grams(500)),
+    ("1 kg", Weight::from_kg(1)),
+    ("2 kg", Weight::from_kg(2)),
+];
+pub const DEFAULT_REFERENCE: usize = 2;
+
+const CAPTURE_MS: u16 = 1600;
+// Largest deviation, in counts, accepted while capturing a reading
+const CAPTURE_BAND_COUNTS: i32 = 250;
+const CAPTURE_STABILITY: StabilityConfig = StabilityConfig {
+    band_divisions: 1,
+    window_ms: 800,
+};
+// The reference mass has to move the reading by at least this many counts, half at gain 64
+const MIN_SPAN_COUNTS: i32 = 1000;
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum WizardStep {
+    EmptyPlatform,
+    CaptureZero,
+    /* The same captures again at gain 64, they follow without a press */
+    CaptureZeroLow,
+    SelectMass,
+    PlaceMass,
+    CaptureSpan,
+    CaptureSpanLow,
+    Confirm,
+    Saved,
+    Cancelled,
+}
+
+pub(crate) enum CaptureStatus {
+    Settling,
+    Collecting,
+    Unstable,
+    Done(i32),
+}
+
+/* Average of a stable reading, also used by the temperature drift test */
+pub(crate) struct Capture {
+    stability: StabilityDetector,
+    count: u8,
+    samples: u8,
+    sum: i64,
+}
+
+impl Capture {
+    pub(crate) fn new(rate: SampleRate) -> Self {
+        Self {
+            stability: StabilityDetector::new(CAPTURE_STABILITY, CAPTURE_BAND_COUNTS, rate),
+            samples: rate.window(CAPTURE_MS),
+            count: 0,
+            sum: 0,
+        }
+    }
+
+    pub(crate) fn add(&mut self, raw: i32) -> CaptureStatus {
+        if self.stability.update(raw) == Motion::Moving {
+            if self.count == 0 {
+                return CaptureStatus::Settling;
+            }
+            self.count = 0;
+            self.sum = 0;
+            return CaptureStatus::Unstable;
+        }
+
+        self.count += 1;
+        self.sum += raw as i64;
+        if self.count < self.samples {
+            return CaptureStatus::Collecting;
+        }
+        return CaptureStatus::Done((self.sum / self.count as i64) as i32);
+    }
+}
+
+pub struct CalibrationWizard {
+    step: WizardStep,
+    rate: SampleRate,
+    capture: Capture,
+    reference: usize,
+    offset: i32,
+    calibration: LoadCellCalibration,
+    offset_low: i32,
+    calibration_low: LoadCellCalibration,
+    message: Option<&'static str>,
+    redraw: bool,
+}
+
+impl CalibrationWizard {
+    pub fn new(rate: SampleRate) -> Self {
+        Self {
+            step: WizardStep::EmptyPlatform,
+            rate,
+            capture: Capture::new(rate),
+            reference: DEFAULT_REFERENCE,
+            offset: 0,
+            calibration: LoadCellCalibration::new(),
+            offset_low: 0,
+            calibration_low: LoadCellCalibration::new(),
+            message: None,
+            redraw: true,
+        }
+    }
+
+    pub fn step(&self) -> WizardStep {
+        return self.step;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        return matches!(self.step, WizardStep::Saved | WizardStep::Cancelled);
+    }
+
+    /* Offset and calibration to apply, once the user confirmed them */
+    pub fn result(&self) -> Option<(i32, LoadCellCalibration)> {
+        match self.step {
+            WizardStep::Saved => Some((self.offset, self.calibration)),
+            _ => None,
+        }
+    }
+
+    /* The same at gain 64 */
+    pub fn low_gain_result(&self) -> Option<(i32, LoadCellCalibration)> {
+        match self.step {
+            WizardStep::Saved => Some((self.offset_low, self.calibration_low)),
+            _ => None,
+        }
+    }
+
+    /* HX711 gain the samples have to be read at */
+    pub fn gain(&self) -> u8 {
+        match self.step {
+            WizardStep::CaptureZeroLow | WizardStep::CaptureSpanLow => 64,
+            _ => 128,
+        }
+    }
+
+    pub fn on_button(&mut self, event: ButtonEvent) {
+        let next = match (self.step, event) {
+            (WizardStep::SelectMass, ButtonEvent::Short) => {
+                self.reference = (self.reference + 1) % REFERENCE_MASSES.len();
+                WizardStep::SelectMass
+            }
+            (WizardStep::SelectMass, ButtonEvent::Long) => WizardStep::PlaceMass,
+            (WizardStep::EmptyPlatform, ButtonEvent::Short) => WizardStep::CaptureZero,
+            (WizardStep::PlaceMass, ButtonEvent::Short) => WizardStep::CaptureSpan,
+            (WizardStep::Confirm, ButtonEvent::Short) => WizardStep::Saved,
+            (WizardStep::Confirm, ButtonEvent::Long) => WizardStep::EmptyPlatform,
+            (WizardStep::Saved, _) | (WizardStep::Cancelled, _) => return,
+            (_, ButtonEvent::Long) => WizardStep::Cancelled,
+            (_, ButtonEvent::Short) => return,
+        };
+        self.enter(next);
+    }
+
+    /* A raw reading and the gain it was converted at, readings at any other gain than gain() are dropped */
+    pub fn on_sample(&mut self, raw: i32, gain: u8) {
+        if gain != self.gain() {
+            return;
+        }
+        if !matches!(
+            self.step,
+            WizardStep::CaptureZero | WizardStep::CaptureZeroLow | WizardStep::CaptureSpan | WizardStep::CaptureSpanLow
+        ) {
+            return;
+        }
+
+        let value = match self.capture.add(raw) {
+            CaptureStatus::Settling | CaptureStatus::Collecting => return,
+            CaptureStatus::Unstable => {
+                self.message = Some("Unsettled, retrying");
+                self.redraw = true;
+                return;
+            }
+            CaptureStatus::Done(value) => value,
+        };
+
+        match self.step {
+            WizardStep::CaptureZero => {
+                self.offset = value;
+                self.enter(WizardStep::CaptureZeroLow);
+            }
+            WizardStep::CaptureZeroLow => {
+                self.offset_low = value;
+                self.enter(WizardStep::SelectMass);
+            }
+            WizardStep::CaptureSpan => match self.span(value - self.offset, MIN_SPAN_COUNTS) {
+                Some(calibration) => {
+                    self.calibration = calibration;
+                    self.enter(WizardStep::CaptureSpanLow);
+                }
+                None => self.retry_span(),
+            },
+            _ => match self.span(value - self.offset_low, MIN_SPAN_COUNTS / 2) {
+                Some(calibration) => {
+                    self.calibration_low = calibration;
+                    self.enter(WizardStep::Confirm);
+                }
+                None => self.retry_span(),
+            },
+        }
+    }
+
+    fn span(&self, net: i32, min_counts: i32) -> Option<LoadCellCalibration> {
+        let mut calibration = LoadCellCalibration::new();
+        if net.abs() < min_counts || calibration.add_point(net, REFERENCE_MASSES[self.reference].1).is_err() {
+            return None;
+        }
+        return Some(calibration);
+    }
+
+    fn retry_span(&mut self) {
+        self.enter(WizardStep::PlaceMass);
+        self.message = Some("No load detected");
+    }
+
+    pub fn render<I2C, D>(&mut self, lcd: &mut LCD<I2C, D>)
+    where
+        I2C: Write + Read,
+        D: DelayUs<u16> + DelayMs<u16>,
+    {
+        if !self.redraw {
+            return;
+        }
+        self.redraw = false;
+
+        let mass = REFERENCE_MASSES[self.reference].0;
+        let lines: [&str; 3] = match self.step {
+            WizardStep::EmptyPlatform => ["Empty the platform", "Press: tare", "Hold: cancel"],
+            WizardStep::CaptureZero => ["Taring...", "Keep still", ""],
+            WizardStep::CaptureZeroLow => ["Taring...", "Keep still", "Gain 64"],
+            WizardStep::SelectMass => ["Reference mass", mass, "Press: next/Hold: ok"],
+            WizardStep::PlaceMass => ["Place reference mass", mass, "Press when placed"],
+            WizardStep::CaptureSpan => ["Measuring...", "Keep still", ""],
+            WizardStep::CaptureSpanLow => ["Measuring...", "Keep still", "Gain 64"],
+            WizardStep::Confirm => ["Calibration done", "Press: save", "Hold: start over"],
+            WizardStep::Saved => ["Calibration saved", "", ""],
+            WizardStep::Cancelled => ["Calibration", "cancelled", ""],
+        };
+
+        lcd.clear();
+        for (row, line) in lines.iter().enumerate() {
+            lcd.set_cursor(0, row as u8);
+            lcd.write_str(line);
+        }
+        if let Some(message) = self.message {
+            lcd.set_cursor(0, 3);
+            lcd.write_str(message);
+        }
+    }
+
+    fn enter(&mut self, step: WizardStep) {
+        self.step = step;
+        self.capture = Capture::new(self.rate);
+        self.message = None;
+        self.redraw = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(wizard: &mut CalibrationWizard, raw: i32, samples: usize) {
+        let gain = wizard.gain();
+        (0..samples).for_each(|_| wizard.on_sample(raw, gain));
+    }
+
+    #[test]
+    fn full_calibration() {
+        let mut wizard = CalibrationWizard::new(SampleRate::from_sps(10));
+        wizard.on_button(ButtonEvent::Short);
+        assert_eq!(wizard.step(), WizardStep::CaptureZero);
+        feed(&mut wizard, 10_000, 30);
+        assert_eq!(wizard.step(), WizardStep::CaptureZeroLow);
+        assert_eq!(wizard.gain(), 64);
+        // Still queued at gain 128 when the wizard moved on
+        (0..30).for_each(|_| wizard.on_sample(10_000, 128));
+        assert_eq!(wizard.step(), WizardStep::CaptureZeroLow);
+        feed(&mut wizard, 5_200, 30);
+        assert_eq!(wizard.step(), WizardStep::SelectMass);
+        assert_eq!(wizard.gain(), 128);
+
+        // 500 g -> 1 kg
+        wizard.on_button(ButtonEvent::Short);
+        wizard.on_button(ButtonEvent::Long);
+        wizard.on_button(ButtonEvent::Short);
+        assert_eq!(wizard.step(), WizardStep::CaptureSpan);
+        feed(&mut wizard, 430_000, 30);
+        assert_eq!(wizard.step(), WizardStep::CaptureSpanLow);
+        feed(&mut wizard, 215_500, 30);
+        assert_eq!(wizard.step(), WizardStep::Confirm);
+        assert_eq!(wizard.result(), None);
+
+        wizard.on_button(ButtonEvent::Short);
+        assert!(wizard.is_finished());
+        let (offset, calibration) = wizard.result().unwrap();
+        assert_eq!(offset, 10_000);
+        assert_eq!(calibration.to_weight(420_000), Weight::from_kg(1));
+        let (offset, calibration) = wizard.low_gain_result().unwrap();
+        assert_eq!(offset, 5_200);
+        assert_eq!(calibration.to_weight(210_300), Weight::from_kg(1));
+    }
+
+    #[test]
+    fn span_without_load_is_retried() {
+        let mut wizard = CalibrationWizard::new(SampleRate::from_sps(10));
+        wizard.on_button(ButtonEvent::Short);
+        feed(&mut wizard, 0, 30);
+        feed(&mut wizard, 0, 30);
+        wizard.on_button(ButtonEvent::Long);
+        wizard.on_button(ButtonEvent::Short);
+        feed(&mut wizard, 500, 30);
+        assert_eq!(wizard.step(), WizardStep::PlaceMass);
+
+        // The gain 64 span is checked as well
+        wizard.on_button(ButtonEvent::Short);
+        feed(&mut wizard, 2_000, 30);
+        assert_eq!(wizard.step(), WizardStep::CaptureSpanLow);
+        feed(&mut wizard, 300, 30);
+        assert_eq!(wizard.step(), WizardStep::PlaceMass);
+    }
+
+    #[test]
+    fn long_press_cancels() {
+        let mut wizard = CalibrationWizard::new(SampleRate::from_sps(10));
+        wizard.on_button(ButtonEvent::Long);
+        assert_eq!(wizard.step(), WizardStep::Cancelled);
+        assert!(wizard.is_finished());
+        assert_eq!(wizard.result(), None);
+    }
+}
@@ -0,0 +1,351 @@
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::blocking::i2c::{Read, Write};
+
+use crate::drivers::button::ButtonEvent;
+use crate::drivers::lcd::LCD;
+use crate::ui::calibration_wizard::{DEFAULT_REFERENCE, REFERENCE_MASSES};
+use crate::weighing::corner_correction::{self, CornerError};
+use crate::weighing::stability::{Motion, StabilityConfig, StabilityDetector};
+use crate::weighing::timing::SampleRate;
+use crate::weighing::weight::Weight;
+
+/*
+ * Guided corner-load test of a platform on several load cells.
+ *
+ * Empty the platform -> capture zero -> select the test mass -> place it
+ * over corner 1 -> capture -> ... -> corner N -> confirm. The test mass is
+ * one of the calibration reference masses and every corner is corrected to
+ * read it, so the factors fix the span as well. The test is fed the
+ * calibrated weight of every cell, see LoadCellArray::cell_weights, and never
+ * blocks. A short press advances, a long press cancels (or selects the test
+ * mass). Captures wait for a stable total and average the readings of
+ * CAPTURE_MS, like the calibration wizard.
+ */
+const CAPTURE_MS: u16 = 1600;
+const CAPTURE_BAND: Weight = Weight::from_grams(1);
+const CAPTURE_STABILITY: StabilityConfig = StabilityConfig {
+    band_divisions: 1,
+    window_ms: 800,
+};
+// The test mass has to add at least this much
+const MIN_TEST_LOAD: Weight = Weight::from_grams(50);
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CornerStep {
+    EmptyPlatform,
+    CaptureZero,
+    SelectMass,
+    /* Corner index, from 0 */
+    PlaceMass(u8),
+    CaptureCorner(u8),
+    Confirm,
+    Saved,
+    Cancelled,
+}
+
+enum CaptureStatus<const N: usize> {
+    Waiting,
+    Unstable,
+    Done([Weight; N]),
+}
+
+struct Capture<const N: usize> {
+    stability: StabilityDetector,
+    count: u8,
+    samples: u8,
+    sums: [i64; N],
+}
+
+impl<const N: usize> Capture<N> {
+    fn new(rate: SampleRate) -> Self {
+        Self {
+            stability: StabilityDetector::new(CAPTURE_STABILITY, CAPTURE_BAND.mg(), rate),
+            samples: rate.window(CAPTURE_MS),
+            count: 0,
+            sums: [0; N],
+        }
+    }
+
+    fn add(&mut self, cells: &[Weight; N]) -> CaptureStatus<N> {
+        let total = cells.iter().fold(Weight::ZERO, |total, &cell| total + cell);
+        if self.stability.update(total.mg()) == Motion::Moving {
+            if self.count == 0 {
+                return CaptureStatus::Waiting;
+            }
+            self.count = 0;
+            self.sums = [0; N];
+            return CaptureStatus::Unstable;
+        }
+
+        self.count += 1;
+        self.sums.iter_mut().zip(cells.iter()).for_each(|(sum, cell)| *sum += cell.mg() as i64);
+        if self.count < self.samples {
+            return CaptureStatus::Waiting;
+        }
+        let mut average = [Weight::ZERO; N];
+        for (cell, sum) in average.iter_mut().zip(self.sums.iter()) {
+            *cell = Weight::from_mg((sum / self.count as i64) as i32);
+        }
+        return CaptureStatus::Done(average);
+    }
+}
+
+pub struct CornerTest<const N: usize> {
+    step: CornerStep,
+    rate: SampleRate,
+    capture: Capture<N>,
+    /* Index into REFERENCE_MASSES */
+    reference: usize,
+    zero: [Weight; N],
+    /* loads[i][j], cell j with the mass over corner i, zero subtracted */
+    loads: [[Weight; N]; N],
+    corrections: [i32; N],
+    message: Option<&'static str>,
+    redraw: bool,
+}
+
+impl<const N: usize> CornerTest<N> {
+    pub fn new(rate: SampleRate) -> Self {
+        Self {
+            step: CornerStep::EmptyPlatform,
+            rate,
+            capture: Capture::new(rate),
+            reference: DEFAULT_REFERENCE,
+            zero: [Weight::ZERO; N],
+            loads: [[Weight::ZERO; N]; N],
+            corrections: [corner_correction::UNITY_PPM; N],
+            message: None,
+            redraw: true,
+        }
+    }
+
+    pub fn step(&self) -> CornerStep {
+        return self.step;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        return matches!(self.step, CornerStep::Saved | CornerStep::Cancelled);
+    }
+
+    /* Weight of every cell on the empty platform, from the zero capture */
+    pub fn zero(&self) -> [Weight; N] {
+        return self.zero;
+    }
+
+    /* Correction factors in ppm for LoadCellArray::set_corrections, once confirmed */
+    pub fn result(&self) -> Option<[i32; N]> {
+        match self.step {
+            CornerStep::Saved => Some(self.corrections),
+            _ => None,
+        }
+    }
+
+    pub fn on_button(&mut self, event: ButtonEvent) {
+        let next = match (self.step, event) {
+            (CornerStep::SelectMass, ButtonEvent::Short) => {
+                self.reference = (self.reference + 1) % REFERENCE_MASSES.len();
+                CornerStep::SelectMass
+            }
+            (CornerStep::SelectMass, ButtonEvent::Long) => CornerStep::PlaceMass(0),
+            (CornerStep::EmptyPlatform, ButtonEvent::Short) => CornerStep::CaptureZero,
+            (CornerStep::PlaceMass(corner), ButtonEvent::Short) => CornerStep::CaptureCorner(corner),
+            (CornerStep::Confirm, ButtonEvent::Short) => CornerStep::Saved,
+            (CornerStep::Confirm, ButtonEvent::Long) => CornerStep::EmptyPlatform,
+            (CornerStep::Saved, _) | (CornerStep::Cancelled, _) => return,
+            (_, ButtonEvent::Long) => CornerStep::Cancelled,
+            (_, ButtonEvent::Short) => return,
+        };
+        self.enter(next);
+    }
+
+    /* Calibrated weight of every cell, one call per sample */
+    pub fn on_sample(&mut self, cells: &[Weight; N]) {
+        if !matches!(self.step, CornerStep::CaptureZero | CornerStep::CaptureCorner(_)) {
+            return;
+        }
+
+        let average = match self.capture.add(cells) {
+            CaptureStatus::Waiting => return,
+            CaptureStatus::Unstable => {
+                self.message = Some("Unsettled, retrying");
+                self.redraw = true;
+                return;
+            }
+            CaptureStatus::Done(average) => average,
+        };
+
+        let corner = match self.step {
+            CornerStep::CaptureCorner(corner) => corner as usize,
+            _ => {
+                self.zero = average;
+                self.enter(CornerStep::SelectMass);
+                return;
+            }
+        };
+
+        let mut load = [Weight::ZERO; N];
+        for ((cell, &value), &zero) in load.iter_mut().zip(average.iter()).zip(self.zero.iter()) {
+            *cell = value - zero;
+        }
+        let total = load.iter().fold(Weight::ZERO, |total, &cell| total + cell);
+        if total < MIN_TEST_LOAD {
+            self.enter(CornerStep::PlaceMass(corner as u8));
+            self.message = Some("No load detected");
+            return;
+        }
+        self.loads[corner] = load;
+
+        if corner + 1 < N {
+            self.enter(CornerStep::PlaceMass(corner as u8 + 1));
+            return;
+        }
+        match corner_correction::solve(&self.loads, REFERENCE_MASSES[self.reference].1) {
+            Ok(corrections) => {
+                self.corrections = corrections;
+                self.enter(CornerStep::Confirm);
+            }
+            Err(e) => {
+                self.enter(CornerStep::EmptyPlatform);
+                self.message = Some(match e {
+                    CornerError::Singular => "Same corner twice?",
+                    CornerError::OutOfRange => "Check the cells",
+                });
+            }
+        }
+    }
+
+    pub fn render<I2C, D>(&mut self, lcd: &mut LCD<I2C, D>)
+    where
+        I2C: Write + Read,
+        D: DelayUs<u16> + DelayMs<u16>,
+    {
+        if !self.redraw {
+            return;
+        }
+        self.redraw = false;
+
+        let mass = REFERENCE_MASSES[self.reference].0;
+        let lines: [&str; 3] = match self.step {
+            CornerStep::EmptyPlatform => ["Empty the platform", "Press: start", "Hold: cancel"],
+            CornerStep::CaptureZero => ["Zeroing...", "Keep still", ""],
+            CornerStep::SelectMass => ["Test mass", mass, "Press: next/Hold: ok"],
+            CornerStep::PlaceMass(_) => ["Place the mass over", "", "Press when placed"],
+            CornerStep::CaptureCorner(_) => ["Measuring...", "", "Keep still"],
+            CornerStep::Confirm => ["Corner test done", "Press: save", "Hold: start over"],
+            CornerStep::Saved => ["Corrections saved", "", ""],
+            CornerStep::Cancelled => ["Corner test", "cancelled", ""],
+        };
+
+        lcd.clear();
+        for (row, line) in lines.iter().enumerate() {
+            lcd.set_cursor(0, row as u8);
+            lcd.write_str(line);
+        }
+        if let CornerStep::PlaceMass(corner) | CornerStep::CaptureCorner(corner) = self.step {
+            // "corner 2 of 4", there are never more than 9 cells
+            lcd.set_cursor(0, 1);
+            lcd.write_str("corner ");
+            lcd.write_char(b'1' + corner);
+            lcd.write_str(" of ");
+            lcd.write_char(b'0' + N as u8);
+        }
+        if let Some(message) = self.message {
+            lcd.set_cursor(0, 3);
+            lcd.write_str(message);
+        }
+    }
+
+    fn enter(&mut self, step: CornerStep) {
+        self.step = step;
+        self.capture = Capture::new(self.rate);
+        self.message = None;
+        self.redraw = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::shared_bus::SharedI2c;
+    use core::cell::RefCell;
+    use kitchen_emu::delay::VirtualDelay;
+    use kitchen_emu::hd44780::LcdBackpack;
+
+    fn feed<const N: usize>(test: &mut CornerTest<N>, grams: [i32; N], samples: usize) {
+        let cells = grams.map(Weight::from_grams);
+        (0..samples).for_each(|_| test.on_sample(&cells));
+    }
+
+    #[test]
+    fn four_corners() {
+        let mut test = CornerTest::<4>::new(SampleRate::from_sps(10));
+        test.on_button(ButtonEvent::Short);
+        feed(&mut test, [50, 60, 40, 50], 30);
+        assert_eq!(test.step(), CornerStep::SelectMass);
+        test.on_button(ButtonEvent::Short);
+        test.on_button(ButtonEvent::Long);
+        assert_eq!(test.step(), CornerStep::PlaceMass(0));
+
+        // 1 kg, 70 % over its corner, and cell 1 reads 5 % high
+        let corners = [[750, 165, 140, 150], [150, 795, 140, 150], [150, 165, 740, 150], [150, 165, 140, 750]];
+        for (i, corner) in corners.iter().enumerate() {
+            test.on_button(ButtonEvent::Short);
+            assert_eq!(test.step(), CornerStep::CaptureCorner(i as u8));
+            feed(&mut test, *corner, 30);
+        }
+        assert_eq!(test.step(), CornerStep::Confirm);
+        assert_eq!(test.result(), None);
+
+        test.on_button(ButtonEvent::Short);
+        let corrections = test.result().unwrap();
+        // Every corner reads 1 kg: cell 1 is scaled back by its 5 %, the others are right already
+        assert!((corrections[1] - 952_381).abs() < 100, "{:?}", corrections);
+        assert!(corrections.iter().enumerate().all(|(i, &c)| i == 1 || (c - 1_000_000).abs() < 100));
+    }
+
+    #[test]
+    fn empty_corner_is_retried() {
+        let mut test = CornerTest::<2>::new(SampleRate::from_sps(10));
+        test.on_button(ButtonEvent::Short);
+        feed(&mut test, [0, 0], 30);
+        test.on_button(ButtonEvent::Long);
+        test.on_button(ButtonEvent::Short);
+        feed(&mut test, [10, 10], 30);
+        assert_eq!(test.step(), CornerStep::PlaceMass(0));
+    }
+
+    #[test]
+    fn same_corner_twice_starts_over() {
+        let mut test = CornerTest::<2>::new(SampleRate::from_sps(10));
+        test.on_button(ButtonEvent::Short);
+        feed(&mut test, [0, 0], 30);
+        test.on_button(ButtonEvent::Long);
+        for _ in 0..2 {
+            test.on_button(ButtonEvent::Short);
+            feed(&mut test, [400, 100], 30);
+        }
+        assert_eq!(test.step(), CornerStep::EmptyPlatform);
+        test.on_button(ButtonEvent::Long);
+        assert!(test.is_finished());
+        assert_eq!(test.result(), None);
+    }
+
+    #[test]
+    fn shows_the_corner() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        let mut lcd = LCD::init(SharedI2c::new(&backpack), VirtualDelay::new());
+        let mut test = CornerTest::<4>::new(SampleRate::from_sps(10));
+        test.on_button(ButtonEvent::Short);
+        feed(&mut test, [0; 4], 30);
+        test.on_button(ButtonEvent::Long);
+        test.on_button(ButtonEvent::Short);
+        feed(&mut test, [10; 4], 30);
+        test.render(&mut lcd);
+
+        let backpack = backpack.borrow();
+        assert_eq!(backpack.lcd().row_text(0).trim_end(), "Place the mass over");
+        assert_eq!(backpack.lcd().row_text(1).trim_end(), "corner 1 of 4");
+        assert_eq!(backpack.lcd().row_text(3).trim_end(), "No load detected");
+    }
+}
@@ -0,0 +1,291 @@
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::blocking::i2c::{Read, Write};
+
+use crate::drivers::button::ButtonEvent;
+use crate::drivers::lcd::LCD;
+use crate::ui::calibration_wizard::{Capture, CaptureStatus};
+use crate::weighing::format::{Render, Style};
+use crate::weighing::temperature::{Drift, DriftError, DriftPoint, MIN_DELTA_TENTHS};
+use crate::weighing::timing::SampleRate;
+use crate::weighing::units::UnitReading;
+
+/*
+ * Guided test of the temperature drift, learns the coefficients of
+ * TemperatureCompensation.
+ *
+ * Empty the platform -> capture zero -> place a test mass -> capture, at one
+ * temperature, then again once the scale is MIN_DELTA_TENTHS warmer or cooler
+ * (next to the oven, or out on the balcony) -> confirm. Any mass will do as
+ * long as it is the same both times. Fed the raw samples and the internal
+ * temperature by the main loop, never blocks. A short press advances, a long
+ * press cancels.
+ */
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DriftStep {
+    /* Temperature index, 0 or 1 */
+    EmptyPlatform(u8),
+    CaptureZero(u8),
+    PlaceMass(u8),
+    CaptureLoad(u8),
+    ChangeTemperature,
+    Confirm,
+    Saved,
+    Cancelled,
+}
+
+pub struct DriftWizard {
+    step: DriftStep,
+    rate: SampleRate,
+    capture: Capture,
+    temperature: Option<i16>,
+    /* Temperature during the zero capture, averaged with the one during the load capture */
+    zero_tenths: i16,
+    points: [DriftPoint; 2],
+    drift: Drift,
+    message: Option<&'static str>,
+    redraw: bool,
+}
+
+impl DriftWizard {
+    pub fn new(rate: SampleRate, temperature: Option<i16>) -> Self {
+        Self {
+            step: DriftStep::EmptyPlatform(0),
+            rate,
+            capture: Capture::new(rate),
+            temperature,
+            zero_tenths: 0,
+            points: [DriftPoint::default(); 2],
+            drift: Drift::NONE,
+            message: None,
+            redraw: true,
+        }
+    }
+
+    pub fn step(&self) -> DriftStep {
+        return self.step;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        return matches!(self.step, DriftStep::Saved | DriftStep::Cancelled);
+    }
+
+    /* Coefficients to store with the calibration, once confirmed */
+    pub fn result(&self) -> Option<Drift> {
+        match self.step {
+            DriftStep::Saved => Some(self.drift),
+            _ => None,
+        }
+    }
+
+    pub fn on_button(&mut self, event: ButtonEvent) {
+        let next = match (self.step, event) {
+            (DriftStep::EmptyPlatform(i), ButtonEvent::Short) => DriftStep::CaptureZero(i),
+            (DriftStep::PlaceMass(i), ButtonEvent::Short) => DriftStep::CaptureLoad(i),
+            (DriftStep::ChangeTemperature, ButtonEvent::Short) => match self.temperature {
+                Some(now) if (now - self.points[0].tenths).abs() >= MIN_DELTA_TENTHS => DriftStep::EmptyPlatform(1),
+                _ => {
+                    self.message = Some("Not there yet");
+                    self.redraw = true;
+                    return;
+                }
+            },
+            (DriftStep::Confirm, ButtonEvent::Short) => DriftStep::Saved,
+            (DriftStep::Confirm, ButtonEvent::Long) => DriftStep::EmptyPlatform(0),
+            (DriftStep::Saved, _) | (DriftStep::Cancelled, _) => return,
+            (_, ButtonEvent::Long) => DriftStep::Cancelled,
+            (_, ButtonEvent::Short) => return,
+        };
+        self.enter(next);
+    }
+
+    /* Internal temperature in tenths of °C, whenever a new one is measured */
+    pub fn on_temperature(&mut self, tenths: i16) {
+        self.temperature = Some(tenths);
+        if self.step == DriftStep::ChangeTemperature {
+            self.redraw = true;
+        }
+    }
+
+    /* A raw reading and the gain it was converted at, the test runs at gain 128 only */
+    pub fn on_sample(&mut self, raw: i32, gain: u8) {
+        if gain != 128 {
+            return;
+        }
+        let i = match self.step {
+            DriftStep::CaptureZero(i) | DriftStep::CaptureLoad(i) => i,
+            _ => return,
+        };
+
+        let value = match self.capture.add(raw) {
+            CaptureStatus::Settling | CaptureStatus::Collecting => return,
+            CaptureStatus::Unstable => {
+                self.message = Some("Unsettled, retrying");
+                self.redraw = true;
+                return;
+            }
+            CaptureStatus::Done(value) => value,
+        };
+        let tenths = match self.temperature {
+            Some(tenths) => tenths,
+            None => {
+                self.enter(DriftStep::EmptyPlatform(i));
+                self.message = Some("No temperature yet");
+                return;
+            }
+        };
+
+        let point = &mut self.points[i as usize];
+        if let DriftStep::CaptureZero(_) = self.step {
+            point.zero = value;
+            self.zero_tenths = tenths;
+            self.enter(DriftStep::PlaceMass(i));
+            return;
+        }
+        point.loaded = value;
+        point.tenths = ((self.zero_tenths as i32 + tenths as i32) / 2) as i16;
+        if i == 0 {
+            self.enter(DriftStep::ChangeTemperature);
+            return;
+        }
+
+        match Drift::learn(self.points[0], self.points[1]) {
+            Ok(drift) => {
+                self.drift = drift;
+                self.enter(DriftStep::Confirm);
+            }
+            Err(e) => {
+                self.enter(match e {
+                    DriftError::TooClose => DriftStep::ChangeTemperature,
+                    _ => DriftStep::PlaceMass(i),
+                });
+                self.message = Some(match e {
+                    DriftError::TooClose => "Not there yet",
+                    DriftError::NoLoad => "No load detected",
+                    DriftError::OutOfRange => "Same mass as before?",
+                });
+            }
+        }
+    }
+
+    pub fn render<I2C, D>(&mut self, lcd: &mut LCD<I2C, D>)
+    where
+        I2C: Write + Read,
+        D: DelayUs<u16> + DelayMs<u16>,
+    {
+        if !self.redraw {
+            return;
+        }
+        self.redraw = false;
+
+        let lines: [&str; 3] = match self.step {
+            DriftStep::EmptyPlatform(0) => ["Temperature drift", "Empty the platform", "Press: zero"],
+            DriftStep::EmptyPlatform(_) => ["Second temperature", "Empty the platform", "Press: zero"],
+            DriftStep::CaptureZero(_) => ["Zeroing...", "Keep still", ""],
+            DriftStep::PlaceMass(_) => ["Place a test mass", "the same both times", "Press when placed"],
+            DriftStep::CaptureLoad(_) => ["Measuring...", "Keep still", ""],
+            DriftStep::ChangeTemperature => ["Warm or cool by 10C", "", "Press when there"],
+            DriftStep::Confirm => ["Drift measured", "Press: save", "Hold: start over"],
+            DriftStep::Saved => ["Compensation saved", "", ""],
+            DriftStep::Cancelled => ["Drift test", "cancelled", ""],
+        };
+
+        lcd.clear();
+        for (row, line) in lines.iter().enumerate() {
+            lcd.set_cursor(0, row as u8);
+            lcd.write_str(line);
+        }
+        if self.step == DriftStep::ChangeTemperature {
+            // "was 21.5C now 24.0C"
+            lcd.set_cursor(0, 1);
+            lcd.write_str("was ");
+            write_tenths(lcd, self.points[0].tenths);
+            if let Some(now) = self.temperature {
+                lcd.write_str(" now ");
+                write_tenths(lcd, now);
+            }
+        }
+        if let Some(message) = self.message {
+            lcd.set_cursor(0, 3);
+            lcd.write_str(message);
+        }
+    }
+
+    fn enter(&mut self, step: DriftStep) {
+        self.step = step;
+        self.capture = Capture::new(self.rate);
+        self.message = None;
+        self.redraw = true;
+    }
+}
+
+fn write_tenths<I2C, D>(lcd: &mut LCD<I2C, D>, tenths: i16)
+where
+    I2C: Write + Read,
+    D: DelayUs<u16> + DelayMs<u16>,
+{
+    let reading = UnitReading::Decimal { value: tenths as i32, decimals: 1 };
+    lcd.write_str(reading.styled(Style::left(1)).to_field().as_str());
+    lcd.write_char(b'C');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::shared_bus::SharedI2c;
+    use core::cell::RefCell;
+    use kitchen_emu::delay::VirtualDelay;
+    use kitchen_emu::hd44780::LcdBackpack;
+
+    fn feed(wizard: &mut DriftWizard, raw: i32, samples: usize) {
+        (0..samples).for_each(|_| wizard.on_sample(raw, 128));
+    }
+
+    /* Zero and loaded capture at the current temperature */
+    fn measure(wizard: &mut DriftWizard, zero: i32, loaded: i32) {
+        wizard.on_button(ButtonEvent::Short);
+        feed(wizard, zero, 30);
+        wizard.on_button(ButtonEvent::Short);
+        feed(wizard, loaded, 30);
+    }
+
+    #[test]
+    fn two_temperatures() {
+        let mut wizard = DriftWizard::new(SampleRate::from_sps(10), Some(200));
+        measure(&mut wizard, 84_000, 504_000);
+        assert_eq!(wizard.step(), DriftStep::ChangeTemperature);
+
+        // Not far enough yet
+        wizard.on_temperature(250);
+        wizard.on_button(ButtonEvent::Short);
+        assert_eq!(wizard.step(), DriftStep::ChangeTemperature);
+
+        wizard.on_temperature(350);
+        wizard.on_button(ButtonEvent::Short);
+        measure(&mut wizard, 84_180, 505_125);
+        assert_eq!(wizard.step(), DriftStep::Confirm);
+        wizard.on_button(ButtonEvent::Short);
+        assert_eq!(wizard.result(), Some(Drift { offset_per_c: 12, scale_ppm_per_c: 150 }));
+    }
+
+    #[test]
+    fn different_mass_is_retried() {
+        let backpack = RefCell::new(LcdBackpack::new(20, 4));
+        let mut lcd = LCD::init(SharedI2c::new(&backpack), VirtualDelay::new());
+        let mut wizard = DriftWizard::new(SampleRate::from_sps(10), Some(200));
+        measure(&mut wizard, 0, 420_000);
+        wizard.on_temperature(350);
+        wizard.render(&mut lcd);
+        assert_eq!(backpack.borrow().lcd().row_text(1).trim_end(), "was 20.0C now 35.0C");
+
+        wizard.on_button(ButtonEvent::Short);
+        measure(&mut wizard, 0, 210_000);
+        assert_eq!(wizard.step(), DriftStep::PlaceMass(1));
+        wizard.render(&mut lcd);
+        assert_eq!(backpack.borrow().lcd().row_text(3).trim_end(), "Same mass as before?");
+
+        wizard.on_button(ButtonEvent::Long);
+        assert!(wizard.is_finished());
+        assert_eq!(wizard.result(), None);
+    }
+}
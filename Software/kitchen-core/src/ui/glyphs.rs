@@ -0,0 +1,28 @@
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::blocking::i2c::{Read, Write};
+
+use crate::drivers::lcd::LCD;
+
+/* Custom characters, stored in the first CGRAM locations of the LCD */
+pub const STABLE_GLYPH: u8 = 0;
+
+pub const STABLE_CHARMAP: [u8; 8] = [
+    0b00000,
+    0b00000,
+    0b00001,
+    0b00010,
+    0b10100,
+    0b01000,
+    0b00000,
+    0b00000,
+];
+
+pub fn load<I2C, D>(lcd: &mut LCD<I2C, D>)
+where
+    I2C: Write + Read,
+    D: DelayUs<u16> + DelayMs<u16>,
+{
+    lcd.createChar(STABLE_GLYPH, &STABLE_CHARMAP);
+    // Writing CGRAM moves the address counter, point it back at the display
+    lcd.set_cursor(0, 0);
+}
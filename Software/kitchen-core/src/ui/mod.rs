@@ -0,0 +1,5 @@
+/* User interaction flows built on the LCD and the push button */
+pub mod calibration_wizard;
+pub mod corner_test;
+pub mod drift_wizard;
+pub mod glyphs;
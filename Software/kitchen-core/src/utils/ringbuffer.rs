@@ -0,0 +1,108 @@
+use core::mem::MaybeUninit;
+
+#[derive(Debug)]
+#[repr(u8)]
+pub enum RingBufferError {
+    Full,
+}
+
+/* Fixed capacity FIFO, safe to share with an interrupt handler through a Mutex */
+#[repr(C)]
+pub struct RingBuffer<T: Copy, const S: usize> {
+    items: [MaybeUninit<T>; S],
+    head: usize,
+    len: usize,
+}
+
+impl<T: Copy, const S: usize> RingBuffer<T, S> {
+    const ELEM: MaybeUninit<T> = MaybeUninit::uninit();
+    const INIT: [MaybeUninit<T>; S] = [Self::ELEM; S]; // important for optimization of `new`
+
+    pub const fn new() -> Self {
+        Self {
+            items: Self::INIT,
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, item: T) -> Result<(), RingBufferError> {
+        /* Check if there is space */
+        if self.len == S {
+            return Err(RingBufferError::Full);
+        }
+        let tail = (self.head + self.len) % S;
+        self.items[tail] = MaybeUninit::new(item);
+        self.len += 1;
+
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let item = unsafe { self.items[self.head].assume_init() };
+        self.head = (self.head + 1) % S;
+        self.len -= 1;
+
+        Some(item)
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == S
+    }
+
+    pub const fn capacity(&self) -> usize {
+        S
+    }
+}
+
+impl<T: Copy, const S: usize> Default for RingBuffer<T, S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fifo_order_across_the_wrap() {
+        let mut buffer: RingBuffer<u8, 3> = RingBuffer::new();
+        assert!(buffer.is_empty());
+        for round in 0..4u8 {
+            buffer.push(round).unwrap();
+            buffer.push(round + 10).unwrap();
+            assert_eq!(buffer.pop(), Some(round));
+            assert_eq!(buffer.pop(), Some(round + 10));
+        }
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn full() {
+        let mut buffer: RingBuffer<u8, 2> = RingBuffer::new();
+        buffer.push(1).unwrap();
+        buffer.push(2).unwrap();
+        assert!(buffer.is_full());
+        assert!(matches!(buffer.push(3), Err(RingBufferError::Full)));
+        assert_eq!(buffer.len(), buffer.capacity());
+        buffer.clear();
+        assert!(buffer.is_empty());
+    }
+}
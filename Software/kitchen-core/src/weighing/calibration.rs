@@ -0,0 +1,245 @@
+use super::weight::{div_round, Weight};
+
+/*
+ * Piecewise linear load cell calibration.
+ *
+ * The table maps net counts (raw reading minus the tare offset) to weight.
+ * Zero counts is always zero weight, so the table holds the points on either
+ * side of that implicit origin, sorted by counts. Readings between two points
+ * are interpolated, readings outside the table are extrapolated using the
+ * outermost segment.
+ */
+
+pub const CALIBRATION_POINTS: usize = 6;
+
+const ORIGIN: CalibrationPoint = CalibrationPoint { counts: 0, weight: Weight::ZERO };
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CalibrationError {
+    Full,
+    InvalidPoint,
+}
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct CalibrationPoint {
+    pub counts: i32,
+    pub weight: Weight,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Calibration<const N: usize> {
+    points: [CalibrationPoint; N],
+    len: usize,
+}
+
+pub type LoadCellCalibration = Calibration<CALIBRATION_POINTS>;
+
+impl<const N: usize> Calibration<N> {
+    /* An empty table reads one count as one gram */
+    pub const fn new() -> Self {
+        Self {
+            points: [ORIGIN; N],
+            len: 0,
+        }
+    }
+
+    /* Single point calibration, `counts` net counts read as `weight` */
+    pub fn from_scale(counts: i32, weight: Weight) -> Self {
+        let mut calibration = Self::new();
+        if calibration.add_point(counts, weight).is_err() {
+            return Self::new();
+        }
+        return calibration;
+    }
+
+    /* Adds a point, replacing any existing point at the same counts */
+    pub fn add_point(&mut self, counts: i32, weight: Weight) -> Result<(), CalibrationError> {
+        if counts == 0 {
+            return Err(CalibrationError::InvalidPoint);
+        }
+
+        let mut candidate = *self;
+        let index = candidate.points().iter().take_while(|p| p.counts < counts).count();
+        let point = CalibrationPoint { counts, weight };
+
+        if index < candidate.len && candidate.points[index].counts == counts {
+            candidate.points[index] = point;
+        } else {
+            if candidate.len == N {
+                return Err(CalibrationError::Full);
+            }
+            candidate.points.copy_within(index..candidate.len, index + 1);
+            candidate.points[index] = point;
+            candidate.len += 1;
+        }
+
+        // The table has to stay invertible, so the weight must strictly
+        // increase or strictly decrease with counts across every segment.
+        let rising = candidate.node(1).weight > candidate.node(0).weight;
+        for i in 1..candidate.len + 1 {
+            let (a, b) = (candidate.node(i - 1), candidate.node(i));
+            if b.weight == a.weight || (b.weight > a.weight) != rising {
+                return Err(CalibrationError::InvalidPoint);
+            }
+        }
+
+        *self = candidate;
+        return Ok(());
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn points(&self) -> &[CalibrationPoint] {
+        return &self.points[..self.len];
+    }
+
+    /* Net counts for `weight` around zero, e.g. the size of a display division */
+    pub fn counts_for(&self, weight: Weight) -> i32 {
+        if self.len == 0 {
+            return weight.grams();
+        }
+        let zero = self.zero_index();
+        let point = if zero < self.len { self.node(zero + 1) } else { self.node(zero - 1) };
+        return div_round(weight.mg() as i64 * point.counts as i64, point.weight.mg() as i64) as i32;
+    }
+
+    pub fn to_weight(&self, counts: i32) -> Weight {
+        if self.len == 0 {
+            return Weight::from_grams(counts);
+        }
+
+        // First segment ending above counts, the outermost ones extrapolate
+        let last = self.len;
+        let mut i = 1;
+        while i < last && self.node(i).counts < counts {
+            i += 1;
+        }
+
+        let (a, b) = (self.node(i - 1), self.node(i));
+        let delta = div_round(
+            (counts - a.counts) as i64 * (b.weight.mg() as i64 - a.weight.mg() as i64),
+            (b.counts - a.counts) as i64,
+        );
+        let mg = (a.weight.mg() as i64 + delta).clamp(i32::MIN as i64, i32::MAX as i64);
+        return Weight::from_mg(mg as i32);
+    }
+
+    /* Net counts that read as `weight`, the inverse of to_weight */
+    pub fn to_counts(&self, weight: Weight) -> i32 {
+        if self.len == 0 {
+            return weight.grams();
+        }
+
+        // The weights run the same way as the counts or the opposite way, see add_point
+        let rising = self.node(1).weight > self.node(0).weight;
+        let last = self.len;
+        let mut i = 1;
+        while i < last && (if rising { self.node(i).weight < weight } else { self.node(i).weight > weight }) {
+            i += 1;
+        }
+
+        let (a, b) = (self.node(i - 1), self.node(i));
+        let delta = div_round(
+            (weight.mg() as i64 - a.weight.mg() as i64) * (b.counts - a.counts) as i64,
+            b.weight.mg() as i64 - a.weight.mg() as i64,
+        );
+        return (a.counts as i64 + delta).clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+    }
+
+    /* Position of the implicit origin among the sorted points */
+    fn zero_index(&self) -> usize {
+        return self.points().iter().take_while(|p| p.counts < 0).count();
+    }
+
+    /* Table points with the origin inserted, len + 1 nodes in total */
+    fn node(&self, i: usize) -> CalibrationPoint {
+        let zero = self.zero_index();
+        if i < zero {
+            return self.points[i];
+        } else if i == zero {
+            return ORIGIN;
+        } else {
+            return self.points[i - 1];
+        }
+    }
+}
+
+impl<const N: usize> Default for Calibration<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_point() -> LoadCellCalibration {
+        let mut calibration = LoadCellCalibration::new();
+        calibration.add_point(100_000, Weight::from_kg(1)).unwrap();
+        calibration.add_point(220_000, Weight::from_kg(2)).unwrap();
+        return calibration;
+    }
+
+    #[test]
+    fn empty_table_is_one_count_per_gram() {
+        let calibration = LoadCellCalibration::new();
+        assert_eq!(calibration.to_weight(1234), Weight::from_grams(1234));
+        assert_eq!(calibration.counts_for(Weight::from_grams(5)), 5);
+    }
+
+    #[test]
+    fn interpolates_and_extrapolates() {
+        let calibration = two_point();
+        assert_eq!(calibration.to_weight(0), Weight::ZERO);
+        assert_eq!(calibration.to_weight(50_000), Weight::from_grams(500));
+        assert_eq!(calibration.to_weight(160_000), Weight::from_grams(1500));
+        assert_eq!(calibration.to_weight(340_000), Weight::from_grams(3000));
+        assert_eq!(calibration.to_weight(-100_000), Weight::from_grams(-1000));
+    }
+
+    #[test]
+    fn to_counts_inverts_to_weight() {
+        let calibration = two_point();
+        for counts in [-100_000, 0, 50_000, 160_000, 340_000] {
+            assert_eq!(calibration.to_counts(calibration.to_weight(counts)), counts);
+        }
+        let falling = LoadCellCalibration::from_scale(-419_000, Weight::from_kg(1));
+        assert_eq!(falling.to_counts(Weight::from_grams(500)), -209_500);
+        assert_eq!(LoadCellCalibration::new().to_counts(Weight::from_grams(7)), 7);
+    }
+
+    #[test]
+    fn counts_for_uses_the_segment_at_zero() {
+        assert_eq!(two_point().counts_for(Weight::from_grams(1)), 100);
+        let calibration = LoadCellCalibration::from_scale(-419_000, Weight::from_kg(1));
+        assert_eq!(calibration.counts_for(Weight::from_grams(1)), -419);
+        assert_eq!(calibration.to_weight(-419_000), Weight::from_kg(1));
+    }
+
+    #[test]
+    fn rejects_invalid_points() {
+        let mut calibration = two_point();
+        assert_eq!(calibration.add_point(0, Weight::from_grams(1)), Err(CalibrationError::InvalidPoint));
+        // Heavier point at fewer counts would make the table non monotonic
+        assert_eq!(calibration.add_point(150_000, Weight::from_kg(3)), Err(CalibrationError::InvalidPoint));
+        assert_eq!(calibration, two_point());
+
+        // Same counts replaces the point
+        calibration.add_point(100_000, Weight::from_grams(900)).unwrap();
+        assert_eq!(calibration.points().len(), 2);
+        assert_eq!(calibration.to_weight(100_000), Weight::from_grams(900));
+    }
+
+    #[test]
+    fn table_is_bounded() {
+        let mut calibration = LoadCellCalibration::new();
+        for i in 1..=CALIBRATION_POINTS as i32 {
+            calibration.add_point(i * 1000, Weight::from_grams(i)).unwrap();
+        }
+        assert_eq!(calibration.add_point(99_000, Weight::from_grams(99)), Err(CalibrationError::Full));
+    }
+}
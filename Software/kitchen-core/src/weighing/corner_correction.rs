@@ -0,0 +1,183 @@
+use super::weight::{div_round, Weight};
+
+/*
+ * Corner-load correction for platforms on several load cells.
+ *
+ * The same mass, placed over each corner in turn, should read the same.
+ * With cells of slightly different sensitivity it does not, so every cell
+ * gets a correction factor. For N cells the test loads N corners, and
+ * loads[i][j] is what cell j read with the mass over corner i. The factors k
+ * solve
+ *
+ *   sum over j of loads[i][j] * k[j] = m   for every corner i
+ *
+ * where m is the known test mass, so the factors correct the span of the
+ * calibration as well: every corner reads the mass, not the mean of the
+ * uncorrected corners. The factors are kept in parts per million and the solve
+ * is integer only: Gaussian elimination on the milligrams with the row
+ * multipliers in Q30 fixed point. Fraction free (Bareiss) elimination would
+ * be exact, but its intermediate minors outgrow i64 at milligram resolution
+ * for four cells.
+ */
+pub const UNITY_PPM: i32 = 1_000_000;
+
+/* Cells of a multi-cell platform, one per corner, the settings keep a factor for each */
+pub const PLATFORM_CELLS: usize = 4;
+
+// A factor outside this range means a broken or badly mounted cell, not a mismatch
+pub const MIN_FACTOR_PPM: i32 = 500_000;
+pub const MAX_FACTOR_PPM: i32 = 2_000_000;
+
+// Multipliers of the elimination are at most one, kept with this many fraction bits
+const FRACTION_BITS: u32 = 30;
+// Keeps the fixed point products within i64, far beyond any kitchen platform
+const MAX_LOAD_MG: i64 = 1 << 28;
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CornerError {
+    /* The corner readings do not tell the cells apart, e.g. the same corner loaded twice */
+    Singular,
+    /* A cell would need a correction beyond MIN_FACTOR_PPM..MAX_FACTOR_PPM */
+    OutOfRange,
+}
+
+/* Per cell factors in ppm from the readings of a corner-load test with `mass` */
+pub fn solve<const N: usize>(loads: &[[Weight; N]; N], mass: Weight) -> Result<[i32; N], CornerError> {
+    let mut matrix = [[0i64; N]; N];
+    let mut largest = 0i64;
+    for (i, corner) in loads.iter().enumerate() {
+        for (j, load) in corner.iter().enumerate() {
+            matrix[i][j] = load.mg() as i64;
+            largest = largest.max(matrix[i][j].abs());
+        }
+    }
+    if largest > MAX_LOAD_MG || mass.mg() as i64 > MAX_LOAD_MG {
+        return Err(CornerError::OutOfRange);
+    }
+    let mut rhs = [mass.mg() as i64; N];
+
+    // Gaussian elimination with partial pivoting
+    let one = 1i64 << FRACTION_BITS;
+    for col in 0..N {
+        let mut pivot = col;
+        for row in col + 1..N {
+            if matrix[row][col].abs() > matrix[pivot][col].abs() {
+                pivot = row;
+            }
+        }
+        if matrix[pivot][col].abs() * 1000 <= largest {
+            return Err(CornerError::Singular);
+        }
+        matrix.swap(col, pivot);
+        rhs.swap(col, pivot);
+        let pivot_row = matrix[col];
+        for row in col + 1..N {
+            // No larger than one thanks to the pivoting
+            let factor = div_round(matrix[row][col] << FRACTION_BITS, pivot_row[col]);
+            for (value, pivot) in matrix[row][col..].iter_mut().zip(pivot_row[col..].iter()) {
+                *value -= div_round(factor * pivot, one);
+            }
+            rhs[row] -= div_round(factor * rhs[col], one);
+        }
+    }
+
+    // Back substitution straight into ppm, checked as it goes so the products stay small
+    let mut ppm = [UNITY_PPM; N];
+    for row in (0..N).rev() {
+        let mut sum = rhs[row] * UNITY_PPM as i64;
+        for k in row + 1..N {
+            sum -= matrix[row][k] * ppm[k] as i64;
+        }
+        let factor = div_round(sum, matrix[row][row]);
+        if factor < MIN_FACTOR_PPM as i64 || factor > MAX_FACTOR_PPM as i64 {
+            return Err(CornerError::OutOfRange);
+        }
+        ppm[row] = factor as i32;
+    }
+    return Ok(ppm);
+}
+
+/* Sum of the cell weights, each scaled by its factor */
+pub fn corrected_total<const N: usize>(weights: &[Weight; N], factors_ppm: &[i32; N]) -> Weight {
+    return weights
+        .iter()
+        .zip(factors_ppm.iter())
+        .map(|(weight, &ppm)| weight.checked_mul_ratio(ppm, UNITY_PPM).unwrap_or(Weight::MAX))
+        .fold(Weight::ZERO, |total, weight| total + weight);
+}
+
+/* Sum of the raw cell readings, each scaled by its factor, for a platform calibrated as a whole */
+pub fn corrected_counts<const N: usize>(raws: &[i32; N], factors_ppm: &[i32; N]) -> i32 {
+    let sum = raws
+        .iter()
+        .zip(factors_ppm.iter())
+        .map(|(&raw, &ppm)| div_round(raw as i64 * ppm as i64, UNITY_PPM as i64))
+        .sum::<i64>();
+    return sum.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grams<const N: usize>(loads: [[i32; N]; N]) -> [[Weight; N]; N] {
+        return loads.map(|corner| corner.map(Weight::from_grams));
+    }
+
+    /* Readings of cells with the given sensitivities, for a mass spread over them by `shares` (percent) */
+    fn readings<const N: usize>(mass_g: i32, shares: [[i32; N]; N], sensitivity_ppm: [i32; N]) -> [[Weight; N]; N] {
+        return shares.map(|corner| {
+            let mut cells = [Weight::ZERO; N];
+            for (j, cell) in cells.iter_mut().enumerate() {
+                *cell = Weight::from_grams(mass_g)
+                    .checked_mul_ratio(corner[j], 100)
+                    .and_then(|w| w.checked_mul_ratio(sensitivity_ppm[j], UNITY_PPM))
+                    .unwrap();
+            }
+            cells
+        });
+    }
+
+    #[test]
+    fn matched_cells_need_no_correction() {
+        let loads = grams([[400, 100], [100, 400]]);
+        assert_eq!(solve(&loads, Weight::from_grams(500)), Ok([UNITY_PPM, UNITY_PPM]));
+    }
+
+    #[test]
+    fn equalises_the_corners() {
+        let shares = [[70, 10, 10, 10], [10, 70, 10, 10], [10, 10, 70, 10], [10, 10, 10, 70]];
+        let sensitivity = [1_020_000, 980_000, 1_000_000, 1_050_000];
+        let loads = readings(1000, shares, sensitivity);
+        let factors = solve(&loads, Weight::from_kg(1)).unwrap();
+
+        // Every corner reads the test mass, each factor undoes its cell's sensitivity
+        let totals = loads.map(|corner| corrected_total(&corner, &factors).mg());
+        assert!(totals.iter().all(|t| (t - 1_000_000).abs() <= 2), "{:?}", totals);
+        for (factor, sensitivity) in factors.iter().zip(sensitivity) {
+            assert!((*factor as i64 * sensitivity as i64 / UNITY_PPM as i64 - UNITY_PPM as i64).abs() <= 2);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_tests() {
+        let mass = Weight::from_grams(500);
+        assert_eq!(solve(&grams([[300, 200], [300, 200]]), mass), Err(CornerError::Singular));
+        assert_eq!(solve(&grams([[0, 0], [0, 0]]), mass), Err(CornerError::Singular));
+        assert_eq!(solve(&grams([[500, 0], [0, 100]]), mass), Err(CornerError::OutOfRange));
+        // A calibration this far off needs redoing, not a correction
+        assert_eq!(solve(&grams([[150, 50], [50, 150]]), mass), Err(CornerError::OutOfRange));
+        let huge = [[Weight::MAX, Weight::ZERO], [Weight::ZERO, Weight::MAX]];
+        assert_eq!(solve(&huge, mass), Err(CornerError::OutOfRange));
+    }
+
+    #[test]
+    fn total() {
+        let weights = [Weight::from_grams(100), Weight::from_grams(-20)];
+        assert_eq!(corrected_total(&weights, &[UNITY_PPM; 2]), Weight::from_grams(80));
+        assert_eq!(corrected_total(&weights, &[1_100_000, 500_000]), Weight::from_grams(100));
+        assert_eq!(corrected_counts(&[40_000, -8_000], &[1_100_000, 500_000]), 40_000);
+        assert_eq!(corrected_counts(&[i32::MAX; 2], &[MAX_FACTOR_PPM; 2]), i32::MAX);
+    }
+}
@@ -0,0 +1,401 @@
+use super::timing::SampleRate;
+
+/*
+ * Streaming filters between raw HX711 samples and the displayed weight.
+ *
+ * Every stage takes one sample and returns one sample, so they can be chained
+ * in any order in a FilterPipeline and reconfigured at runtime. All stages
+ * work on integer counts to stay cheap on the AVR. The filters themselves
+ * count samples, their configuration is in milliseconds and converted with
+ * the sample rate, windows longer than a filter can hold are cut short.
+ */
+
+pub const MOVING_AVERAGE_MAX: usize = 16;
+pub const MEDIAN_MAX: usize = 7;
+pub const PIPELINE_STAGES: usize = 3;
+
+// Fractional bits kept by the IIR stages so small steps are not lost to
+// truncation. The state is an i64, the summed cells of a LoadCellArray go
+// past 2^25 and would overflow an i32 once shifted.
+const IIR_FRACTION_BITS: u8 = 6;
+
+pub trait Filter {
+    fn update(&mut self, input: i32) -> i32;
+    fn reset(&mut self);
+}
+
+pub struct MovingAverage {
+    window: [i32; MOVING_AVERAGE_MAX],
+    len: usize,
+    count: usize,
+    pos: usize,
+    sum: i64,
+}
+
+impl MovingAverage {
+    pub fn new(len: u8) -> Self {
+        Self {
+            window: [0; MOVING_AVERAGE_MAX],
+            len: (len as usize).clamp(1, MOVING_AVERAGE_MAX),
+            count: 0,
+            pos: 0,
+            sum: 0,
+        }
+    }
+}
+
+impl Filter for MovingAverage {
+    fn update(&mut self, input: i32) -> i32 {
+        if self.count == self.len {
+            self.sum -= self.window[self.pos] as i64;
+        } else {
+            self.count += 1;
+        }
+        self.window[self.pos] = input;
+        self.sum += input as i64;
+        self.pos = (self.pos + 1) % self.len;
+
+        (self.sum / self.count as i64) as i32
+    }
+
+    fn reset(&mut self) {
+        self.count = 0;
+        self.pos = 0;
+        self.sum = 0;
+    }
+}
+
+/* Median of the last N samples, rejects single sample spikes */
+pub struct Median {
+    window: [i32; MEDIAN_MAX],
+    len: usize,
+    count: usize,
+    pos: usize,
+}
+
+impl Median {
+    pub fn new(len: u8) -> Self {
+        Self {
+            window: [0; MEDIAN_MAX],
+            len: (len as usize).clamp(1, MEDIAN_MAX),
+            count: 0,
+            pos: 0,
+        }
+    }
+}
+
+impl Filter for Median {
+    fn update(&mut self, input: i32) -> i32 {
+        self.window[self.pos] = input;
+        self.pos = (self.pos + 1) % self.len;
+        self.count = (self.count + 1).min(self.len);
+
+        let mut sorted = [0i32; MEDIAN_MAX];
+        let sorted = &mut sorted[..self.count];
+        sorted.copy_from_slice(&self.window[..self.count]);
+        sorted.sort_unstable();
+
+        sorted[self.count / 2]
+    }
+
+    fn reset(&mut self) {
+        self.count = 0;
+        self.pos = 0;
+    }
+}
+
+/* First order low pass, y += (x - y) / 2^shift */
+pub struct Iir {
+    shift: u8,
+    state: i64,
+    primed: bool,
+}
+
+impl Iir {
+    pub fn new(shift: u8) -> Self {
+        Self {
+            shift: shift.min(16),
+            state: 0,
+            primed: false,
+        }
+    }
+
+    fn step(&mut self, input: i32, shift: u8) -> i32 {
+        let input = (input as i64) << IIR_FRACTION_BITS;
+        if !self.primed {
+            self.state = input;
+            self.primed = true;
+        }
+        self.state += (input - self.state) >> shift;
+
+        (self.state >> IIR_FRACTION_BITS) as i32
+    }
+
+    fn value(&self) -> i32 {
+        (self.state >> IIR_FRACTION_BITS) as i32
+    }
+}
+
+impl Filter for Iir {
+    fn update(&mut self, input: i32) -> i32 {
+        self.step(input, self.shift)
+    }
+
+    fn reset(&mut self) {
+        self.primed = false;
+    }
+}
+
+/*
+ * IIR whose time constant follows the signal: a deviation beyond `threshold`
+ * counts drops straight to the fastest setting, and every `settle_samples`
+ * quiet samples the smoothing is increased by one step up to `max_shift`.
+ */
+pub struct Adaptive {
+    iir: Iir,
+    threshold: i32,
+    min_shift: u8,
+    max_shift: u8,
+    settle_samples: u8,
+    quiet: u8,
+}
+
+impl Adaptive {
+    pub fn new(threshold: i32, min_shift: u8, max_shift: u8, settle_samples: u8) -> Self {
+        Self {
+            iir: Iir::new(min_shift),
+            threshold,
+            min_shift,
+            max_shift: max_shift.max(min_shift),
+            settle_samples,
+            quiet: 0,
+        }
+    }
+}
+
+impl Filter for Adaptive {
+    fn update(&mut self, input: i32) -> i32 {
+        if self.iir.primed && (input as i64 - self.iir.value() as i64).abs() > self.threshold as i64 {
+            self.iir.shift = self.min_shift;
+            self.quiet = 0;
+        } else if self.iir.shift < self.max_shift {
+            self.quiet += 1;
+            if self.quiet >= self.settle_samples {
+                self.iir.shift += 1;
+                self.quiet = 0;
+            }
+        }
+
+        self.iir.step(input, self.iir.shift)
+    }
+
+    fn reset(&mut self) {
+        self.iir.reset();
+        self.iir.shift = self.min_shift;
+        self.quiet = 0;
+    }
+}
+
+/* Times in ms, an IIR time constant becomes the nearest power of two samples */
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum StageConfig {
+    Off,
+    MovingAverage { window_ms: u16 },
+    Median { window_ms: u16 },
+    Iir { time_constant_ms: u16 },
+    Adaptive { threshold: i32, min_time_constant_ms: u16, max_time_constant_ms: u16, settle_ms: u16 },
+}
+
+pub enum Stage {
+    Off,
+    MovingAverage(MovingAverage),
+    Median(Median),
+    Iir(Iir),
+    Adaptive(Adaptive),
+}
+
+impl Stage {
+    pub fn new(config: StageConfig, rate: SampleRate) -> Self {
+        match config {
+            StageConfig::Off => Stage::Off,
+            StageConfig::MovingAverage { window_ms } => {
+                Stage::MovingAverage(MovingAverage::new(rate.window(window_ms)))
+            }
+            StageConfig::Median { window_ms } => Stage::Median(Median::new(rate.window(window_ms))),
+            StageConfig::Iir { time_constant_ms } => Stage::Iir(Iir::new(iir_shift(rate, time_constant_ms))),
+            StageConfig::Adaptive { threshold, min_time_constant_ms, max_time_constant_ms, settle_ms } => {
+                Stage::Adaptive(Adaptive::new(
+                    threshold,
+                    iir_shift(rate, min_time_constant_ms),
+                    iir_shift(rate, max_time_constant_ms),
+                    rate.window(settle_ms),
+                ))
+            }
+        }
+    }
+}
+
+/* Shift whose 2^shift samples come closest to the time constant */
+fn iir_shift(rate: SampleRate, time_constant_ms: u16) -> u8 {
+    let samples = rate.samples(time_constant_ms);
+    let mut shift = 0;
+    // 2^(shift + 1) is closer once it is below 1.5 times the samples
+    while shift < 16 && (1u32 << (shift + 1)) * 2 <= samples * 3 {
+        shift += 1;
+    }
+    return shift;
+}
+
+impl Filter for Stage {
+    fn update(&mut self, input: i32) -> i32 {
+        match self {
+            Stage::Off => input,
+            Stage::MovingAverage(f) => f.update(input),
+            Stage::Median(f) => f.update(input),
+            Stage::Iir(f) => f.update(input),
+            Stage::Adaptive(f) => f.update(input),
+        }
+    }
+
+    fn reset(&mut self) {
+        match self {
+            Stage::Off => {}
+            Stage::MovingAverage(f) => f.reset(),
+            Stage::Median(f) => f.reset(),
+            Stage::Iir(f) => f.reset(),
+            Stage::Adaptive(f) => f.reset(),
+        }
+    }
+}
+
+/* Spike rejection followed by adaptive smoothing, at 10 SPS 3 samples and 1 to 16 samples */
+pub const DEFAULT_PIPELINE: [StageConfig; PIPELINE_STAGES] = [
+    StageConfig::Median { window_ms: 300 },
+    StageConfig::Adaptive { threshold: 2000, min_time_constant_ms: 0, max_time_constant_ms: 1600, settle_ms: 400 },
+    StageConfig::Off,
+];
+
+pub struct FilterPipeline {
+    stages: [Stage; PIPELINE_STAGES],
+}
+
+impl FilterPipeline {
+    pub fn new(config: &[StageConfig; PIPELINE_STAGES], rate: SampleRate) -> Self {
+        Self {
+            stages: config.map(|stage| Stage::new(stage, rate)),
+        }
+    }
+
+    /* Replaces the stages, the filter history is lost */
+    pub fn configure(&mut self, config: &[StageConfig; PIPELINE_STAGES], rate: SampleRate) {
+        self.stages = config.map(|stage| Stage::new(stage, rate));
+    }
+}
+
+impl Filter for FilterPipeline {
+    fn update(&mut self, input: i32) -> i32 {
+        self.stages.iter_mut().fold(input, |value, stage| stage.update(value))
+    }
+
+    fn reset(&mut self) {
+        self.stages.iter_mut().for_each(|stage| stage.reset());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::hx711::HX711_MAX;
+    use crate::weighing::corner_correction::{corrected_counts, MAX_FACTOR_PPM};
+
+    #[test]
+    fn moving_average() {
+        let mut filter = MovingAverage::new(4);
+        assert_eq!(filter.update(4), 4);
+        assert_eq!(filter.update(8), 6);
+        for _ in 0..4 {
+            filter.update(100);
+        }
+        assert_eq!(filter.update(100), 100);
+        filter.reset();
+        assert_eq!(filter.update(-8), -8);
+    }
+
+    #[test]
+    fn median_rejects_spikes() {
+        let mut filter = Median::new(3);
+        filter.update(10);
+        filter.update(10);
+        assert_eq!(filter.update(5000), 10);
+        assert_eq!(filter.update(11), 11);
+    }
+
+    #[test]
+    fn iir_converges() {
+        let mut filter = Iir::new(2);
+        assert_eq!(filter.update(0), 0);
+        let mut value = 0;
+        for _ in 0..50 {
+            value = filter.update(1000);
+        }
+        assert!((995..=1000).contains(&value));
+    }
+
+    #[test]
+    fn full_scale_cell_array() {
+        // Four cells at the rails with the largest corner factors
+        let full = corrected_counts(&[HX711_MAX; 4], &[MAX_FACTOR_PPM; 4]);
+        assert!(full > 1 << 25);
+        for input in [full, -full] {
+            let mut pipeline = FilterPipeline::new(&DEFAULT_PIPELINE, SampleRate::from_sps(10));
+            let mut iir = Iir::new(4);
+            iir.update(-input);
+            let mut value = 0;
+            for _ in 0..400 {
+                pipeline.update(input);
+                value = iir.update(input);
+            }
+            assert_eq!(pipeline.update(input), input);
+            assert!((value as i64 - input as i64).abs() <= 16);
+        }
+    }
+
+    #[test]
+    fn adaptive_follows_steps_immediately() {
+        let mut filter = Adaptive::new(100, 0, 4, 2);
+        for _ in 0..20 {
+            filter.update(0);
+        }
+        // A step beyond the threshold drops to no smoothing
+        assert_eq!(filter.update(10_000), 10_000);
+    }
+
+    #[test]
+    fn pipeline_off_passes_through() {
+        let rate = SampleRate::from_sps(10);
+        let mut pipeline = FilterPipeline::new(&[StageConfig::Off; PIPELINE_STAGES], rate);
+        assert_eq!(pipeline.update(-1234), -1234);
+
+        pipeline.configure(&DEFAULT_PIPELINE, rate);
+        pipeline.update(0);
+        pipeline.update(0);
+        assert_eq!(pipeline.update(1_000_000), 0);
+    }
+
+    #[test]
+    fn windows_follow_the_rate() {
+        let config = StageConfig::MovingAverage { window_ms: 200 };
+        for (sps, samples) in [(10, 2), (80, 16)] {
+            let mut stage = Stage::new(config, SampleRate::from_sps(sps));
+            stage.update(0);
+            (1..samples).for_each(|_| assert!(stage.update(1600) < 1600));
+            assert_eq!(stage.update(1600), 1600);
+        }
+
+        assert_eq!(iir_shift(SampleRate::from_sps(10), 0), 0);
+        assert_eq!(iir_shift(SampleRate::from_sps(10), 1600), 4);
+        assert_eq!(iir_shift(SampleRate::from_sps(80), 1600), 7);
+        assert_eq!(iir_shift(SampleRate::from_sps(80), 500), 5);
+    }
+}
@@ -0,0 +1,287 @@
+#[cfg(feature = "ufmt")]
+use ufmt::{uDisplay, uWrite, Formatter};
+
+use super::units::{Unit, UnitReading};
+use super::weight::{div_round, Weight};
+
+/*
+ * Fixed decimal text output.
+ *
+ * ufmt has no floats, so values are printed from their integer form: a
+ * number of the last shown digit plus the count of decimals. Everything is
+ * first rendered into a small field buffer and then padded, so `uwrite!`
+ * produces the same bytes on the UART as on the LCD.
+ */
+
+// One LCD row, the longest value is "-13421772:15.9" for lb:oz
+const FIELD_MAX: usize = 20;
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Sign {
+    /* "-" for negative values only */
+    Negative,
+    /* "+" or "-", for deltas */
+    Always,
+}
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Style {
+    /* Minimum number of characters, 0 for no padding */
+    pub width: u8,
+    pub sign: Sign,
+    pub align: Align,
+}
+
+impl Style {
+    pub const PLAIN: Style = Style { width: 0, sign: Sign::Negative, align: Align::Right };
+
+    pub const fn right(width: u8) -> Self {
+        Style { width, sign: Sign::Negative, align: Align::Right }
+    }
+
+    pub const fn left(width: u8) -> Self {
+        Style { width, sign: Sign::Negative, align: Align::Left }
+    }
+
+    pub const fn with_sign(self) -> Self {
+        Style { sign: Sign::Always, ..self }
+    }
+}
+
+/* `value` in units of the last digit, e.g. 12345 with 2 decimals is 123.45 */
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Fixed {
+    pub value: i32,
+    pub decimals: u8,
+}
+
+impl Fixed {
+    pub const fn new(value: i32, decimals: u8) -> Self {
+        Fixed { value, decimals }
+    }
+}
+
+/* Anything that can be rendered into a field */
+pub trait Render {
+    fn render(&self, field: &mut Field, sign: Sign);
+
+    fn styled(self, style: Style) -> Styled<Self>
+    where
+        Self: Sized,
+    {
+        return Styled { value: self, style };
+    }
+}
+
+pub struct Styled<T> {
+    value: T,
+    style: Style,
+}
+
+impl<T: Render> Styled<T> {
+    /* The padded text, clipped to FIELD_MAX */
+    pub fn to_field(&self) -> Field {
+        let mut value = Field::new();
+        self.value.render(&mut value, self.style.sign);
+
+        let mut field = Field::new();
+        let padding = (self.style.width as usize).saturating_sub(value.len);
+        if self.style.align == Align::Right {
+            (0..padding).for_each(|_| field.push(b' '));
+        }
+        field.push_str(value.as_str());
+        if self.style.align == Align::Left {
+            (0..padding).for_each(|_| field.push(b' '));
+        }
+        return field;
+    }
+}
+
+#[cfg(feature = "ufmt")]
+impl<T: Render> uDisplay for Styled<T> {
+    fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        f.write_str(self.to_field().as_str())
+    }
+}
+
+/* Fixed size text buffer, anything beyond FIELD_MAX is dropped */
+pub struct Field {
+    buf: [u8; FIELD_MAX],
+    len: usize,
+}
+
+impl Field {
+    fn new() -> Self {
+        Field { buf: [0; FIELD_MAX], len: 0 }
+    }
+
+    pub fn push(&mut self, byte: u8) {
+        if self.len < FIELD_MAX {
+            self.buf[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    pub fn push_str(&mut self, s: &str) {
+        s.bytes().for_each(|b| self.push(b));
+    }
+
+    pub fn push_sign(&mut self, negative: bool, sign: Sign) {
+        if negative {
+            self.push(b'-');
+        } else if sign == Sign::Always {
+            self.push(b'+');
+        }
+    }
+
+    /* Unsigned value with `decimals` digits after the point, leading "0." as needed */
+    pub fn push_fixed(&mut self, value: u32, decimals: u8) {
+        let mut digits = [0u8; 10];
+        let mut count = 0;
+        let mut rest = value;
+        while rest > 0 || count <= decimals as usize {
+            digits[count] = b'0' + (rest % 10) as u8;
+            rest /= 10;
+            count += 1;
+            if count == digits.len() {
+                break;
+            }
+        }
+        for i in (0..count).rev() {
+            self.push(digits[i]);
+            if i == decimals as usize && i > 0 {
+                self.push(b'.');
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ASCII is ever pushed
+        return core::str::from_utf8(&self.buf[..self.len]).unwrap_or("");
+    }
+}
+
+impl Render for Fixed {
+    fn render(&self, field: &mut Field, sign: Sign) {
+        field.push_sign(self.value < 0, sign);
+        field.push_fixed(self.value.unsigned_abs(), self.decimals);
+    }
+}
+
+/* lb:oz is shown as pounds, a colon and ounces with one decimal */
+impl Render for UnitReading {
+    fn render(&self, field: &mut Field, sign: Sign) {
+        match *self {
+            UnitReading::Decimal { value, decimals } => Fixed::new(value, decimals).render(field, sign),
+            UnitReading::PoundsOunces { negative, pounds, tenth_ounces } => {
+                field.push_sign(negative, sign);
+                field.push_fixed(pounds.unsigned_abs(), 0);
+                field.push(b':');
+                if tenth_ounces < 100 {
+                    field.push(b'0');
+                }
+                field.push_fixed(tenth_ounces.unsigned_abs(), 1);
+            }
+        }
+    }
+}
+
+impl Render for Unit {
+    fn render(&self, field: &mut Field, _sign: Sign) {
+        field.push_str(self.symbol());
+    }
+}
+
+/* Grams with all three decimals, see `Weight::in_grams` for fewer */
+impl Render for Weight {
+    fn render(&self, field: &mut Field, sign: Sign) {
+        self.in_grams(3).render(field, sign);
+    }
+}
+
+impl Weight {
+    /* Grams with 0 to 3 decimals, rounded half away from zero */
+    pub fn in_grams(&self, decimals: u8) -> Fixed {
+        let decimals = decimals.min(3);
+        let divisor = 10i64.pow(3 - decimals as u32);
+        return Fixed::new(div_round(self.mg() as i64, divisor) as i32, decimals);
+    }
+}
+
+#[cfg(feature = "ufmt")]
+macro_rules! plain_display {
+    ($($t:ty),*) => {
+        $(
+            impl uDisplay for $t {
+                fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+                where
+                    W: uWrite + ?Sized,
+                {
+                    return uDisplay::fmt(&(*self).styled(Style::PLAIN), f);
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "ufmt")]
+plain_display!(Fixed, UnitReading, Unit, Weight);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text<T: Render>(value: T, style: Style) -> std::string::String {
+        return std::string::String::from(value.styled(style).to_field().as_str());
+    }
+
+    #[test]
+    fn fixed_decimals() {
+        assert_eq!(text(Fixed::new(12345, 2), Style::PLAIN), "123.45");
+        assert_eq!(text(Fixed::new(7, 3), Style::PLAIN), "0.007");
+        assert_eq!(text(Fixed::new(-5, 1), Style::PLAIN), "-0.5");
+        assert_eq!(text(Fixed::new(0, 0), Style::PLAIN), "0");
+        assert_eq!(text(Weight::MIN, Style::PLAIN), "-2147483.648");
+    }
+
+    #[test]
+    fn alignment_and_sign() {
+        assert_eq!(text(Fixed::new(-42, 1), Style::right(8)), "    -4.2");
+        assert_eq!(text(Fixed::new(42, 1), Style::left(8).with_sign()), "+4.2    ");
+        assert_eq!(text(Unit::Kilograms, Style::left(5)), "kg   ");
+        assert_eq!(text(Fixed::new(123456, 0), Style::right(3)), "123456");
+    }
+
+    #[test]
+    fn pounds_and_ounces() {
+        let reading = UnitReading::PoundsOunces { negative: false, pounds: 2, tenth_ounces: 53 };
+        assert_eq!(text(reading, Style::PLAIN), "2:05.3");
+        let reading = UnitReading::PoundsOunces { negative: true, pounds: 0, tenth_ounces: 1 };
+        assert_eq!(text(reading, Style::PLAIN), "-0:00.1");
+    }
+
+    #[test]
+    fn grams_round_to_decimals() {
+        assert_eq!(Weight::from_mg(1250).in_grams(1), Fixed::new(13, 1));
+        assert_eq!(Weight::from_mg(-1250).in_grams(0), Fixed::new(-1, 0));
+        assert_eq!(Weight::from_mg(1250).in_grams(9), Fixed::new(1250, 3));
+    }
+
+    #[test]
+    fn field_is_clipped() {
+        assert_eq!(text(Fixed::new(1, 0), Style::right(40)).len(), FIELD_MAX);
+    }
+}
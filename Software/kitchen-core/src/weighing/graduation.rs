@@ -0,0 +1,174 @@
+use super::weight::{div_round, Weight};
+
+/*
+ * Display quantisation.
+ *
+ * The weight is shown in whole display divisions (d). A new value is only
+ * taken once the reading has moved more than ½ d plus the hysteresis away
+ * from the value on display, which stops the last digit from flickering
+ * between two neighbouring steps. Anything within ±½ d of zero is shown as
+ * exactly zero.
+ */
+
+/* Size of one display division, `mg / per` milligrams. Imperial divisions are not whole milligrams */
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DivisionSize {
+    pub mg: i32,
+    pub per: i32,
+}
+
+impl DivisionSize {
+    pub const fn from_weight(weight: Weight) -> Self {
+        Self { mg: weight.mg(), per: 1 }
+    }
+
+    /* The division rounded to the nearest milligram */
+    pub const fn weight(&self) -> Weight {
+        Weight::from_mg(div_round(self.mg as i64, self.per as i64) as i32)
+    }
+
+    /* Number of whole divisions in `weight`, rounded half away from zero */
+    pub const fn steps(&self, weight: Weight) -> i32 {
+        div_round(weight.mg() as i64 * self.per as i64, self.mg as i64) as i32
+    }
+}
+
+/* Display resolutions offered when weighing in grams */
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Division {
+    Tenth,
+    Half,
+    One,
+    Two,
+}
+
+impl Division {
+    pub const ALL: [Division; 4] = [Division::Tenth, Division::Half, Division::One, Division::Two];
+
+    pub const fn weight(&self) -> Weight {
+        match self {
+            Division::Tenth => Weight::from_mg(100),
+            Division::Half => Weight::from_mg(500),
+            Division::One => Weight::from_grams(1),
+            Division::Two => Weight::from_grams(2),
+        }
+    }
+
+    /* Number of decimals needed to show a multiple of this division */
+    pub fn decimals(&self) -> u8 {
+        match self {
+            Division::Tenth | Division::Half => 1,
+            Division::One | Division::Two => 0,
+        }
+    }
+
+    /* One division, in units of the last shown digit */
+    pub fn step(&self) -> i32 {
+        match self {
+            Division::Tenth | Division::One => 1,
+            Division::Two => 2,
+            Division::Half => 5,
+        }
+    }
+
+    pub fn id(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn from_id(id: u8) -> Option<Division> {
+        Self::ALL.get(id as usize).copied()
+    }
+}
+
+// Extra distance, in percent of a division, needed to leave the shown step
+pub const DEFAULT_HYSTERESIS_PERCENT: u8 = 20;
+
+pub struct Graduation {
+    division: DivisionSize,
+    hysteresis_percent: u8,
+    shown: i32,
+}
+
+impl Graduation {
+    pub fn new(division: DivisionSize, hysteresis_percent: u8) -> Self {
+        Self {
+            division,
+            hysteresis_percent: hysteresis_percent.min(50),
+            shown: 0,
+        }
+    }
+
+    pub fn division(&self) -> DivisionSize {
+        return self.division;
+    }
+
+    pub fn set_division(&mut self, division: DivisionSize) {
+        self.division = division;
+        self.shown = 0;
+    }
+
+    /* Quantises a weight, returns the number of divisions to show */
+    pub fn update(&mut self, weight: Weight) -> i32 {
+        // Everything in 1/per milligrams so imperial divisions stay exact
+        let d = self.division.mg as i64;
+        let value = weight.mg() as i64 * self.division.per as i64;
+
+        // Zero band
+        if 2 * value.abs() < d {
+            self.shown = 0;
+            return 0;
+        }
+
+        let threshold = d * (50 + self.hysteresis_percent as i64) / 100;
+        let distance = value - self.shown as i64 * d;
+        if distance.abs() > threshold {
+            self.shown = self.division.steps(weight);
+        }
+        return self.shown;
+    }
+
+    /* Number of divisions on display */
+    pub fn shown(&self) -> i32 {
+        return self.shown;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hysteresis_between_steps() {
+        let mut graduation = Graduation::new(DivisionSize::from_weight(Division::One.weight()), 20);
+        let shown: [i32; 7] = [300, 600, 800, 1400, 1800, 1600, 1250].map(|mg| graduation.update(Weight::from_mg(mg)));
+        assert_eq!(shown, [0, 0, 1, 1, 2, 2, 1]);
+        assert_eq!(graduation.shown(), 1);
+    }
+
+    #[test]
+    fn zero_band() {
+        let mut graduation = Graduation::new(DivisionSize::from_weight(Division::Two.weight()), 20);
+        assert_eq!(graduation.update(Weight::from_grams(10)), 5);
+        assert_eq!(graduation.update(Weight::from_mg(-999)), 0);
+        assert_eq!(graduation.update(Weight::from_grams(-3)), -2);
+    }
+
+    #[test]
+    fn fractional_divisions() {
+        let tenth_ounce = DivisionSize { mg: 28_349_523, per: 10_000 };
+        assert_eq!(tenth_ounce.weight(), Weight::from_mg(2835));
+        assert_eq!(tenth_ounce.steps(Weight::from_mg(1000)), 0);
+        assert_eq!(tenth_ounce.steps(Weight::from_mg(1500)), 1);
+        assert_eq!(tenth_ounce.steps(Weight::from_mg(2835)), 1);
+    }
+
+    #[test]
+    fn division_ids_round_trip() {
+        for division in Division::ALL {
+            assert_eq!(Division::from_id(division.id()), Some(division));
+        }
+        assert_eq!(Division::from_id(4), None);
+    }
+}
@@ -0,0 +1,13 @@
+/* Load cell signal processing, independent of the hardware */
+pub mod calibration;
+pub mod corner_correction;
+pub mod filter;
+pub mod stability;
+pub mod timing;
+pub mod zero_tracking;
+pub mod overload;
+pub mod temperature;
+pub mod graduation;
+pub mod weight;
+pub mod units;
+pub mod format;
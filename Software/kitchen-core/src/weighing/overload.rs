@@ -0,0 +1,116 @@
+use crate::weighing::weight::Weight;
+
+/*
+ * Overload and underload of the load cell.
+ *
+ * Works on the gross load, the weight above the calibration zero whatever the
+ * tare, so a tared pot still counts towards the capacity. Past the capacity
+ * plus the margin the load is Over, below minus the margin (the platform is
+ * lifted or caught on something) it is Under. Either state only ends once the
+ * load is back within half the margin, so a load right at the limit does not
+ * flicker between the reading and "OL".
+ */
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct LoadLimits {
+    /* Rated capacity of the cell, e.g. 5 kg for the TAL220B */
+    pub capacity: Weight,
+    pub margin: Weight,
+}
+
+impl LoadLimits {
+    /* A capacity above zero and a margin below it */
+    pub fn is_valid(&self) -> bool {
+        return self.capacity > Weight::ZERO && self.margin >= Weight::ZERO && self.margin < self.capacity;
+    }
+}
+
+pub const DEFAULT_LIMITS: LoadLimits = LoadLimits {
+    capacity: Weight::from_kg(5),
+    margin: Weight::from_grams(100),
+};
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LoadState {
+    Normal,
+    Over,
+    Under,
+}
+
+pub struct OverloadDetector {
+    limits: LoadLimits,
+    state: LoadState,
+}
+
+impl OverloadDetector {
+    pub fn new(limits: LoadLimits) -> Self {
+        Self { limits, state: LoadState::Normal }
+    }
+
+    pub fn set_limits(&mut self, limits: LoadLimits) {
+        self.limits = limits;
+    }
+
+    pub fn state(&self) -> LoadState {
+        return self.state;
+    }
+
+    /* Gross load of one sample, returns the new state when it changed */
+    pub fn update(&mut self, gross: Weight) -> Option<LoadState> {
+        let margin = self.limits.margin;
+        let half = Weight::from_mg(margin.mg() / 2);
+        let over = self.limits.capacity + margin;
+
+        let state = match self.state {
+            _ if gross > over => LoadState::Over,
+            _ if gross < -margin => LoadState::Under,
+            LoadState::Over if gross > self.limits.capacity + half => LoadState::Over,
+            LoadState::Under if gross < -half => LoadState::Under,
+            _ => LoadState::Normal,
+        };
+        if state == self.state {
+            return None;
+        }
+        self.state = state;
+        return Some(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn over_and_under_with_hysteresis() {
+        let mut detector = OverloadDetector::new(DEFAULT_LIMITS);
+        assert_eq!(detector.update(Weight::from_grams(5100)), None);
+        assert_eq!(detector.update(Weight::from_grams(5101)), Some(LoadState::Over));
+        assert_eq!(detector.update(Weight::from_grams(5060)), None);
+        assert_eq!(detector.update(Weight::from_grams(5050)), Some(LoadState::Normal));
+
+        assert_eq!(detector.update(Weight::from_grams(-100)), None);
+        assert_eq!(detector.update(Weight::from_grams(-150)), Some(LoadState::Under));
+        assert_eq!(detector.update(Weight::from_grams(-60)), None);
+        assert_eq!(detector.update(Weight::from_grams(-50)), Some(LoadState::Normal));
+
+        // Straight from one to the other
+        detector.update(Weight::from_grams(-500));
+        assert_eq!(detector.update(Weight::from_kg(6)), Some(LoadState::Over));
+        assert_eq!(detector.state(), LoadState::Over);
+    }
+
+    #[test]
+    fn valid_limits() {
+        assert!(DEFAULT_LIMITS.is_valid());
+        let limits = |capacity, margin| LoadLimits {
+            capacity: Weight::from_grams(capacity),
+            margin: Weight::from_grams(margin),
+        };
+        assert!(limits(100, 0).is_valid());
+        assert!(!limits(0, 0).is_valid());
+        assert!(!limits(-5000, 100).is_valid());
+        assert!(!limits(5000, -100).is_valid());
+        assert!(!limits(100, 100).is_valid());
+    }
+}
@@ -0,0 +1,141 @@
+use super::timing::SampleRate;
+
+/*
+ * Motion detection over the (filtered) sample stream.
+ *
+ * The reading is stable once it stayed within ±band_divisions display
+ * divisions of the first sample of the run for `window_ms`. The detector works
+ * in counts, so the size of one display division in counts has to be set from
+ * the calibration, and the window is counted in samples at the sample rate.
+ */
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Motion {
+    Moving,
+    Stable,
+}
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct StabilityConfig {
+    pub band_divisions: u8,
+    pub window_ms: u16,
+}
+
+pub const DEFAULT_STABILITY: StabilityConfig = StabilityConfig {
+    band_divisions: 1,
+    window_ms: 800,
+};
+
+pub struct StabilityDetector {
+    config: StabilityConfig,
+    rate: SampleRate,
+    /* The window in samples */
+    window: u8,
+    division_counts: i32,
+    anchor: i32,
+    count: u8,
+    state: Motion,
+}
+
+impl StabilityDetector {
+    pub fn new(config: StabilityConfig, division_counts: i32, rate: SampleRate) -> Self {
+        Self {
+            config,
+            rate,
+            window: rate.window(config.window_ms),
+            division_counts: division_counts.abs().max(1),
+            anchor: 0,
+            count: 0,
+            state: Motion::Moving,
+        }
+    }
+
+    pub fn set_config(&mut self, config: StabilityConfig) {
+        self.config = config;
+        self.window = self.rate.window(config.window_ms);
+        self.reset();
+    }
+
+    pub fn set_rate(&mut self, rate: SampleRate) {
+        self.rate = rate;
+        self.set_config(self.config);
+    }
+
+    /* Size of one display division in counts */
+    pub fn set_division_counts(&mut self, division_counts: i32) {
+        self.division_counts = division_counts.abs().max(1);
+    }
+
+    pub fn update(&mut self, counts: i32) -> Motion {
+        let band = self.division_counts * self.config.band_divisions as i32;
+
+        if self.count == 0 || (counts - self.anchor).abs() > band {
+            self.anchor = counts;
+            self.count = 1;
+            self.state = Motion::Moving;
+        } else if self.count < self.window {
+            self.count += 1;
+        }
+
+        if self.count >= self.window {
+            self.state = Motion::Stable;
+        }
+        return self.state;
+    }
+
+    pub fn state(&self) -> Motion {
+        return self.state;
+    }
+
+    pub fn is_stable(&self) -> bool {
+        return self.state == Motion::Stable;
+    }
+
+    pub fn reset(&mut self) {
+        self.count = 0;
+        self.state = Motion::Moving;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: SampleRate = SampleRate::from_sps(10);
+
+    #[test]
+    fn stable_after_a_full_window() {
+        let mut detector = StabilityDetector::new(StabilityConfig { band_divisions: 1, window_ms: 400 }, 10, RATE);
+        assert_eq!(detector.update(1000), Motion::Moving);
+        assert_eq!(detector.update(1010), Motion::Moving);
+        assert_eq!(detector.update(990), Motion::Moving);
+        assert_eq!(detector.update(1005), Motion::Stable);
+        assert!(detector.is_stable());
+    }
+
+    #[test]
+    fn leaving_the_band_restarts_the_window() {
+        let mut detector = StabilityDetector::new(StabilityConfig { band_divisions: 1, window_ms: 300 }, -10, RATE);
+        for _ in 0..3 {
+            detector.update(0);
+        }
+        assert_eq!(detector.update(11), Motion::Moving);
+        assert_eq!(detector.update(11), Motion::Moving);
+        assert_eq!(detector.update(11), Motion::Stable);
+
+        detector.reset();
+        assert_eq!(detector.state(), Motion::Moving);
+    }
+
+    #[test]
+    fn window_follows_the_rate() {
+        let mut detector = StabilityDetector::new(StabilityConfig { band_divisions: 1, window_ms: 300 }, 10, RATE);
+        detector.set_rate(SampleRate::from_sps(80));
+        for _ in 0..23 {
+            assert_eq!(detector.update(0), Motion::Moving);
+        }
+        assert_eq!(detector.update(0), Motion::Stable);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::weighing::weight::div_round;
+
+/*
+ * Temperature compensation of zero and span.
+ *
+ * Next to the oven the load cell and the HX711 drift: the empty platform reads
+ * `offset_per_c` counts more for every °C, and a load reads `scale_ppm_per_c`
+ * ppm more. Drift::learn fits both from the same test mass measured at two
+ * temperatures, TemperatureCompensation undoes them for every reading, back to
+ * the temperature the calibration was captured at. Temperatures are in tenths
+ * of °C, as the ATmega internal sensor reports them. The sensor is only good
+ * to about ±10 °C, so the reference has to be a reading of the same sensor:
+ * without one the compensation stays off.
+ */
+
+// The two temperatures have to be at least this far apart for a usable slope
+pub const MIN_DELTA_TENTHS: i16 = 100;
+// More span drift than this is a broken cell or a different mass, not temperature
+const MAX_SCALE_PPM_PER_C: i32 = 2000;
+// The test mass has to move the reading by at least this many counts
+const MIN_SPAN_COUNTS: i32 = 1000;
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DriftError {
+    TooClose,
+    NoLoad,
+    OutOfRange,
+}
+
+/* Zero and loaded raw readings at one temperature */
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct DriftPoint {
+    pub tenths: i16,
+    pub zero: i32,
+    pub loaded: i32,
+}
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Drift {
+    pub offset_per_c: i32,
+    pub scale_ppm_per_c: i32,
+}
+
+impl Drift {
+    pub const NONE: Drift = Drift { offset_per_c: 0, scale_ppm_per_c: 0 };
+
+    pub fn learn(a: DriftPoint, b: DriftPoint) -> Result<Drift, DriftError> {
+        let delta = b.tenths as i64 - a.tenths as i64;
+        if delta.abs() < MIN_DELTA_TENTHS as i64 {
+            return Err(DriftError::TooClose);
+        }
+        let (span_a, span_b) = (a.loaded - a.zero, b.loaded - b.zero);
+        if span_a.abs() < MIN_SPAN_COUNTS || span_b.abs() < MIN_SPAN_COUNTS {
+            return Err(DriftError::NoLoad);
+        }
+
+        let offset_per_c = div_round((b.zero as i64 - a.zero as i64) * 10, delta);
+        let scale_ppm_per_c = div_round((span_b as i64 - span_a as i64) * 10_000_000, span_a as i64 * delta);
+        if scale_ppm_per_c.abs() > MAX_SCALE_PPM_PER_C as i64 || offset_per_c.abs() > i32::MAX as i64 {
+            return Err(DriftError::OutOfRange);
+        }
+        return Ok(Drift { offset_per_c: offset_per_c as i32, scale_ppm_per_c: scale_ppm_per_c as i32 });
+    }
+}
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TemperatureCompensation {
+    /* Temperature the calibration was captured at, None if there was no reading */
+    pub reference_tenths: Option<i16>,
+    pub drift: Drift,
+}
+
+impl TemperatureCompensation {
+    pub const fn new(reference_tenths: Option<i16>) -> Self {
+        return Self { reference_tenths, drift: Drift::NONE };
+    }
+
+    /* Raw reading as it would be at the reference temperature, `zero` is the calibration zero */
+    pub fn apply(&self, raw: i32, zero: i32, tenths: i16) -> i32 {
+        let reference = match self.reference_tenths {
+            Some(reference) if self.drift != Drift::NONE => reference,
+            _ => return raw,
+        };
+        let delta = tenths as i64 - reference as i64;
+        let net = raw as i64 - zero as i64 - div_round(self.drift.offset_per_c as i64 * delta, 10);
+        let scale_ppm = 1_000_000 + div_round(self.drift.scale_ppm_per_c as i64 * delta, 10);
+        let corrected = zero as i64 + div_round(net * 1_000_000, scale_ppm.max(1));
+        return corrected.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+    }
+}
+
+impl Default for TemperatureCompensation {
+    fn default() -> Self {
+        return Self::new(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Zero rises 12 counts/°C, span 150 ppm/°C
+    fn reading(grams: i32, tenths: i16) -> i32 {
+        let delta = (tenths - 200) as f64 / 10.0;
+        let span = grams as f64 * 420.0 * (1.0 + 150e-6 * delta);
+        return (84_000.0 + 12.0 * delta + span) as i32;
+    }
+
+    #[test]
+    fn learns_and_undoes_the_drift() {
+        let a = DriftPoint { tenths: 200, zero: reading(0, 200), loaded: reading(1000, 200) };
+        let b = DriftPoint { tenths: 350, zero: reading(0, 350), loaded: reading(1000, 350) };
+        let drift = Drift::learn(a, b).unwrap();
+        assert_eq!(drift, Drift { offset_per_c: 12, scale_ppm_per_c: 150 });
+
+        // Calibrated at 20 °C, weighing at 40 °C
+        let compensation = TemperatureCompensation { reference_tenths: Some(200), drift };
+        let zero = reading(0, 200);
+        assert!((compensation.apply(reading(0, 400), zero, 400) - zero).abs() <= 1);
+        assert!((compensation.apply(reading(2000, 400), zero, 400) - reading(2000, 200)).abs() <= 2);
+        assert_eq!(TemperatureCompensation::default().apply(123, 0, 400), 123);
+        // Calibrated without a temperature reading, nothing to correct back to
+        let unknown = TemperatureCompensation { reference_tenths: None, drift };
+        assert_eq!(unknown.apply(reading(2000, 400), zero, 400), reading(2000, 400));
+    }
+
+    #[test]
+    fn rejects_bad_points() {
+        let a = DriftPoint { tenths: 200, zero: 0, loaded: 420_000 };
+        let close = DriftPoint { tenths: 250, ..a };
+        assert_eq!(Drift::learn(a, close), Err(DriftError::TooClose));
+        let empty = DriftPoint { tenths: 350, zero: 0, loaded: 10 };
+        assert_eq!(Drift::learn(a, empty), Err(DriftError::NoLoad));
+        // A 500 g mass instead of the 1 kg one
+        let other = DriftPoint { tenths: 350, zero: 0, loaded: 210_000 };
+        assert_eq!(Drift::learn(a, other), Err(DriftError::OutOfRange));
+    }
+}
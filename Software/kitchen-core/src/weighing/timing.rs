@@ -0,0 +1,286 @@
+/*
+ * Sample rate of the converter and the timing of the samples as they arrive.
+ *
+ * Filter, stability and zero tracking windows are given in milliseconds and
+ * turned into sample counts through the configured SampleRate, so they mean
+ * the same at 10 and 80 SPS. SampleTiming checks the configuration against
+ * the timestamps of the samples: mean interval, jitter and missed samples.
+ * Conversions the driver drops on purpose while it settles after a switch are
+ * expected, they are neither measured nor missed.
+ */
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SampleRate {
+    period_us: u32,
+}
+
+impl SampleRate {
+    pub const fn from_sps(sps: u16) -> Self {
+        let sps = if sps == 0 { 1 } else { sps };
+        return Self { period_us: 1_000_000 / sps as u32 };
+    }
+
+    pub const fn period_us(&self) -> u32 {
+        return self.period_us;
+    }
+
+    pub fn sps(&self) -> u16 {
+        return ((1_000_000 + self.period_us / 2) / self.period_us) as u16;
+    }
+
+    /* Samples in `ms`, rounded, may be 0 */
+    pub fn samples(&self, ms: u16) -> u32 {
+        return (ms as u32 * 1000 + self.period_us / 2) / self.period_us;
+    }
+
+    /* Samples in a window of `ms`, at least one */
+    pub fn window(&self, ms: u16) -> u8 {
+        return self.samples(ms).clamp(1, u8::MAX as u32) as u8;
+    }
+
+    /* How long `samples` samples take */
+    pub fn duration_ms(&self, samples: u32) -> u32 {
+        return samples.saturating_mul(self.period_us) / 1000;
+    }
+}
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct TimingStats {
+    /* Intervals the mean and jitter are taken over */
+    pub intervals: u32,
+    pub mean_interval_us: u32,
+    /* Standard deviation of the intervals */
+    pub jitter_us: u32,
+    pub min_interval_us: u32,
+    pub max_interval_us: u32,
+    /* Intervals half a period or more too long, left out of the mean */
+    pub gaps: u32,
+    /* Conversions that never arrived, estimated from the gaps */
+    pub missed: u32,
+}
+
+impl TimingStats {
+    /*
+     * The measured rate is within 25 % of the configured one, RATE pin wired as
+     * configured. Mostly gaps means the converter runs slower than configured,
+     * no samples at all is a timeout rather than a mismatch.
+     */
+    pub fn matches(&self, rate: SampleRate) -> bool {
+        if self.gaps > self.intervals {
+            return false;
+        }
+        let period = rate.period_us();
+        return self.intervals == 0 || self.mean_interval_us.abs_diff(period) <= period / 4;
+    }
+}
+
+// Past this many intervals the sums are halved, older samples count less
+const MAX_INTERVALS: u32 = 4096;
+
+pub struct SampleTiming {
+    rate: SampleRate,
+    last_us: Option<u32>,
+    intervals: u32,
+    /* Deviations from the configured period, their sum and sum of squares */
+    sum_dev: i64,
+    sum_dev_sq: u64,
+    min_us: u32,
+    max_us: u32,
+    gaps: u32,
+    missed: u32,
+}
+
+impl SampleTiming {
+    pub fn new(rate: SampleRate) -> Self {
+        Self {
+            rate,
+            last_us: None,
+            intervals: 0,
+            sum_dev: 0,
+            sum_dev_sq: 0,
+            min_us: u32::MAX,
+            max_us: 0,
+            gaps: 0,
+            missed: 0,
+        }
+    }
+
+    pub fn rate(&self) -> SampleRate {
+        return self.rate;
+    }
+
+    /* A new configuration starts the statistics over */
+    pub fn set_rate(&mut self, rate: SampleRate) {
+        *self = Self::new(rate);
+    }
+
+    /*
+     * Timestamp of a sample, a free running µs clock that may wrap, and the
+     * conversions the driver dropped since the previous sample while settling
+     */
+    pub fn on_sample(&mut self, timestamp_us: u32, skipped: u8) {
+        let last_us = self.last_us.replace(timestamp_us);
+        let elapsed = match last_us {
+            Some(last_us) => timestamp_us.wrapping_sub(last_us),
+            None => return,
+        };
+        // The skipped conversions split the time evenly
+        let conversions = skipped as u32 + 1;
+        let interval = elapsed / conversions;
+
+        // Half a period more than expected lost conversions, the gap stays out of the jitter
+        let period = self.rate.period_us();
+        if elapsed > conversions * period + period / 2 {
+            let periods = (elapsed + period / 2) / period;
+            self.gaps = self.gaps.saturating_add(1);
+            self.missed = self.missed.saturating_add(periods.saturating_sub(conversions));
+            return;
+        }
+
+        if self.intervals >= MAX_INTERVALS {
+            self.intervals /= 2;
+            self.sum_dev /= 2;
+            self.sum_dev_sq /= 2;
+        }
+        let deviation = interval as i64 - period as i64;
+        self.intervals += 1;
+        self.sum_dev += deviation;
+        self.sum_dev_sq += (deviation * deviation) as u64;
+        self.min_us = self.min_us.min(interval);
+        self.max_us = self.max_us.max(interval);
+    }
+
+    pub fn stats(&self) -> TimingStats {
+        if self.intervals == 0 {
+            return TimingStats { gaps: self.gaps, missed: self.missed, ..TimingStats::default() };
+        }
+        let n = self.intervals as i64;
+        let mean_dev = self.sum_dev / n;
+        let variance = (self.sum_dev_sq as i64 - self.sum_dev * self.sum_dev / n) / n;
+        return TimingStats {
+            intervals: self.intervals,
+            mean_interval_us: (self.rate.period_us() as i64 + mean_dev) as u32,
+            jitter_us: isqrt(variance.max(0) as u64) as u32,
+            min_interval_us: self.min_us,
+            max_interval_us: self.max_us,
+            gaps: self.gaps,
+            missed: self.missed,
+        };
+    }
+
+    /* The statistics so far, and starts over, e.g. for a periodic log line */
+    pub fn take(&mut self) -> TimingStats {
+        let stats = self.stats();
+        let last_us = self.last_us;
+        *self = Self::new(self.rate);
+        self.last_us = last_us;
+        return stats;
+    }
+}
+
+fn isqrt(value: u64) -> u64 {
+    let mut root = 0u64;
+    let mut bit = 1u64 << 62;
+    let mut rest = value;
+    while bit > value {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if rest >= root + bit {
+            rest -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    return root;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_in_samples() {
+        let slow = SampleRate::from_sps(10);
+        let fast = SampleRate::from_sps(80);
+        assert_eq!(slow.period_us(), 100_000);
+        assert_eq!(fast.period_us(), 12_500);
+        assert_eq!(fast.sps(), 80);
+        assert_eq!(slow.window(800), 8);
+        assert_eq!(fast.window(800), 64);
+        assert_eq!(slow.window(20), 1);
+        assert_eq!(slow.samples(20), 0);
+        assert_eq!(fast.window(10_000), 255);
+        assert_eq!(slow.duration_ms(16), 1600);
+    }
+
+    #[test]
+    fn mean_and_jitter() {
+        let mut timing = SampleTiming::new(SampleRate::from_sps(80));
+        let mut now = 0u32;
+        // 12.5 ms ± 100 µs
+        for gap in [12_600, 12_400].iter().cycle().take(99) {
+            timing.on_sample(now, 0);
+            now += gap;
+        }
+        timing.on_sample(now, 0);
+        let stats = timing.stats();
+        assert_eq!(stats.intervals, 99);
+        assert!(stats.mean_interval_us.abs_diff(12_500) <= 2, "{:?}", stats);
+        assert!(stats.jitter_us.abs_diff(100) <= 2, "{:?}", stats);
+        assert_eq!((stats.min_interval_us, stats.max_interval_us, stats.missed), (12_400, 12_600, 0));
+        assert!(stats.matches(SampleRate::from_sps(80)));
+        assert!(!stats.matches(SampleRate::from_sps(10)));
+    }
+
+    #[test]
+    fn gaps_are_missed_samples() {
+        let mut timing = SampleTiming::new(SampleRate::from_sps(10));
+        // Starts just before the clock wraps
+        let mut now = u32::MAX - 150_000;
+        for gap in [100_000, 100_000, 300_000, 100_000, 210_000] {
+            timing.on_sample(now, 0);
+            now = now.wrapping_add(gap);
+        }
+        timing.on_sample(now, 0);
+        let stats = timing.take();
+        assert_eq!((stats.intervals, stats.mean_interval_us, stats.jitter_us, stats.missed), (3, 100_000, 0, 3));
+        assert_eq!(stats.gaps, 2);
+
+        // Starts over but keeps the last timestamp
+        timing.on_sample(now.wrapping_add(100_000), 0);
+        assert_eq!((timing.stats().intervals, timing.stats().missed), (1, 0));
+    }
+
+    #[test]
+    fn slower_than_configured() {
+        // Configured for 80 SPS, RATE pin left at 10 SPS
+        let mut timing = SampleTiming::new(SampleRate::from_sps(80));
+        for i in 0..20u32 {
+            timing.on_sample(i * 100_000, 0);
+        }
+        let stats = timing.stats();
+        assert_eq!((stats.intervals, stats.gaps, stats.missed), (0, 19, 133));
+        assert!(!stats.matches(SampleRate::from_sps(80)));
+        assert!(TimingStats::default().matches(SampleRate::from_sps(80)));
+    }
+
+    #[test]
+    fn settling_conversions_are_not_missed() {
+        // Alternating channels, every other conversion is dropped
+        let mut timing = SampleTiming::new(SampleRate::from_sps(10));
+        for i in 0..10u32 {
+            timing.on_sample(i * 200_000, 1);
+        }
+        // A gain switch costs two, and one really went missing
+        timing.on_sample(2_200_000, 2);
+        let stats = timing.stats();
+        assert_eq!((stats.intervals, stats.gaps, stats.missed), (9, 1, 1));
+        assert_eq!(stats.mean_interval_us, 100_000);
+        assert!(stats.matches(SampleRate::from_sps(10)));
+    }
+}
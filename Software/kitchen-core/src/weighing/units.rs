@@ -0,0 +1,223 @@
+use super::graduation::{Division, DivisionSize};
+use super::weight::Weight;
+
+/*
+ * Display units.
+ *
+ * Every unit brings its own display division, so the graduation, stability
+ * and zero tracking all work in steps of what is on screen. The shown value
+ * is the number of divisions times `step`, a fixed decimal number with
+ * `decimals` digits after the point. Volume units divide the weight by a
+ * density, given in milligrams per millilitre (1000 for water).
+ */
+
+// 1 oz = 28.349523125 g, a tenth of that in 1/10000 mg
+const TENTH_OUNCE: DivisionSize = DivisionSize { mg: 28_349_523, per: 10_000 };
+// 1 US fl oz = 29.5735295625 ml, a tenth of that in 1/100000 ml
+const TENTH_FLUID_OUNCE_ML: i32 = 295_735;
+const TENTH_OUNCES_PER_POUND: i32 = 160;
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Density(u16);
+
+impl Density {
+    pub const WATER: Density = Density(1000);
+    // Anything in a kitchen, from puffed rice to honey and well beyond; keeps the fl oz division in range
+    pub const MIN: Density = Density(10);
+    pub const MAX: Density = Density(5000);
+
+    /* Clamped to MIN..=MAX */
+    pub const fn from_mg_per_ml(mg_per_ml: u16) -> Self {
+        if mg_per_ml < Self::MIN.0 {
+            return Self::MIN;
+        }
+        if mg_per_ml > Self::MAX.0 {
+            return Self::MAX;
+        }
+        Density(mg_per_ml)
+    }
+
+    /* None outside MIN..=MAX, e.g. for a damaged settings record */
+    pub const fn checked(mg_per_ml: u16) -> Option<Self> {
+        if mg_per_ml < Self::MIN.0 || mg_per_ml > Self::MAX.0 {
+            return None;
+        }
+        Some(Density(mg_per_ml))
+    }
+
+    pub const fn mg_per_ml(&self) -> u16 {
+        self.0
+    }
+}
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct UnitFormat {
+    pub division: DivisionSize,
+    pub step: i32,
+    pub decimals: u8,
+}
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum UnitReading {
+    Decimal { value: i32, decimals: u8 },
+    PoundsOunces { negative: bool, pounds: i32, tenth_ounces: i32 },
+}
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Unit {
+    Grams,
+    Kilograms,
+    Ounces,
+    PoundsOunces,
+    Millilitres,
+    FluidOunces,
+}
+
+impl Unit {
+    pub const ALL: [Unit; 6] = [
+        Unit::Grams,
+        Unit::Kilograms,
+        Unit::Ounces,
+        Unit::PoundsOunces,
+        Unit::Millilitres,
+        Unit::FluidOunces,
+    ];
+
+    pub fn id(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn from_id(id: u8) -> Option<Unit> {
+        Self::ALL.get(id as usize).copied()
+    }
+
+    pub fn next(&self) -> Unit {
+        Self::ALL[(self.id() as usize + 1) % Self::ALL.len()]
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Unit::Grams => "g",
+            Unit::Kilograms => "kg",
+            Unit::Ounces => "oz",
+            Unit::PoundsOunces => "lb:oz",
+            Unit::Millilitres => "ml",
+            Unit::FluidOunces => "fl oz",
+        }
+    }
+
+    pub fn is_volume(&self) -> bool {
+        matches!(self, Unit::Millilitres | Unit::FluidOunces)
+    }
+
+    /* Division and decimals, `grams` picks the resolution of the gram display */
+    pub fn format(&self, grams: Division, density: Density) -> UnitFormat {
+        let density = density.mg_per_ml() as i64;
+        match self {
+            Unit::Grams => UnitFormat {
+                division: DivisionSize::from_weight(grams.weight()),
+                step: grams.step(),
+                decimals: grams.decimals(),
+            },
+            Unit::Kilograms => UnitFormat {
+                division: DivisionSize::from_weight(Weight::from_grams(1)),
+                step: 1,
+                decimals: 3,
+            },
+            Unit::Ounces | Unit::PoundsOunces => UnitFormat {
+                division: TENTH_OUNCE,
+                step: 1,
+                decimals: 1,
+            },
+            Unit::Millilitres => UnitFormat {
+                division: DivisionSize { mg: density as i32, per: 1 },
+                step: 1,
+                decimals: 0,
+            },
+            Unit::FluidOunces => UnitFormat {
+                // At most Density::MAX * 295_735, fits an i32
+                division: DivisionSize { mg: (density * TENTH_FLUID_OUNCE_ML as i64) as i32, per: 100_000 },
+                step: 1,
+                decimals: 1,
+            },
+        }
+    }
+
+    /* The value to show for `steps` divisions */
+    pub fn reading(&self, steps: i32, format: &UnitFormat) -> UnitReading {
+        let value = steps.saturating_mul(format.step);
+        match self {
+            Unit::PoundsOunces => UnitReading::PoundsOunces {
+                negative: value < 0,
+                pounds: value.saturating_abs() / TENTH_OUNCES_PER_POUND,
+                tenth_ounces: value.saturating_abs() % TENTH_OUNCES_PER_POUND,
+            },
+            _ => UnitReading::Decimal {
+                value,
+                decimals: format.decimals,
+            },
+        }
+    }
+
+    /* Converts without hysteresis, e.g. for logging */
+    pub fn convert(&self, weight: Weight, format: &UnitFormat) -> UnitReading {
+        return self.reading(format.division.steps(weight), format);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WEIGHT: Weight = Weight::from_mg(1_234_567);
+
+    fn convert(unit: Unit) -> UnitReading {
+        return unit.convert(WEIGHT, &unit.format(Division::Tenth, Density::WATER));
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(convert(Unit::Grams), UnitReading::Decimal { value: 12346, decimals: 1 });
+        assert_eq!(convert(Unit::Kilograms), UnitReading::Decimal { value: 1235, decimals: 3 });
+        assert_eq!(convert(Unit::Ounces), UnitReading::Decimal { value: 435, decimals: 1 });
+        assert_eq!(
+            convert(Unit::PoundsOunces),
+            UnitReading::PoundsOunces { negative: false, pounds: 2, tenth_ounces: 115 }
+        );
+        assert_eq!(convert(Unit::Millilitres), UnitReading::Decimal { value: 1235, decimals: 0 });
+        assert_eq!(convert(Unit::FluidOunces), UnitReading::Decimal { value: 417, decimals: 1 });
+    }
+
+    #[test]
+    fn half_gram_steps() {
+        let format = Unit::Grams.format(Division::Half, Density::WATER);
+        assert_eq!(Unit::Grams.convert(WEIGHT, &format), UnitReading::Decimal { value: 12345, decimals: 1 });
+    }
+
+    #[test]
+    fn volume_uses_density() {
+        let oil = Unit::Millilitres.format(Division::One, Density::from_mg_per_ml(920));
+        assert_eq!(Unit::Millilitres.convert(Weight::from_grams(920), &oil), UnitReading::Decimal { value: 1000, decimals: 0 });
+        assert_eq!(Density::from_mg_per_ml(0), Density::MIN);
+        assert_eq!(Density::checked(0), None);
+        assert_eq!(Density::checked(u16::MAX), None);
+
+        // The densest allowed still gets a positive fl oz division
+        let dense = Unit::FluidOunces.format(Division::One, Density::from_mg_per_ml(u16::MAX));
+        assert_eq!(dense.division, DivisionSize { mg: 1_478_675_000, per: 100_000 });
+    }
+
+    #[test]
+    fn cycles_through_all_units() {
+        let mut unit = Unit::Grams;
+        for expected in Unit::ALL.iter().skip(1).chain(Unit::ALL.iter().take(1)) {
+            unit = unit.next();
+            assert_eq!(unit, *expected);
+            assert_eq!(Unit::from_id(unit.id()), Some(unit));
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use core::ops::{Add, Neg, Sub};
+
+/*
+ * Fixed point weight, in milligrams.
+ *
+ * An i32 covers ±2147 kg with 1 mg resolution, far more than the load cell
+ * can resolve, and keeps all the weighing math in integers. The operators
+ * saturate instead of panicking, the checked_* variants report overflow.
+ */
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Weight(i32);
+
+impl Weight {
+    pub const ZERO: Weight = Weight(0);
+    pub const MAX: Weight = Weight(i32::MAX);
+    pub const MIN: Weight = Weight(i32::MIN);
+
+    pub const fn from_mg(mg: i32) -> Self {
+        Weight(mg)
+    }
+
+    pub const fn from_grams(grams: i32) -> Self {
+        Weight(grams.saturating_mul(1000))
+    }
+
+    pub const fn from_kg(kg: i32) -> Self {
+        Weight(kg.saturating_mul(1_000_000))
+    }
+
+    pub const fn mg(&self) -> i32 {
+        self.0
+    }
+
+    /* Whole grams, rounded half away from zero */
+    pub const fn grams(&self) -> i32 {
+        div_round(self.0 as i64, 1000) as i32
+    }
+
+    pub const fn is_negative(&self) -> bool {
+        self.0 < 0
+    }
+
+    pub const fn abs(self) -> Self {
+        Weight(self.0.saturating_abs())
+    }
+
+    pub const fn checked_add(self, other: Weight) -> Option<Weight> {
+        match self.0.checked_add(other.0) {
+            Some(mg) => Some(Weight(mg)),
+            None => None,
+        }
+    }
+
+    pub const fn checked_sub(self, other: Weight) -> Option<Weight> {
+        match self.0.checked_sub(other.0) {
+            Some(mg) => Some(Weight(mg)),
+            None => None,
+        }
+    }
+
+    pub const fn checked_mul(self, factor: i32) -> Option<Weight> {
+        match self.0.checked_mul(factor) {
+            Some(mg) => Some(Weight(mg)),
+            None => None,
+        }
+    }
+
+    /* self * num / den, rounded, with a 64 bit intermediate */
+    pub const fn checked_mul_ratio(self, num: i32, den: i32) -> Option<Weight> {
+        if den == 0 {
+            return None;
+        }
+        let mg = div_round(self.0 as i64 * num as i64, den as i64);
+        if mg > i32::MAX as i64 || mg < i32::MIN as i64 {
+            return None;
+        }
+        Some(Weight(mg as i32))
+    }
+
+    /* Number of `unit` sized steps in self, rounded half away from zero */
+    pub const fn div_round(self, unit: Weight) -> i32 {
+        if unit.0 == 0 {
+            return 0;
+        }
+        div_round(self.0 as i64, unit.0 as i64) as i32
+    }
+
+    /* Converts into another unit given as `num` units per `den` milligrams, saturating. None if `den` is 0 */
+    pub const fn to_units(self, num: i32, den: i32) -> Option<i32> {
+        if den == 0 {
+            return None;
+        }
+        let value = div_round(self.0 as i64 * num as i64, den as i64);
+        if value > i32::MAX as i64 {
+            Some(i32::MAX)
+        } else if value < i32::MIN as i64 {
+            Some(i32::MIN)
+        } else {
+            Some(value as i32)
+        }
+    }
+}
+
+impl Add for Weight {
+    type Output = Weight;
+
+    fn add(self, other: Weight) -> Weight {
+        Weight(self.0.saturating_add(other.0))
+    }
+}
+
+impl Sub for Weight {
+    type Output = Weight;
+
+    fn sub(self, other: Weight) -> Weight {
+        Weight(self.0.saturating_sub(other.0))
+    }
+}
+
+impl Neg for Weight {
+    type Output = Weight;
+
+    fn neg(self) -> Weight {
+        Weight(self.0.saturating_neg())
+    }
+}
+
+/* Milligrams, for code that handles all sensor values as plain fixed point */
+impl From<Weight> for i32 {
+    fn from(weight: Weight) -> i32 {
+        weight.0
+    }
+}
+
+/* Integer division rounding half away from zero */
+pub const fn div_round(num: i64, den: i64) -> i64 {
+    let half = den.abs() / 2;
+    let adjust = if (num < 0) == (den < 0) { half } else { -half };
+    (num + adjust * den.signum()) / den
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn div_round_rounds_half_away_from_zero() {
+        assert_eq!(div_round(7, 2), 4);
+        assert_eq!(div_round(-7, 2), -4);
+        assert_eq!(div_round(7, -2), -4);
+        assert_eq!(div_round(-7, -2), 4);
+        assert_eq!(div_round(5, 3), 2);
+        assert_eq!(div_round(4, 3), 1);
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(Weight::from_grams(12).mg(), 12_000);
+        assert_eq!(Weight::from_kg(2).mg(), 2_000_000);
+        assert_eq!(Weight::from_mg(1_499).grams(), 1);
+        assert_eq!(Weight::from_mg(-1_500).grams(), -2);
+        assert_eq!(Weight::from_grams(i32::MAX), Weight::MAX);
+    }
+
+    #[test]
+    fn operators_saturate() {
+        assert_eq!(Weight::MAX + Weight::from_mg(1), Weight::MAX);
+        assert_eq!(Weight::MIN - Weight::from_mg(1), Weight::MIN);
+        assert_eq!(-Weight::MIN, Weight::MAX);
+        assert_eq!(Weight::MAX.checked_add(Weight::from_mg(1)), None);
+        assert_eq!(Weight::from_mg(3).checked_mul(2), Some(Weight::from_mg(6)));
+    }
+
+    #[test]
+    fn ratios() {
+        assert_eq!(Weight::from_grams(100).checked_mul_ratio(1, 3), Some(Weight::from_mg(33_333)));
+        assert_eq!(Weight::from_grams(1).checked_mul_ratio(1, 0), None);
+        assert_eq!(Weight::from_grams(10).div_round(Weight::from_grams(3)), 3);
+        assert_eq!(Weight::from_grams(10).div_round(Weight::ZERO), 0);
+        assert_eq!(Weight::MAX.to_units(2, 1), Some(i32::MAX));
+        assert_eq!(Weight::from_grams(1).to_units(1, 1000), Some(1));
+        assert_eq!(Weight::from_grams(1).to_units(1, 0), None);
+    }
+}
@@ -0,0 +1,151 @@
+use super::stability::Motion;
+use super::timing::SampleRate;
+
+/*
+ * Automatic zero tracking.
+ *
+ * While the reading is stable and within ±band of zero, the remaining
+ * deviation is folded into the tare offset, at most one step every
+ * `interval_ms`. Bands and steps are given in tenths of a display
+ * division. The total correction since the last manual zero is capped, so a
+ * load that is added slowly enough is never tracked away.
+ */
+
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ZeroTrackingConfig {
+    pub enabled: bool,
+    pub band_tenths: u8,
+    pub step_tenths: u8,
+    pub interval_ms: u16,
+    pub max_total_divisions: u16,
+}
+
+/* ±0.5 d band, at most 0.1 d per 800 ms, 20 d in total */
+pub const DEFAULT_ZERO_TRACKING: ZeroTrackingConfig = ZeroTrackingConfig {
+    enabled: true,
+    band_tenths: 5,
+    step_tenths: 1,
+    interval_ms: 800,
+    max_total_divisions: 20,
+};
+
+pub struct ZeroTracker {
+    config: ZeroTrackingConfig,
+    rate: SampleRate,
+    /* The interval in samples */
+    interval: u8,
+    division_counts: i32,
+    quiet: u8,
+    total: i32,
+}
+
+impl ZeroTracker {
+    pub fn new(config: ZeroTrackingConfig, division_counts: i32, rate: SampleRate) -> Self {
+        Self {
+            config,
+            rate,
+            interval: rate.window(config.interval_ms),
+            division_counts: division_counts.abs().max(1),
+            quiet: 0,
+            total: 0,
+        }
+    }
+
+    pub fn set_config(&mut self, config: ZeroTrackingConfig) {
+        self.config = config;
+        self.interval = self.rate.window(config.interval_ms);
+        self.quiet = 0;
+    }
+
+    pub fn set_rate(&mut self, rate: SampleRate) {
+        self.rate = rate;
+        self.set_config(self.config);
+    }
+
+    /* Size of one display division in counts */
+    pub fn set_division_counts(&mut self, division_counts: i32) {
+        self.division_counts = division_counts.abs().max(1);
+    }
+
+    /* A manual tare or zero restarts the correction budget */
+    pub fn rezero(&mut self) {
+        self.quiet = 0;
+        self.total = 0;
+    }
+
+    /* Returns the correction, in counts, to add to the tare offset */
+    pub fn update(&mut self, net_counts: i32, motion: Motion) -> i32 {
+        let division = self.division_counts as i64;
+        let band = (division * self.config.band_tenths as i64 / 10) as i32;
+
+        if !self.config.enabled || motion != Motion::Stable || net_counts.abs() > band {
+            self.quiet = 0;
+            return 0;
+        }
+
+        self.quiet += 1;
+        if self.quiet < self.interval {
+            return 0;
+        }
+        self.quiet = 0;
+
+        let max_step = ((division * self.config.step_tenths as i64 / 10) as i32).max(1);
+        let step = net_counts.clamp(-max_step, max_step);
+        let max_total = division * self.config.max_total_divisions as i64;
+        if ((self.total + step) as i64).abs() > max_total {
+            return 0;
+        }
+
+        self.total += step;
+        return step;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: ZeroTrackingConfig = ZeroTrackingConfig {
+        enabled: true,
+        band_tenths: 5,
+        step_tenths: 1,
+        interval_ms: 200,
+        max_total_divisions: 1,
+    };
+    const RATE: SampleRate = SampleRate::from_sps(10);
+
+    #[test]
+    fn tracks_small_drift_in_steps() {
+        let mut tracker = ZeroTracker::new(CONFIG, 100, RATE);
+        assert_eq!(tracker.update(40, Motion::Stable), 0);
+        assert_eq!(tracker.update(40, Motion::Stable), 10);
+        assert_eq!(tracker.update(-3, Motion::Stable), 0);
+        assert_eq!(tracker.update(-3, Motion::Stable), -3);
+    }
+
+    #[test]
+    fn ignores_motion_and_loads() {
+        let mut tracker = ZeroTracker::new(CONFIG, 100, RATE);
+        for _ in 0..4 {
+            assert_eq!(tracker.update(40, Motion::Moving), 0);
+            assert_eq!(tracker.update(51, Motion::Stable), 0);
+        }
+
+        tracker.set_config(ZeroTrackingConfig { enabled: false, ..CONFIG });
+        for _ in 0..4 {
+            assert_eq!(tracker.update(1, Motion::Stable), 0);
+        }
+    }
+
+    #[test]
+    fn total_correction_is_capped() {
+        let mut tracker = ZeroTracker::new(CONFIG, 100, RATE);
+        let total: i32 = (0..40).map(|_| tracker.update(50, Motion::Stable)).sum();
+        assert_eq!(total, 100);
+
+        tracker.rezero();
+        tracker.update(50, Motion::Stable);
+        assert_eq!(tracker.update(50, Motion::Stable), 10);
+    }
+}
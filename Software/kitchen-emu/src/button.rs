@@ -0,0 +1,57 @@
+use core::cell::Cell;
+use core::convert::Infallible;
+use embedded_hal::digital::v2::InputPin;
+
+/*
+ * Push button held down for a given time, e.g. from a key press. The pin
+ * reads low while pressed, like SW1 against its pull-up.
+ */
+#[derive(Debug, Default)]
+pub struct VirtualButton {
+    pressed: Cell<bool>,
+    release_at_ms: Cell<u32>,
+}
+
+impl VirtualButton {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /* Holds the button from `now_ms` for `hold_ms` */
+    pub fn press(&self, now_ms: u32, hold_ms: u32) {
+        self.pressed.set(true);
+        self.release_at_ms.set(now_ms.wrapping_add(hold_ms));
+    }
+
+    /* Releases the button once the hold time is over */
+    pub fn update(&self, now_ms: u32) {
+        if self.pressed.get() && (now_ms.wrapping_sub(self.release_at_ms.get()) as i32) >= 0 {
+            self.pressed.set(false);
+        }
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        return self.pressed.get();
+    }
+
+    /* The input pin for the driver */
+    pub fn pin(&self) -> ButtonPin<'_> {
+        return ButtonPin { pressed: &self.pressed };
+    }
+}
+
+pub struct ButtonPin<'a> {
+    pressed: &'a Cell<bool>,
+}
+
+impl InputPin for ButtonPin<'_> {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Infallible> {
+        return Ok(!self.pressed.get());
+    }
+
+    fn is_low(&self) -> Result<bool, Infallible> {
+        return Ok(self.pressed.get());
+    }
+}
@@ -0,0 +1,29 @@
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+/* Returns at once and only adds up the time the driver asked to wait */
+#[derive(Debug, Default)]
+pub struct VirtualDelay {
+    elapsed_us: u64,
+}
+
+impl VirtualDelay {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn elapsed_us(&self) -> u64 {
+        return self.elapsed_us;
+    }
+}
+
+impl DelayUs<u16> for VirtualDelay {
+    fn delay_us(&mut self, us: u16) {
+        self.elapsed_us += us as u64;
+    }
+}
+
+impl DelayMs<u16> for VirtualDelay {
+    fn delay_ms(&mut self, ms: u16) {
+        self.elapsed_us += ms as u64 * 1000;
+    }
+}
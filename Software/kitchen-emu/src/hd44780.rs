@@ -0,0 +1,517 @@
+use embedded_hal::blocking::i2c::{Read, Write};
+
+/*
+ * HD44780 character LCD behind a PCF8574 I2C expander, as on the usual
+ * 20x4 backpacks.
+ *
+ * The expander drives the controller in 4-bit mode:
+ *
+ *   P7..P4: D7..D4 | P3: backlight | P2: En | P1: Rw | P0: Rs
+ *
+ * Every byte written on the bus sets the port. With Rw low the controller
+ * latches a nibble on the falling edge of En; with Rw high it drives D7..D4
+ * from the rising edge of En on, and reading the expander returns them.
+ * After reset the controller is in 8-bit mode and every nibble is a whole
+ * instruction, until a function set selects the 4-bit interface; from then
+ * on instructions, data and reads take two nibbles, high one first.
+ *
+ * Instructions execute instantly, the busy flag always reads clear.
+ */
+pub const PCF8574_ADDRESS: u8 = 0x27;
+
+const RS: u8 = 0b0000_0001;
+const RW: u8 = 0b0000_0010;
+const EN: u8 = 0b0000_0100;
+const BL: u8 = 0b0000_1000;
+
+const DDRAM_SIZE: usize = 80;
+const LINE_LENGTH: u8 = 40;
+const LINE2_ADDRESS: u8 = 0x40;
+const CGRAM_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum I2cError {
+    /* Nothing acknowledged the address */
+    Nack,
+}
+
+/* The controller, fed with whole nibbles */
+#[derive(Debug, Clone)]
+pub struct Hd44780 {
+    columns: u8,
+    rows: u8,
+    four_bit: bool,
+    two_lines: bool,
+    high_nibble: Option<u8>,
+    low_nibble_out: Option<u8>,
+    ddram: [u8; DDRAM_SIZE],
+    cgram: [u8; CGRAM_SIZE],
+    address: u8,
+    cgram_selected: bool,
+    increment: bool,
+    shift_on_write: bool,
+    shift: u8,
+    display_on: bool,
+    cursor_on: bool,
+    blink_on: bool,
+}
+
+impl Hd44780 {
+    /* State after the internal reset: 8-bit interface, one line, display off */
+    pub fn new(columns: u8, rows: u8) -> Self {
+        Self {
+            columns,
+            rows,
+            four_bit: false,
+            two_lines: false,
+            high_nibble: None,
+            low_nibble_out: None,
+            ddram: [b' '; DDRAM_SIZE],
+            cgram: [0; CGRAM_SIZE],
+            address: 0,
+            cgram_selected: false,
+            increment: true,
+            shift_on_write: false,
+            shift: 0,
+            display_on: false,
+            cursor_on: false,
+            blink_on: false,
+        }
+    }
+
+    pub fn columns(&self) -> u8 {
+        return self.columns;
+    }
+
+    pub fn rows(&self) -> u8 {
+        return self.rows;
+    }
+
+    pub fn is_four_bit(&self) -> bool {
+        return self.four_bit;
+    }
+
+    pub fn is_two_lines(&self) -> bool {
+        return self.two_lines;
+    }
+
+    pub fn is_display_on(&self) -> bool {
+        return self.display_on;
+    }
+
+    pub fn is_cursor_on(&self) -> bool {
+        return self.cursor_on;
+    }
+
+    pub fn is_blink_on(&self) -> bool {
+        return self.blink_on;
+    }
+
+    /* Entry mode I/D, the address counter counts up after each access */
+    pub fn is_increment(&self) -> bool {
+        return self.increment;
+    }
+
+    /* Entry mode S, DDRAM writes shift the display along */
+    pub fn is_autoscroll(&self) -> bool {
+        return self.shift_on_write;
+    }
+
+    /* Display shift, in positions to the left */
+    pub fn shift(&self) -> u8 {
+        return self.shift;
+    }
+
+    /* Address counter, a DDRAM address unless CGRAM was selected last */
+    pub fn address(&self) -> u8 {
+        return self.address;
+    }
+
+    /* Screen position of the address counter, if it points at a visible cell */
+    pub fn cursor(&self) -> Option<(u8, u8)> {
+        if self.cgram_selected {
+            return None;
+        }
+        return (0..self.rows)
+            .flat_map(|row| (0..self.columns).map(move |col| (col, row)))
+            .find(|&(col, row)| self.cell_address(col, row) == Some(self.address));
+    }
+
+    /* Character codes shown on a row, rows no line is driven on stay blank */
+    pub fn row(&self, row: u8) -> Vec<u8> {
+        return (0..self.columns)
+            .map(|col| match self.cell_address(col, row) {
+                Some(address) => self.ddram[self.ddram_index(address)],
+                None => b' ',
+            })
+            .collect();
+    }
+
+    /* A row as text, see `rom_char` */
+    pub fn row_text(&self, row: u8) -> String {
+        return self.row(row).into_iter().map(rom_char).collect();
+    }
+
+    /* The 8 pixel rows of custom character 0 to 7 */
+    pub fn glyph(&self, code: u8) -> [u8; 8] {
+        let start = (code as usize & 0x07) * 8;
+        let mut glyph = [0; 8];
+        glyph.copy_from_slice(&self.cgram[start..start + 8]);
+        return glyph.map(|row| row & 0x1F);
+    }
+
+    /* One nibble latched by the falling edge of En, in bits 7..4 */
+    pub fn latch(&mut self, rs: bool, nibble: u8) {
+        let nibble = nibble & 0xF0;
+        self.low_nibble_out = None;
+        if !self.four_bit {
+            // 8-bit interface, D3..D0 are not wired and read as zero
+            self.execute(rs, nibble);
+            return;
+        }
+        match self.high_nibble.take() {
+            None => self.high_nibble = Some(nibble),
+            Some(high) => self.execute(rs, high | (nibble >> 4)),
+        }
+    }
+
+    /* Nibble driven onto D7..D4 from the rising edge of En in a read, in bits 7..4 */
+    pub fn output(&mut self, rs: bool) -> u8 {
+        if !self.four_bit {
+            return self.read(rs) & 0xF0;
+        }
+        match self.low_nibble_out.take() {
+            Some(low) => return low,
+            None => {
+                let value = self.read(rs);
+                self.low_nibble_out = Some(value << 4);
+                return value & 0xF0;
+            }
+        }
+    }
+
+    fn read(&mut self, rs: bool) -> u8 {
+        if !rs {
+            // Busy flag, never set, and the address counter
+            return self.address & 0x7F;
+        }
+        let value = if self.cgram_selected {
+            self.cgram[self.address as usize % CGRAM_SIZE]
+        } else {
+            self.ddram[self.ddram_index(self.address)]
+        };
+        self.step_address();
+        return value;
+    }
+
+    fn execute(&mut self, rs: bool, value: u8) {
+        if rs {
+            self.write_data(value);
+        } else {
+            self.instruction(value);
+        }
+    }
+
+    fn instruction(&mut self, value: u8) {
+        if value & 0x80 != 0 {
+            // Set DDRAM address
+            self.address = value & 0x7F;
+            self.cgram_selected = false;
+        } else if value & 0x40 != 0 {
+            // Set CGRAM address
+            self.address = value & 0x3F;
+            self.cgram_selected = true;
+        } else if value & 0x20 != 0 {
+            // Function set, the 5x10 font makes no difference to the text shown
+            self.four_bit = value & 0x10 == 0;
+            self.two_lines = value & 0x08 != 0;
+        } else if value & 0x10 != 0 {
+            // Cursor or display shift, S/C selects the display
+            let right = value & 0x04 != 0;
+            if value & 0x08 != 0 {
+                self.shift_display(!right);
+            } else if !self.cgram_selected {
+                self.address = self.step_ddram(self.address, right);
+            }
+        } else if value & 0x08 != 0 {
+            self.display_on = value & 0x04 != 0;
+            self.cursor_on = value & 0x02 != 0;
+            self.blink_on = value & 0x01 != 0;
+        } else if value & 0x04 != 0 {
+            self.increment = value & 0x02 != 0;
+            self.shift_on_write = value & 0x01 != 0;
+        } else if value & 0x02 != 0 {
+            // Return home, undoes the shift as well
+            self.address = 0;
+            self.cgram_selected = false;
+            self.shift = 0;
+        } else if value & 0x01 != 0 {
+            // Clear display
+            self.ddram = [b' '; DDRAM_SIZE];
+            self.address = 0;
+            self.cgram_selected = false;
+            self.increment = true;
+            self.shift = 0;
+        }
+    }
+
+    fn write_data(&mut self, value: u8) {
+        if self.cgram_selected {
+            self.cgram[self.address as usize % CGRAM_SIZE] = value;
+        } else {
+            let index = self.ddram_index(self.address);
+            self.ddram[index] = value;
+            if self.shift_on_write {
+                // The display moves with the address counter, the cursor stays put on screen
+                self.shift_display(self.increment);
+            }
+        }
+        self.step_address();
+    }
+
+    fn step_address(&mut self) {
+        if self.cgram_selected {
+            let next = if self.increment { self.address.wrapping_add(1) } else { self.address.wrapping_sub(1) };
+            self.address = next & 0x3F;
+        } else {
+            self.address = self.step_ddram(self.address, self.increment);
+        }
+    }
+
+    /* Moves the text one position left or right, every line wraps around on its own */
+    fn shift_display(&mut self, left: bool) {
+        let positions = self.line_length();
+        self.shift = if left { (self.shift + 1) % positions } else { (self.shift + positions - 1) % positions };
+    }
+
+    /* Characters per line, a single line has all of the DDRAM */
+    fn line_length(&self) -> u8 {
+        return if self.two_lines { LINE_LENGTH } else { DDRAM_SIZE as u8 };
+    }
+
+    /*
+     * DDRAM address shown at a screen position. With two lines rows 2 and 3
+     * continue rows 0 and 1, with one line only row 0 is driven.
+     */
+    fn cell_address(&self, col: u8, row: u8) -> Option<u8> {
+        if !self.two_lines {
+            return if row == 0 { Some((col + self.shift) % self.line_length()) } else { None };
+        }
+        let position = ((row / 2) * self.columns + col + self.shift) % LINE_LENGTH;
+        return Some(if row.is_multiple_of(2) { position } else { LINE2_ADDRESS + position });
+    }
+
+    /* Position in the 80 character DDRAM */
+    fn ddram_index(&self, address: u8) -> usize {
+        if !self.two_lines {
+            return address as usize % DDRAM_SIZE;
+        }
+        let (line, offset) = if address >= LINE2_ADDRESS { (1, address - LINE2_ADDRESS) } else { (0, address) };
+        return line * LINE_LENGTH as usize + (offset % LINE_LENGTH) as usize;
+    }
+
+    /* Next DDRAM address, the end of one line wraps to the start of the other */
+    fn step_ddram(&self, address: u8, increment: bool) -> u8 {
+        let index = self.ddram_index(address) as isize + if increment { 1 } else { -1 };
+        let index = index.rem_euclid(DDRAM_SIZE as isize) as u8;
+        if !self.two_lines || index < LINE_LENGTH {
+            return index;
+        }
+        return LINE2_ADDRESS + index - LINE_LENGTH;
+    }
+}
+
+/*
+ * Character for a code of the A00 (Japanese) character ROM. Custom
+ * characters show as circled digits 1 to 8, codes without a close Unicode
+ * equivalent as a box.
+ */
+pub fn rom_char(code: u8) -> char {
+    match code {
+        0x00..=0x0F => char::from_u32(0x2460 + (code & 0x07) as u32).unwrap_or('?'),
+        0x5C => '¥',
+        0x7E => '→',
+        0x7F => '←',
+        0x20..=0x7D => code as char,
+        0xDF => '°',
+        0xE4 => 'µ',
+        0xF4 => 'Ω',
+        _ => '▯',
+    }
+}
+
+/* The PCF8574 port in front of the controller */
+#[derive(Debug, Clone)]
+pub struct LcdBackpack {
+    address: u8,
+    port: u8,
+    /* Nibble the controller drives onto P7..P4 during a read */
+    driven: Option<u8>,
+    lcd: Hd44780,
+}
+
+impl LcdBackpack {
+    pub fn new(columns: u8, rows: u8) -> Self {
+        Self {
+            address: PCF8574_ADDRESS,
+            port: 0xFF,
+            driven: None,
+            lcd: Hd44780::new(columns, rows),
+        }
+    }
+
+    pub fn lcd(&self) -> &Hd44780 {
+        return &self.lcd;
+    }
+
+    pub fn is_backlight_on(&self) -> bool {
+        return self.port & BL != 0;
+    }
+
+    fn write_port(&mut self, value: u8) {
+        let rising = self.port & EN == 0 && value & EN != 0;
+        let falling = self.port & EN != 0 && value & EN == 0;
+        if falling && self.port & RW == 0 {
+            // Data was set up before the edge
+            self.lcd.latch(self.port & RS != 0, self.port);
+        }
+        self.port = value;
+
+        if rising && value & RW != 0 {
+            self.driven = Some(self.lcd.output(value & RS != 0));
+        } else if value & (EN | RW) != EN | RW {
+            self.driven = None;
+        }
+    }
+}
+
+impl Write for LcdBackpack {
+    type Error = I2cError;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), I2cError> {
+        if address != self.address {
+            return Err(I2cError::Nack);
+        }
+        bytes.iter().for_each(|b| self.write_port(*b));
+        return Ok(());
+    }
+}
+
+/*
+ * Quasi-bidirectional port, pins written high are only pulled up and read
+ * whatever the controller drives, pins written low read low.
+ */
+impl Read for LcdBackpack {
+    type Error = I2cError;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), I2cError> {
+        if address != self.address {
+            return Err(I2cError::Nack);
+        }
+        let pins = self.port & self.driven.map_or(0xFF, |nibble| nibble | 0x0F);
+        buffer.iter_mut().for_each(|b| *b = pins);
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* A byte the way the backpack drivers send it, En pulsed once per nibble */
+    fn send(backpack: &mut LcdBackpack, value: u8, rs: bool) {
+        let rs = if rs { RS } else { 0 };
+        for nibble in [value & 0xF0, value << 4] {
+            backpack.write(PCF8574_ADDRESS, &[nibble | rs | EN | BL, nibble | rs | BL]).unwrap();
+        }
+    }
+
+    fn two_lines() -> LcdBackpack {
+        let mut backpack = LcdBackpack::new(20, 4);
+        for nibble in [0x30, 0x30, 0x30, 0x20] {
+            backpack.write(PCF8574_ADDRESS, &[nibble | EN, nibble]).unwrap();
+        }
+        send(&mut backpack, 0x28, false);
+        return backpack;
+    }
+
+    #[test]
+    fn eight_bit_until_function_set() {
+        let mut backpack = LcdBackpack::new(20, 4);
+        backpack.write(PCF8574_ADDRESS, &[0x30 | EN, 0x30]).unwrap();
+        assert!(!backpack.lcd().is_four_bit());
+        backpack.write(PCF8574_ADDRESS, &[0x20 | EN, 0x20]).unwrap();
+        assert!(backpack.lcd().is_four_bit());
+        assert!(!backpack.lcd().is_two_lines());
+        assert!(two_lines().lcd().is_two_lines());
+        assert_eq!(backpack.write(0x20, &[0]), Err(I2cError::Nack));
+    }
+
+    #[test]
+    fn lines_wrap_into_each_other() {
+        let mut backpack = two_lines();
+        send(&mut backpack, 0x80 | 0x26, false);
+        "abcd".bytes().for_each(|b| send(&mut backpack, b, true));
+        assert_eq!(backpack.lcd().address(), 0x42);
+        assert_eq!(&backpack.lcd().row_text(1)[..2], "cd");
+
+        send(&mut backpack, 0x80 | 0x67, false);
+        send(&mut backpack, b'z', true);
+        assert_eq!(backpack.lcd().address(), 0x00);
+        assert_eq!(backpack.lcd().row_text(3).chars().last(), Some('z'));
+    }
+
+    #[test]
+    fn one_line_mode_is_contiguous() {
+        let mut backpack = LcdBackpack::new(20, 4);
+        backpack.write(PCF8574_ADDRESS, &[0x20 | EN, 0x20]).unwrap();
+        send(&mut backpack, 0x0C, false);
+        send(&mut backpack, 0x80 | 0x27, false);
+        "ab".bytes().for_each(|b| send(&mut backpack, b, true));
+        assert_eq!(backpack.lcd().address(), 0x29);
+        assert_eq!(backpack.lcd().row_text(1).trim(), "");
+    }
+
+    #[test]
+    fn reads_return_the_driven_nibbles() {
+        let mut backpack = two_lines();
+        send(&mut backpack, 0x80 | 0x45, false);
+        let mut port = [0];
+        for expected in [0x40, 0x50] {
+            backpack.write(PCF8574_ADDRESS, &[0xF0 | RW, 0xF0 | RW | EN]).unwrap();
+            backpack.read(PCF8574_ADDRESS, &mut port).unwrap();
+            assert_eq!(port[0], expected | RW | EN);
+        }
+
+        // Released once En drops again
+        backpack.write(PCF8574_ADDRESS, &[0xF0 | RW]).unwrap();
+        backpack.read(PCF8574_ADDRESS, &mut port).unwrap();
+        assert_eq!(port[0], 0xF0 | RW);
+    }
+
+    #[test]
+    fn cursor_and_display_shifts() {
+        let mut backpack = two_lines();
+        send(&mut backpack, 0x14, false);
+        assert_eq!(backpack.lcd().address(), 0x01);
+        send(&mut backpack, 0x10, false);
+        send(&mut backpack, 0x10, false);
+        assert_eq!(backpack.lcd().address(), 0x67);
+
+        send(&mut backpack, 0x18, false);
+        assert_eq!(backpack.lcd().shift(), 1);
+        send(&mut backpack, 0x1C, false);
+        send(&mut backpack, 0x1C, false);
+        assert_eq!(backpack.lcd().shift(), 39);
+        send(&mut backpack, 0x02, false);
+        assert_eq!(backpack.lcd().shift(), 0);
+    }
+
+    #[test]
+    fn rom() {
+        assert_eq!(rom_char(b'A'), 'A');
+        assert_eq!(rom_char(0x5C), '¥');
+        assert_eq!(rom_char(0xDF), '°');
+        assert_eq!(rom_char(0x08), '①');
+    }
+}
@@ -0,0 +1,429 @@
+use core::cell::{Cell, RefCell};
+use core::convert::Infallible;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+use crate::load_cell::{HX711_MAX, HX711_MIN};
+
+/*
+ * HX711 at the pin level, driven by PD_SCK edges.
+ *
+ * The chip converts continuously and pulls DOUT low when a result is ready.
+ * The first rising edge of PD_SCK starts the readout, each rising edge puts
+ * the next bit on DOUT, MSB first, 24 bits of two's complement. The 25th
+ * rising edge pulls DOUT high again. Together with the optional 26th and
+ * 27th edge, the number of pulses selects the input and gain:
+ *
+ *   25: channel A, gain 128 | 26: channel B, gain 32 | 27: channel A, gain 64
+ *
+ * The selection applies from the next conversion on, but that first one
+ * has not settled yet: the model returns the code halfway between the old
+ * and the new selection for it. Holding PD_SCK high for 60 us powers the
+ * chip down, taking it low again resets it to channel A at gain 128, and the
+ * first result after that is ready once the output has settled. Readouts are
+ * expected to finish within a conversion period.
+ *
+ * Time is the model's own: it only advances through `advance_us` and the
+ * delay handed to the driver, so waiting in the driver lets conversions
+ * finish. Clocking the pins takes no time.
+ */
+pub const POWER_DOWN_US: u64 = 60;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Channel {
+    A,
+    B,
+}
+
+/* Input and gain selection, as chosen by the pulse count */
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Gain {
+    A128,
+    B32,
+    A64,
+}
+
+impl Gain {
+    /* PD_SCK pulses of a readout that select it */
+    pub fn pulses(&self) -> u8 {
+        match self {
+            Gain::A128 => return 25,
+            Gain::B32 => return 26,
+            Gain::A64 => return 27,
+        }
+    }
+
+    pub fn channel(&self) -> Channel {
+        match self {
+            Gain::A128 | Gain::A64 => return Channel::A,
+            Gain::B32 => return Channel::B,
+        }
+    }
+
+    pub fn gain(&self) -> i64 {
+        match self {
+            Gain::A128 => return 128,
+            Gain::B32 => return 32,
+            Gain::A64 => return 64,
+        }
+    }
+
+    fn from_pulses(pulses: u8) -> Option<Gain> {
+        match pulses {
+            25 => return Some(Gain::A128),
+            26 => return Some(Gain::B32),
+            27 => return Some(Gain::A64),
+            _ => return None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    period_us: u64,
+    settling_us: u64,
+    inputs: [i64; 2],
+    selection: Gain,
+    /* Selection of the last finished conversion */
+    settled: Gain,
+    next_conversion_us: u64,
+    result: Option<i32>,
+    conversions: u32,
+    /* Readout in progress, the shifted word and the rising edges so far */
+    shift: u32,
+    pulses: u8,
+    sck_high: bool,
+    high_since_us: u64,
+    powered_down: bool,
+}
+
+impl State {
+    /* Everything that happened up to `now_us` */
+    fn update(&mut self, now_us: u64) {
+        let power_down_us = self.high_since_us + POWER_DOWN_US;
+        let limit_us = if self.sck_high { now_us.min(power_down_us) } else { now_us };
+        while !self.powered_down && self.next_conversion_us <= limit_us {
+            self.result = Some(self.conversion());
+            self.settled = self.selection;
+            self.next_conversion_us += self.period_us;
+            self.conversions += 1;
+            if self.pulses > 24 {
+                self.pulses = 0;
+            }
+        }
+        if self.sck_high && now_us >= power_down_us && !self.powered_down {
+            self.powered_down = true;
+            self.result = None;
+            self.pulses = 0;
+        }
+    }
+
+    fn conversion(&self) -> i32 {
+        let code = self.code(self.selection);
+        if self.selection == self.settled {
+            return code;
+        }
+        return ((code as i64 + self.code(self.settled) as i64) / 2) as i32;
+    }
+
+    fn code(&self, selection: Gain) -> i32 {
+        let input = self.inputs[selection.channel() as usize];
+        return (input * selection.gain() / 128).clamp(HX711_MIN as i64, HX711_MAX as i64) as i32;
+    }
+
+    fn rising_edge(&mut self, now_us: u64) {
+        self.sck_high = true;
+        self.high_since_us = now_us;
+        if self.pulses == 0 {
+            match self.result.take() {
+                Some(code) => self.shift = code as u32 & 0xFF_FFFF,
+                // Nothing to read out, the edge is lost
+                None => return,
+            }
+        }
+        self.pulses = self.pulses.saturating_add(1);
+        if let Some(selection) = Gain::from_pulses(self.pulses) {
+            self.selection = selection;
+        }
+    }
+
+    fn falling_edge(&mut self, now_us: u64) {
+        self.sck_high = false;
+        if self.powered_down {
+            self.reset(now_us);
+        }
+    }
+
+    fn reset(&mut self, now_us: u64) {
+        self.powered_down = false;
+        self.selection = Gain::A128;
+        self.settled = Gain::A128;
+        self.next_conversion_us = now_us + self.settling_us;
+        self.result = None;
+        self.pulses = 0;
+    }
+
+    fn dout_low(&self) -> bool {
+        match self.pulses {
+            0 => return self.result.is_some(),
+            1..=24 => return (self.shift >> (24 - self.pulses)) & 1 == 0,
+            _ => return false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Hx711Model {
+    state: RefCell<State>,
+    now_us: Cell<u64>,
+}
+
+impl Hx711Model {
+    /* Chip at 10 or 80 samples per second, just powered up with PD_SCK low */
+    pub fn new(rate_sps: u32) -> Self {
+        // Output settling time after a reset, datasheet table
+        let (period_us, settling_us) = if rate_sps >= 80 { (12_500, 50_000) } else { (100_000, 400_000) };
+        let mut state = State {
+            period_us,
+            settling_us,
+            inputs: [0; 2],
+            selection: Gain::A128,
+            settled: Gain::A128,
+            next_conversion_us: 0,
+            result: None,
+            conversions: 0,
+            shift: 0,
+            pulses: 0,
+            sck_high: false,
+            high_since_us: 0,
+            powered_down: false,
+        };
+        state.reset(0);
+        return Self { state: RefCell::new(state), now_us: Cell::new(0) };
+    }
+
+    /*
+     * Differential input of a channel, in output codes at gain 128. Gain 64
+     * gives half the code, channel B at gain 32 a quarter.
+     */
+    pub fn set_input(&self, channel: Channel, counts: i64) {
+        self.state.borrow_mut().inputs[channel as usize] = counts;
+    }
+
+    pub fn now_us(&self) -> u64 {
+        return self.now_us.get();
+    }
+
+    pub fn advance_us(&self, us: u64) {
+        self.now_us.set(self.now_us.get() + us);
+        self.state.borrow_mut().update(self.now_us.get());
+    }
+
+    /* Selection for the next conversion */
+    pub fn selection(&self) -> Gain {
+        return self.state().selection;
+    }
+
+    pub fn is_powered_down(&self) -> bool {
+        return self.state().powered_down;
+    }
+
+    /* Conversions finished since power up, read out or not */
+    pub fn conversions(&self) -> u32 {
+        return self.state().conversions;
+    }
+
+    pub fn dout(&self) -> Hx711Dout<'_> {
+        return Hx711Dout { model: self };
+    }
+
+    pub fn pd_sck(&self) -> Hx711Sck<'_> {
+        return Hx711Sck { model: self };
+    }
+
+    /* Delay for the driver, waiting moves the model's time on */
+    pub fn delay(&self) -> Hx711Delay<'_> {
+        return Hx711Delay { model: self };
+    }
+
+    fn state(&self) -> core::cell::RefMut<'_, State> {
+        let mut state = self.state.borrow_mut();
+        state.update(self.now_us.get());
+        return state;
+    }
+}
+
+pub struct Hx711Dout<'a> {
+    model: &'a Hx711Model,
+}
+
+impl InputPin for Hx711Dout<'_> {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Infallible> {
+        return Ok(!self.model.state().dout_low());
+    }
+
+    fn is_low(&self) -> Result<bool, Infallible> {
+        return Ok(self.model.state().dout_low());
+    }
+}
+
+pub struct Hx711Sck<'a> {
+    model: &'a Hx711Model,
+}
+
+impl OutputPin for Hx711Sck<'_> {
+    type Error = Infallible;
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        let mut state = self.model.state();
+        if !state.sck_high {
+            state.rising_edge(self.model.now_us());
+        }
+        return Ok(());
+    }
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        let mut state = self.model.state();
+        if state.sck_high {
+            state.falling_edge(self.model.now_us());
+        }
+        return Ok(());
+    }
+}
+
+/* One PD_SCK line wired to several chips */
+pub struct Hx711SckGroup<'a> {
+    pins: Vec<Hx711Sck<'a>>,
+}
+
+impl<'a> Hx711SckGroup<'a> {
+    pub fn new(models: &[&'a Hx711Model]) -> Self {
+        return Self { pins: models.iter().map(|model| model.pd_sck()).collect() };
+    }
+}
+
+impl OutputPin for Hx711SckGroup<'_> {
+    type Error = Infallible;
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        for pin in self.pins.iter_mut() {
+            pin.set_high()?;
+        }
+        return Ok(());
+    }
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        for pin in self.pins.iter_mut() {
+            pin.set_low()?;
+        }
+        return Ok(());
+    }
+}
+
+pub struct Hx711Delay<'a> {
+    model: &'a Hx711Model,
+}
+
+impl DelayUs<u16> for Hx711Delay<'_> {
+    fn delay_us(&mut self, us: u16) {
+        self.model.advance_us(us as u64);
+    }
+}
+
+impl DelayMs<u16> for Hx711Delay<'_> {
+    fn delay_ms(&mut self, ms: u16) {
+        self.model.advance_us(ms as u64 * 1000);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Clocks out one conversion with `pulses` edges, returns the 24 bits */
+    fn read_out(model: &Hx711Model, pulses: u8) -> u32 {
+        let (dout, mut sck) = (model.dout(), model.pd_sck());
+        let mut word = 0;
+        for pulse in 0..pulses {
+            sck.set_high().unwrap();
+            if pulse < 24 {
+                word = (word << 1) | dout.is_high().unwrap() as u32;
+            }
+            sck.set_low().unwrap();
+        }
+        return word;
+    }
+
+    #[test]
+    fn ready_after_settling() {
+        let model = Hx711Model::new(10);
+        model.set_input(Channel::A, -2);
+        model.advance_us(399_999);
+        assert!(model.dout().is_high().unwrap());
+        model.advance_us(1);
+        assert!(model.dout().is_low().unwrap());
+        assert_eq!(read_out(&model, 25), 0xFF_FFFE);
+        assert!(model.dout().is_high().unwrap());
+        model.advance_us(100_000);
+        assert!(model.dout().is_low().unwrap());
+    }
+
+    #[test]
+    fn pulse_count_selects_the_next_input() {
+        let model = Hx711Model::new(80);
+        model.set_input(Channel::A, 1000);
+        model.set_input(Channel::B, 8000);
+        model.advance_us(50_000);
+        assert_eq!(read_out(&model, 27), 1000);
+        assert_eq!(model.selection(), Gain::A64);
+
+        // The first conversion after each switch has not settled
+        model.advance_us(12_500);
+        assert_eq!(read_out(&model, 27), 750);
+        model.advance_us(12_500);
+        assert_eq!(read_out(&model, 26), 500);
+        model.advance_us(12_500);
+        assert_eq!(read_out(&model, 25), 1250);
+        model.advance_us(12_500);
+        assert_eq!(read_out(&model, 25), 1500);
+        model.advance_us(12_500);
+        assert_eq!(read_out(&model, 25), 1000);
+    }
+
+    #[test]
+    fn codes_clip() {
+        let model = Hx711Model::new(80);
+        model.set_input(Channel::A, 1 << 30);
+        model.advance_us(50_000);
+        assert_eq!(read_out(&model, 25), 0x7F_FFFF);
+        model.set_input(Channel::A, -(1 << 30));
+        model.advance_us(12_500);
+        assert_eq!(read_out(&model, 25), 0x80_0000);
+    }
+
+    #[test]
+    fn power_down_and_reset() {
+        let model = Hx711Model::new(10);
+        model.advance_us(400_000);
+        read_out(&model, 27);
+
+        let mut sck = model.pd_sck();
+        sck.set_high().unwrap();
+        model.advance_us(POWER_DOWN_US - 1);
+        assert!(!model.is_powered_down());
+        model.advance_us(1);
+        assert!(model.is_powered_down());
+        model.advance_us(1_000_000);
+        assert!(model.dout().is_high().unwrap());
+
+        sck.set_low().unwrap();
+        assert!(!model.is_powered_down());
+        assert_eq!(model.selection(), Gain::A128);
+        model.advance_us(399_999);
+        assert!(model.dout().is_high().unwrap());
+        model.advance_us(1);
+        assert!(model.dout().is_low().unwrap());
+    }
+}
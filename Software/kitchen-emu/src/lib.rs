@@ -0,0 +1,13 @@
+// Explicit returns are the house style
+#![allow(clippy::needless_return)]
+
+/*
+ * Host side models of the peripherals on the scale board. They sit behind
+ * the same embedded-hal traits as the real hardware, so the drivers in
+ * kitchen-core run against them unchanged, in the simulator and in tests.
+ */
+pub mod button;
+pub mod delay;
+pub mod hd44780;
+pub mod hx711;
+pub mod load_cell;
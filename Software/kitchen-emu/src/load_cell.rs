@@ -0,0 +1,179 @@
+use std::str::FromStr;
+
+/*
+ * Load cell and amplifier model: the weight on the platform, from a
+ * scripted profile, turned into raw HX711 counts with gaussian noise.
+ */
+pub const HX711_MIN: i32 = -0x80_0000;
+pub const HX711_MAX: i32 = 0x7F_FFFF;
+
+/*
+ * Weight over time, `ms:grams` points separated by commas, e.g.
+ * "0:0,2000:0,2500:500". The weight is interpolated linearly between the
+ * points and held after the last one.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightProfile {
+    points: Vec<(u32, f64)>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ProfileError {
+    /* A point is not `ms:grams` */
+    Syntax(String),
+    /* Times have to increase */
+    Order(u32),
+}
+
+impl WeightProfile {
+    /* The same weight all the time */
+    pub fn constant(grams: f64) -> Self {
+        return Self { points: vec![(0, grams)] };
+    }
+
+    pub fn grams_at(&self, t_ms: u32) -> f64 {
+        let next = self.points.iter().position(|&(t, _)| t > t_ms);
+        match next {
+            Some(0) => return self.points[0].1,
+            None => return self.points.last().map_or(0.0, |p| p.1),
+            Some(i) => {
+                let (t0, g0) = self.points[i - 1];
+                let (t1, g1) = self.points[i];
+                return g0 + (g1 - g0) * (t_ms - t0) as f64 / (t1 - t0) as f64;
+            }
+        }
+    }
+
+    /* Time of the last point, the weight stays constant after it */
+    pub fn end_ms(&self) -> u32 {
+        return self.points.last().map_or(0, |p| p.0);
+    }
+}
+
+impl FromStr for WeightProfile {
+    type Err = ProfileError;
+
+    fn from_str(s: &str) -> Result<Self, ProfileError> {
+        let mut points: Vec<(u32, f64)> = Vec::new();
+        for point in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let parsed = point
+                .split_once(':')
+                .and_then(|(t, g)| Some((t.trim().parse::<u32>().ok()?, g.trim().parse::<f64>().ok()?)));
+            let (t, g) = parsed.ok_or_else(|| ProfileError::Syntax(point.to_string()))?;
+            if points.last().is_some_and(|&(last, _)| t <= last) {
+                return Err(ProfileError::Order(t));
+            }
+            points.push((t, g));
+        }
+        if points.is_empty() {
+            return Err(ProfileError::Syntax(s.to_string()));
+        }
+        return Ok(Self { points });
+    }
+}
+
+// Temperature the zero and span hold at, drift is relative to it
+pub const REFERENCE_C: f64 = 25.0;
+
+/* Bridge and amplifier, linear around `zero_counts` */
+#[derive(Debug, Clone)]
+pub struct LoadCellModel {
+    pub zero_counts: i32,
+    pub counts_per_gram: f64,
+    /* Standard deviation of the noise, in counts */
+    pub noise_counts: f64,
+    /* Zero drift in counts per °C, span drift as a fraction per °C */
+    pub zero_drift_per_c: f64,
+    pub span_drift_per_c: f64,
+    pub temperature_c: f64,
+    rng: u64,
+}
+
+impl LoadCellModel {
+    pub fn new(zero_counts: i32, counts_per_gram: f64, noise_counts: f64, seed: u64) -> Self {
+        Self {
+            zero_counts,
+            counts_per_gram,
+            noise_counts,
+            zero_drift_per_c: 0.0,
+            span_drift_per_c: 0.0,
+            temperature_c: REFERENCE_C,
+            // xorshift gets stuck at zero
+            rng: seed | 1,
+        }
+    }
+
+    /* Raw reading for a weight, clipped to the range of the HX711 */
+    pub fn counts(&mut self, grams: f64) -> i32 {
+        let delta = self.temperature_c - REFERENCE_C;
+        let zero = self.zero_counts as f64 + self.zero_drift_per_c * delta;
+        let span = grams * self.counts_per_gram * (1.0 + self.span_drift_per_c * delta);
+        let counts = zero + span + self.noise() * self.noise_counts;
+        return counts.round().clamp(HX711_MIN as f64, HX711_MAX as f64) as i32;
+    }
+
+    /* Standard normal sample, Box-Muller */
+    fn noise(&mut self) -> f64 {
+        let u1 = 1.0 - self.uniform();
+        let u2 = self.uniform();
+        return (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+    }
+
+    /* Uniform in [0, 1), xorshift64* */
+    fn uniform(&mut self) -> f64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let value = self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        return (value >> 11) as f64 / (1u64 << 53) as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_interpolates() {
+        let profile: WeightProfile = "0:0, 1000:0, 1500:500, 3000:500".parse().unwrap();
+        assert_eq!(profile.grams_at(500), 0.0);
+        assert_eq!(profile.grams_at(1250), 250.0);
+        assert_eq!(profile.grams_at(10_000), 500.0);
+        assert_eq!(profile.end_ms(), 3000);
+
+        let late: WeightProfile = "100:20".parse().unwrap();
+        assert_eq!(late.grams_at(0), 20.0);
+    }
+
+    #[test]
+    fn profile_errors() {
+        assert_eq!("0:0,x".parse::<WeightProfile>(), Err(ProfileError::Syntax("x".into())));
+        assert_eq!("0:0,10:5,10:6".parse::<WeightProfile>(), Err(ProfileError::Order(10)));
+        assert!("".parse::<WeightProfile>().is_err());
+    }
+
+    #[test]
+    fn counts_are_noisy_around_the_line() {
+        let mut cell = LoadCellModel::new(1000, 420.0, 50.0, 7);
+        let samples: Vec<i32> = (0..2000).map(|_| cell.counts(100.0)).collect();
+        let mean = samples.iter().map(|&s| s as f64).sum::<f64>() / samples.len() as f64;
+        let variance = samples.iter().map(|&s| (s as f64 - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+        assert!((mean - 43_000.0).abs() < 5.0);
+        assert!((variance.sqrt() - 50.0).abs() < 5.0);
+
+        let mut quiet = LoadCellModel::new(0, 420.0, 0.0, 7);
+        assert_eq!(quiet.counts(1e9), HX711_MAX);
+        assert_eq!(quiet.counts(-1e9), HX711_MIN);
+    }
+
+    #[test]
+    fn drifts_with_temperature() {
+        let mut cell = LoadCellModel::new(1000, 420.0, 0.0, 7);
+        cell.zero_drift_per_c = 10.0;
+        cell.span_drift_per_c = 100e-6;
+        assert_eq!(cell.counts(1000.0), 421_000);
+        cell.temperature_c = 35.0;
+        assert_eq!(cell.counts(0.0), 1100);
+        assert_eq!(cell.counts(1000.0), 421_520);
+    }
+}
@@ -0,0 +1,60 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use kitchen_core::settings::Storage;
+
+const EEPROM_SIZE: usize = 1024;
+
+/*
+ * The 1 KiB EEPROM of the ATmega328P, erased to 0xFF. With a file the
+ * contents survive between runs, every write goes straight to disk.
+ */
+pub struct VirtualEeprom {
+    bytes: Vec<u8>,
+    file: Option<PathBuf>,
+}
+
+impl VirtualEeprom {
+    pub fn new() -> Self {
+        return Self { bytes: vec![0xFF; EEPROM_SIZE], file: None };
+    }
+
+    /* Backed by `file`, which is created on the first write if missing */
+    pub fn open(file: PathBuf) -> io::Result<Self> {
+        let mut bytes = match fs::read(&file) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        bytes.resize(EEPROM_SIZE, 0xFF);
+        return Ok(Self { bytes, file: Some(file) });
+    }
+
+    fn range(&self, address: u16, len: usize) -> io::Result<std::ops::Range<usize>> {
+        let start = address as usize;
+        if start + len > self.bytes.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "beyond the end of the EEPROM"));
+        }
+        return Ok(start..start + len);
+    }
+}
+
+impl Storage for VirtualEeprom {
+    type Error = io::Error;
+
+    fn read(&mut self, address: u16, buffer: &mut [u8]) -> io::Result<()> {
+        let range = self.range(address, buffer.len())?;
+        buffer.copy_from_slice(&self.bytes[range]);
+        return Ok(());
+    }
+
+    fn write(&mut self, address: u16, buffer: &[u8]) -> io::Result<()> {
+        let range = self.range(address, buffer.len())?;
+        self.bytes[range].copy_from_slice(buffer);
+        if let Some(file) = &self.file {
+            fs::write(file, &self.bytes)?;
+        }
+        return Ok(());
+    }
+}
@@ -0,0 +1,377 @@
+// Explicit returns are the house style
+#![allow(clippy::needless_return)]
+
+/*
+ * Host simulator of the kitchen scale.
+ *
+ * Runs `kitchen_core::app::Scale`, the same application the firmware runs,
+ * against a virtual load cell and an emulated HD44780, which is drawn in the
+ * terminal. The LCD driver talks to the emulator through the PCF8574 byte
+ * stream, so whatever the real display would show, including driver bugs,
+ * shows here too. Keys stand in for the push button and the weights.
+ */
+mod eeprom;
+mod terminal;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+
+use kitchen_core::app::{Scale, ScaleEvent};
+use kitchen_core::drivers::button::Button;
+use kitchen_core::drivers::hx711::{Hx711Error, Hx711Rate};
+use kitchen_core::drivers::lcd::LCD;
+use kitchen_core::drivers::shared_bus::SharedI2c;
+use kitchen_core::settings::{Settings, Storage};
+use kitchen_core::ui::calibration_wizard::REFERENCE_MASSES;
+use kitchen_core::weighing::calibration::LoadCellCalibration;
+use kitchen_core::weighing::corner_correction::PLATFORM_CELLS;
+use kitchen_core::weighing::format::{Render, Style};
+use kitchen_core::weighing::overload::{LoadLimits, DEFAULT_LIMITS};
+use kitchen_core::weighing::temperature::TemperatureCompensation;
+use kitchen_core::weighing::timing::SampleTiming;
+use kitchen_core::weighing::weight::Weight;
+use kitchen_emu::button::VirtualButton;
+use kitchen_emu::delay::VirtualDelay;
+use kitchen_emu::hd44780::LcdBackpack;
+use kitchen_emu::load_cell::{LoadCellModel, WeightProfile};
+
+use eeprom::VirtualEeprom;
+use terminal::{Status, Terminal};
+
+const USAGE: &str = "\
+usage: kitchen-sim [options]
+
+  --profile POINTS   weight over time, ms:grams points, e.g. 0:0,2000:0,2500:500
+  --noise COUNTS     standard deviation of the load cell noise (default 40)
+  --rate SPS         HX711 output rate, 10 or 80 (default 10)
+  --seed N           seed of the noise generator
+  --eeprom FILE      keep the settings in FILE between runs
+  --calibrate        start with the calibration wizard, like holding SW1 at power up
+  --capacity G       rated capacity of the cell in grams, saved with the settings
+  --drift            start with the temperature drift test, like holding SW1 5 s at power up
+  --temperature C    starting temperature of the scale, 25 by default
+  --margin G         overload margin above the capacity in grams
+  --cells            a platform on four cells, one per corner, instead of a single cell
+  --corners          start with the corner-load test, implies --cells; like holding SW1 10 s at power up
+";
+
+// TAL220B 5 kg at gain 128, roughly
+const ZERO_COUNTS: i32 = 84_000;
+const COUNTS_PER_GRAM: f64 = 420.0;
+// Drift of the cell and the HX711, enough to show after a few degrees
+const ZERO_DRIFT_PER_C: f64 = 15.0;
+const SPAN_DRIFT_PER_C: f64 = 120e-6;
+const TEMPERATURE_STEP_C: f64 = 5.0;
+// Four-cell platform: one cell reads 5 % high, one 5 % low, so the corners differ
+const CELL_SENSITIVITY: [f64; PLATFORM_CELLS] = [1.0, 1.05, 1.0, 0.95];
+// Share of a load over a corner that the cell under it takes
+const CORNER_SHARE: f64 = 0.7;
+
+const SHORT_PRESS_MS: u32 = 150;
+const LONG_PRESS_MS: u32 = 1_500;
+const UART_LINES: usize = 8;
+
+struct Options {
+    profile: WeightProfile,
+    noise: f64,
+    rate: Hx711Rate,
+    seed: u64,
+    eeprom: Option<PathBuf>,
+    calibrate: bool,
+    capacity: Option<i32>,
+    margin: Option<i32>,
+    drift: bool,
+    temperature: f64,
+    cells: bool,
+    corners: bool,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            profile: WeightProfile::constant(0.0),
+            noise: 40.0,
+            rate: Hx711Rate::Sps10,
+            seed: 0x5EED,
+            eeprom: None,
+            calibrate: false,
+            capacity: None,
+            margin: None,
+            drift: false,
+            temperature: 25.0,
+            cells: false,
+            corners: false,
+        };
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--profile" => options.profile = value()?.parse().map_err(|e| format!("bad profile: {:?}", e))?,
+                "--noise" => options.noise = value()?.parse().map_err(|_| "bad noise")?,
+                "--rate" => {
+                    options.rate = match value()?.as_str() {
+                        "10" => Hx711Rate::Sps10,
+                        "80" => Hx711Rate::Sps80,
+                        _ => return Err("the HX711 runs at 10 or 80 SPS".into()),
+                    }
+                }
+                "--seed" => options.seed = value()?.parse().map_err(|_| "bad seed")?,
+                "--eeprom" => options.eeprom = Some(PathBuf::from(value()?)),
+                "--calibrate" => options.calibrate = true,
+                "--capacity" => options.capacity = Some(value()?.parse().map_err(|_| "bad capacity")?),
+                "--margin" => options.margin = Some(value()?.parse().map_err(|_| "bad margin")?),
+                "--drift" => options.drift = true,
+                "--temperature" => options.temperature = value()?.parse().map_err(|_| "bad temperature")?,
+                "--cells" => options.cells = true,
+                "--corners" => {
+                    options.cells = true;
+                    options.corners = true;
+                }
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+        return Ok(options);
+    }
+}
+
+fn main() -> ExitCode {
+    if std::env::args().any(|arg| arg == "--help" || arg == "-h") {
+        print!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprint!("{}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let mut eeprom = match &options.eeprom {
+        Some(file) => match VirtualEeprom::open(file.clone()) {
+            Ok(eeprom) => eeprom,
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => VirtualEeprom::new(),
+    };
+
+    // A blank EEPROM starts out calibrated for the virtual cell, as if from the factory
+    if Settings::load(&mut eeprom).is_err() {
+        let settings = Settings {
+            offset: ZERO_COUNTS,
+            calibration: LoadCellCalibration::from_scale((COUNTS_PER_GRAM * 1000.0) as i32, Weight::from_kg(1)),
+            offset_a64: ZERO_COUNTS / 2,
+            calibration_a64: LoadCellCalibration::from_scale((COUNTS_PER_GRAM * 500.0) as i32, Weight::from_kg(1)),
+            compensation: TemperatureCompensation::new(Some(tenths(options.temperature))),
+            ..Settings::default()
+        };
+        settings.save(&mut eeprom);
+    }
+
+    let saved = Settings::load(&mut eeprom).map(|settings| settings.limits).unwrap_or(DEFAULT_LIMITS);
+    let limits = LoadLimits {
+        capacity: options.capacity.map(Weight::from_grams).unwrap_or(saved.capacity),
+        margin: options.margin.map(Weight::from_grams).unwrap_or(saved.margin),
+    };
+    if !limits.is_valid() {
+        eprint!("the capacity has to be above zero and above the margin\n\n{}", USAGE);
+        return ExitCode::from(2);
+    }
+
+    match run(options, limits, eeprom) {
+        Ok(()) => return ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("terminal: {}", e);
+            return ExitCode::FAILURE;
+        }
+    }
+}
+
+fn run<S: Storage>(options: Options, limits: LoadLimits, eeprom: S) -> std::io::Result<()> {
+    let mut terminal = Terminal::open()?;
+
+    let backpack = RefCell::new(LcdBackpack::new(20, 4));
+    let lcd = LCD::init(SharedI2c::new(&backpack), VirtualDelay::new());
+    let mut scale = Scale::new(lcd, eeprom, options.rate.sample_rate(), options.calibrate);
+    scale.set_limits(limits);
+    scale.on_temperature(tenths(options.temperature));
+    if options.drift {
+        scale.learn_drift();
+    }
+    if options.corners {
+        scale.start_corner_test();
+    }
+
+    let switch = VirtualButton::new();
+    let mut button = Button::new(switch.pin(), 0);
+    let model = |share: f64, sensitivity: f64, seed: u64| {
+        let zero = (ZERO_COUNTS as f64 * share) as i32;
+        let mut cell = LoadCellModel::new(zero, COUNTS_PER_GRAM * sensitivity, options.noise * share.sqrt(), seed);
+        cell.zero_drift_per_c = ZERO_DRIFT_PER_C * share;
+        cell.span_drift_per_c = SPAN_DRIFT_PER_C;
+        cell.temperature_c = options.temperature;
+        return cell;
+    };
+    let mut cell = model(1.0, 1.0, options.seed);
+    // The cells of a platform share the zero and the noise of the single one
+    let mut platform = options.cells.then(|| Platform {
+        cells: (0..PLATFORM_CELLS)
+            .map(|i| model(1.0 / PLATFORM_CELLS as f64, CELL_SENSITIVITY[i], options.seed + i as u64))
+            .collect(),
+        corner: None,
+    });
+
+    let start = Instant::now();
+    let sample_period_us = options.rate.sample_rate().period_us() as u64;
+    // Samples are timed as the main loop gets to them, like the firmware's sampler queue
+    let mut timing = SampleTiming::new(options.rate.sample_rate());
+    let mut next_sample_us = sample_period_us;
+    let mut last_sample_ms = 0;
+    let mut extra_grams = 0.0;
+    let mut connected = true;
+    let mut raw = ZERO_COUNTS;
+    let mut uart: VecDeque<String> = VecDeque::new();
+
+    loop {
+        let now_us = start.elapsed().as_micros() as u64;
+        let now_ms = (now_us / 1000) as u32;
+        let grams = options.profile.grams_at(now_ms) + extra_grams;
+
+        while connected && next_sample_us <= now_us {
+            // The wizard captures at gain 64 as well, the cell never comes near the rails otherwise
+            let gain = scale.fixed_gain().unwrap_or(128);
+            if let Some(platform) = platform.as_mut() {
+                let raws = platform.counts(grams).map(|raw| raw * gain as i32 / 128);
+                raw = raws.iter().sum();
+                scale.on_cells(Ok(raws), gain);
+            } else {
+                raw = cell.counts(grams) * gain as i32 / 128;
+                scale.on_sample(Ok(raw), gain);
+            }
+            timing.on_sample(now_us as u32, 0);
+            last_sample_ms = now_ms;
+            next_sample_us += sample_period_us;
+        }
+        if !connected {
+            next_sample_us = now_us + sample_period_us;
+            if now_ms.wrapping_sub(last_sample_ms) > options.rate.ready_timeout_ms() {
+                scale.on_sample(Err(Hx711Error::Timeout), 128);
+            }
+        }
+
+        switch.update(now_ms);
+        // The UART lines of the firmware
+        let line = match scale.update(button.poll(now_ms)) {
+            Some(ScaleEvent::Settled { reading, unit }) => Some(format!(
+                "{} {}",
+                reading.styled(Style::right(12)).to_field().as_str(),
+                unit.styled(Style::left(5)).to_field().as_str()
+            )),
+            Some(ScaleEvent::Overload { gross, count }) => {
+                Some(format!("overload {} g, {} so far", gross.grams(), count))
+            }
+            Some(ScaleEvent::Underload { gross }) => Some(format!("underload {} g, platform lifted", gross.grams())),
+            Some(ScaleEvent::LoadNormal) => Some("load back in range".to_string()),
+            None => None,
+        };
+        if let Some(line) = line {
+            uart.push_back(line);
+            if uart.len() > UART_LINES {
+                uart.pop_front();
+            }
+        }
+
+        terminal.draw(&Status {
+            lcd: &backpack.borrow(),
+            grams,
+            position: platform.as_ref().map(Platform::position),
+            raw,
+            connected,
+            rate: options.rate.sample_rate().sps(),
+            timing: timing.stats(),
+            temperature: cell.temperature_c,
+            pressed: switch.is_pressed(),
+            calibrating: scale.is_calibrating(),
+            uart: &uart,
+        })?;
+
+        if !event::poll(Duration::from_millis(5))? {
+            continue;
+        }
+        let key = match event::read()? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => key,
+            _ => continue,
+        };
+        match key {
+            KeyEvent { code: KeyCode::Char('c'), modifiers, .. } if modifiers.contains(KeyModifiers::CONTROL) => break,
+            KeyEvent { code: KeyCode::Char('q') | KeyCode::Esc, .. } => break,
+            KeyEvent { code: KeyCode::Char(' '), .. } => switch.press(now_ms, SHORT_PRESS_MS),
+            KeyEvent { code: KeyCode::Enter | KeyCode::Char('l'), .. } => switch.press(now_ms, LONG_PRESS_MS),
+            KeyEvent { code: KeyCode::Char('+'), .. } => extra_grams += 1.0,
+            KeyEvent { code: KeyCode::Char('-'), .. } => extra_grams -= 1.0,
+            KeyEvent { code: KeyCode::Char('0'), .. } => extra_grams = 0.0,
+            KeyEvent { code: KeyCode::Char('d'), .. } => connected = !connected,
+            KeyEvent { code: KeyCode::Char(c @ ('w' | 'c')), .. } => {
+                cell.temperature_c += if c == 'w' { TEMPERATURE_STEP_C } else { -TEMPERATURE_STEP_C };
+                if let Some(platform) = platform.as_mut() {
+                    platform.cells.iter_mut().for_each(|c| c.temperature_c = cell.temperature_c);
+                }
+                scale.on_temperature(tenths(cell.temperature_c));
+            }
+            KeyEvent { code: KeyCode::Char('m'), .. } => {
+                if let Some(platform) = platform.as_mut() {
+                    platform.corner = match platform.corner {
+                        None => Some(0),
+                        Some(i) if i + 1 < PLATFORM_CELLS => Some(i + 1),
+                        Some(_) => None,
+                    };
+                }
+            }
+            KeyEvent { code: KeyCode::Char(c @ '1'..='5'), .. } => {
+                let (_, mass) = REFERENCE_MASSES[c as usize - '1' as usize];
+                extra_grams += mass.mg() as f64 / 1000.0;
+            }
+            _ => {}
+        }
+    }
+    return Ok(());
+}
+
+/* Cells of a multi-cell platform and where the load sits, None is the centre */
+struct Platform {
+    cells: Vec<LoadCellModel>,
+    corner: Option<usize>,
+}
+
+impl Platform {
+    fn counts(&mut self, grams: f64) -> [i32; PLATFORM_CELLS] {
+        let mut raws = [0; PLATFORM_CELLS];
+        for (i, (raw, cell)) in raws.iter_mut().zip(self.cells.iter_mut()).enumerate() {
+            let share = match self.corner {
+                None => 1.0 / PLATFORM_CELLS as f64,
+                Some(corner) if corner == i => CORNER_SHARE,
+                Some(_) => (1.0 - CORNER_SHARE) / (PLATFORM_CELLS - 1) as f64,
+            };
+            *raw = cell.counts(grams * share);
+        }
+        return raws;
+    }
+
+    fn position(&self) -> String {
+        return match self.corner {
+            None => String::from("centre"),
+            Some(corner) => format!("corner {}", corner + 1),
+        };
+    }
+}
+
+/* °C as the internal sensor of the ATmega reports it */
+fn tenths(celsius: f64) -> i16 {
+    return (celsius * 10.0).round() as i16;
+}
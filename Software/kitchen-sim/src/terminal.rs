@@ -0,0 +1,140 @@
+use std::collections::VecDeque;
+use std::io::{self, Stdout, Write};
+
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, execute, queue};
+
+use kitchen_core::ui::glyphs;
+use kitchen_core::weighing::timing::TimingStats;
+use kitchen_emu::hd44780::{rom_char, LcdBackpack};
+
+const KEYS: [&str; 3] = [
+    "space: press SW1   enter/l: hold SW1   q: quit",
+    "1-5: add 100 g, 200 g, 500 g, 1 kg, 2 kg   +/-: 1 g   0: take all off",
+    "d: unplug/plug the HX711   w/c: 5 °C warmer/cooler   m: move the load (--cells)",
+];
+
+/* Everything shown in one frame */
+pub struct Status<'a> {
+    pub lcd: &'a LcdBackpack,
+    pub grams: f64,
+    /* Where the load sits on a multi-cell platform */
+    pub position: Option<String>,
+    /* Sum of the cells on a multi-cell platform */
+    pub raw: i32,
+    pub connected: bool,
+    pub rate: u16,
+    pub timing: TimingStats,
+    /* °C */
+    pub temperature: f64,
+    pub pressed: bool,
+    pub calibrating: bool,
+    pub uart: &'a VecDeque<String>,
+}
+
+/* Raw mode on the alternate screen, restored when dropped */
+pub struct Terminal {
+    out: Stdout,
+    shown: Vec<String>,
+    shown_backlight: bool,
+}
+
+impl Terminal {
+    pub fn open() -> io::Result<Self> {
+        let mut out = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(out, EnterAlternateScreen, cursor::Hide, Clear(ClearType::All))?;
+        return Ok(Self { out, shown: Vec::new(), shown_backlight: false });
+    }
+
+    /* Redraws the screen if anything changed since the last frame */
+    pub fn draw(&mut self, status: &Status) -> io::Result<()> {
+        let display = display_rows(status.lcd);
+        let mut lines = vec![
+            String::from("Kitchen scale simulator"),
+            String::new(),
+            format!("┌{}┐", "─".repeat(display[0].chars().count())),
+        ];
+        lines.extend(display.iter().map(|row| format!("│{}│", row)));
+        lines.push(format!("└{}┘", "─".repeat(display[0].chars().count())));
+        lines.push(String::new());
+        lines.push(format!(
+            "platform {:9.1} g{}   HX711 {}   {:.1} °C   SW1 {}{}",
+            status.grams,
+            status.position.as_ref().map_or(String::new(), |position| format!(" at the {}", position)),
+            if status.connected { format!("{:8} @ {} SPS", status.raw, status.rate) } else { String::from("unplugged") },
+            status.temperature,
+            if status.pressed { "down" } else { "up" },
+            if status.calibrating { "   calibrating" } else { "" },
+        ));
+        lines.push(format!(
+            "samples every {:.1} ms ± {:.1} ms, {} missed",
+            status.timing.mean_interval_us as f64 / 1000.0,
+            status.timing.jitter_us as f64 / 1000.0,
+            status.timing.missed,
+        ));
+        lines.push(String::new());
+        lines.extend(KEYS.iter().map(|k| k.to_string()));
+        lines.push(String::new());
+        lines.push(String::from("UART"));
+        lines.extend(status.uart.iter().cloned());
+
+        // The LCD row colours depend on the backlight as well
+        let backlight = status.lcd.is_backlight_on();
+        if lines == self.shown && backlight == self.shown_backlight {
+            return Ok(());
+        }
+
+        queue!(self.out, Clear(ClearType::All))?;
+        for (y, line) in lines.iter().enumerate() {
+            queue!(self.out, cursor::MoveTo(0, y as u16))?;
+            let is_lcd_row = (3..3 + display.len()).contains(&y);
+            if is_lcd_row {
+                let (fg, bg) = if backlight { (Color::Black, Color::Green) } else { (Color::DarkGreen, Color::Black) };
+                let row = &display[y - 3];
+                queue!(
+                    self.out,
+                    Print("│"),
+                    SetForegroundColor(fg),
+                    SetBackgroundColor(bg),
+                    Print(row),
+                    ResetColor,
+                    Print("│")
+                )?;
+            } else {
+                queue!(self.out, Print(line))?;
+            }
+        }
+        self.out.flush()?;
+        self.shown = lines;
+        self.shown_backlight = backlight;
+        return Ok(());
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = execute!(self.out, ResetColor, cursor::Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/* The visible characters, custom glyphs the firmware loads get a look-alike */
+fn display_rows(backpack: &LcdBackpack) -> Vec<String> {
+    let lcd = backpack.lcd();
+    return (0..lcd.rows())
+        .map(|row| {
+            if !lcd.is_display_on() {
+                return " ".repeat(lcd.columns() as usize);
+            }
+            lcd.row(row)
+                .into_iter()
+                .map(|code| match code {
+                    0x00..=0x0F if lcd.glyph(code) == glyphs::STABLE_CHARMAP => '✓',
+                    _ => rom_char(code),
+                })
+                .collect()
+        })
+        .collect();
+}